@@ -0,0 +1,130 @@
+use Instruction;
+
+/// Whether the program can execute a `,`
+pub fn reads_input(program: &[Instruction]) -> bool {
+    program.iter().any(|instr| match instr {
+        Instruction::Read => true,
+        Instruction::Loop(nested_instructions) => reads_input(nested_instructions),
+        _ => false,
+    })
+}
+
+/// Whether the program can produce any output
+pub fn writes_output(program: &[Instruction]) -> bool {
+    program.iter().any(|instr| match instr {
+        Instruction::Write => true,
+        Instruction::WriteBytes(bytes) => !bytes.is_empty(),
+        Instruction::Loop(nested_instructions) => writes_output(nested_instructions),
+        _ => false,
+    })
+}
+
+/// Net pointer movement of the program, or None if it depends on the data
+/// because some loop doesn't return the pointer to where it started
+pub fn net_movement(program: &[Instruction]) -> Option<i32> {
+    let mut delta = 0;
+
+    for instr in program {
+        match instr {
+            Instruction::IncrementPointer => delta += 1,
+            Instruction::DecrementPointer => delta -= 1,
+            Instruction::Snapshot { pointer, .. } => delta += pointer,
+            Instruction::Loop(nested_instructions)
+                if net_movement(nested_instructions) != Some(0) =>
+            {
+                return None
+            }
+            _ => (),
+        }
+    }
+
+    Some(delta)
+}
+
+/// A program that does no I/O and only touches a statically bounded window
+/// of cells around the pointer, i.e. a pure function of that window
+pub fn is_pure_transform(program: &[Instruction]) -> bool {
+    !reads_input(program) && !writes_output(program) && net_movement(program).is_some()
+}
+
+/// What a loop body does to the pointer on each iteration
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoopShape {
+    /// Only moves the pointer by a fixed stride, like `[>>]` or `[<]`
+    Scan(i32),
+    /// Does some work and then ends up a fixed stride away, like `[->>]`
+    WorkThenMove(i32),
+    /// Returns the pointer to where the iteration started
+    Balanced,
+    /// Movement depends on the data, e.g. through an unbalanced inner loop
+    Irregular,
+}
+
+pub fn loop_shape(body: &[Instruction]) -> LoopShape {
+    let only_moves = body.iter().all(|instr| {
+        matches!(
+            instr,
+            Instruction::IncrementPointer | Instruction::DecrementPointer
+        )
+    });
+
+    match net_movement(body) {
+        Some(0) => LoopShape::Balanced,
+        Some(stride) if only_moves => LoopShape::Scan(stride),
+        Some(stride) => LoopShape::WorkThenMove(stride),
+        None => LoopShape::Irregular,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {lex, parse};
+
+    fn program(source: &str) -> Vec<Instruction> {
+        parse(lex(source.to_string())).unwrap()
+    }
+
+    const CAT: &str = ",[.,]";
+    const ADD: &str = "++>+++[-<+>]<";
+
+    #[test]
+    fn cat_reads_and_writes() {
+        assert!(reads_input(&program(CAT)));
+        assert!(writes_output(&program(CAT)));
+        assert!(!is_pure_transform(&program(CAT)));
+    }
+
+    #[test]
+    fn a_computation_does_no_io_and_is_pure() {
+        assert!(!reads_input(&program(ADD)));
+        assert!(!writes_output(&program(ADD)));
+        assert_eq!(net_movement(&program(ADD)), Some(0));
+        assert!(is_pure_transform(&program(ADD)));
+    }
+
+    #[test]
+    fn a_read_or_write_counts_inside_nested_loops() {
+        assert!(reads_input(&program("+[>[,]<-]")));
+        assert!(!reads_input(&program("+[>[.]<-]")));
+        assert!(writes_output(&program("+[>[.]<-]")));
+        assert!(!writes_output(&program("+[>[,]<-]")));
+        assert!(!reads_input(&program("")));
+    }
+
+    #[test]
+    fn a_scan_makes_the_movement_depend_on_the_data() {
+        assert_eq!(net_movement(&program(">>+<")), Some(1));
+        assert_eq!(net_movement(&program("+[>]")), None);
+        assert!(!is_pure_transform(&program("+[>]")));
+    }
+
+    #[test]
+    fn loops_are_told_apart_by_what_they_do_to_the_pointer() {
+        assert_eq!(loop_shape(&program(">>")), LoopShape::Scan(2));
+        assert_eq!(loop_shape(&program("<")), LoopShape::Scan(-1));
+        assert_eq!(loop_shape(&program("->>")), LoopShape::WorkThenMove(2));
+        assert_eq!(loop_shape(&program("->+<")), LoopShape::Balanced);
+        assert_eq!(loop_shape(&program("-[>]")), LoopShape::Irregular);
+    }
+}
@@ -0,0 +1,219 @@
+use bytecode;
+#[cfg(feature = "jit")]
+use fused_straight_steps;
+#[cfg(feature = "jit")]
+use jit;
+#[cfg(feature = "jit")]
+use tape::TapePolicy;
+use {
+    compile_big, compile_owned, fuse, lower_for, run, run_big, straight_steps, Instruction,
+    Machine, OptLevel,
+};
+
+/// A way of executing a parsed program
+pub trait Backend: Sync {
+    fn name(&self) -> &'static str;
+    fn description(&self) -> &'static str;
+    /// Runs the program against the machine, returning the final data pointer
+    fn run(&self, program: &[Instruction], machine: &mut Machine, data_pointer: i32) -> i32;
+}
+
+/// Every backend, the default one first
+pub static BACKENDS: &[&dyn Backend] = &[
+    &BigClosure,
+    &Closure,
+    &Tree,
+    &Big,
+    &Bytecode,
+    #[cfg(feature = "jit")]
+    &Jit,
+];
+
+pub fn find(name: &str) -> Option<&'static dyn Backend> {
+    BACKENDS
+        .iter()
+        .find(|backend| backend.name() == name)
+        .cloned()
+}
+
+/// Runs the program with no more rewriting than `level` allows, which below
+/// `Full` means on a fixed backend instead of `backend`
+pub fn run_at(
+    level: OptLevel,
+    backend: &dyn Backend,
+    program: &[Instruction],
+    machine: &mut Machine,
+    data_pointer: i32,
+) -> i32 {
+    match level {
+        OptLevel::None => Tree.run(program, machine, data_pointer),
+        OptLevel::Basic => run_big(&fuse(program), machine, data_pointer),
+        OptLevel::Full => backend.run(program, machine, data_pointer),
+    }
+}
+
+struct Closure;
+
+impl Backend for Closure {
+    fn name(&self) -> &'static str {
+        "closure"
+    }
+
+    fn description(&self) -> &'static str {
+        "compiles the program into a chain of closures"
+    }
+
+    fn run(&self, program: &[Instruction], machine: &mut Machine, data_pointer: i32) -> i32 {
+        let code = compile_owned(program);
+        code(machine, data_pointer)
+    }
+}
+
+struct Tree;
+
+impl Backend for Tree {
+    fn name(&self) -> &'static str {
+        "tree"
+    }
+
+    fn description(&self) -> &'static str {
+        "walks the parsed instruction tree directly"
+    }
+
+    fn run(&self, program: &[Instruction], machine: &mut Machine, data_pointer: i32) -> i32 {
+        machine.enter(data_pointer, straight_steps(program));
+        let mut data_pointer = data_pointer as usize;
+        run(program, machine, &mut data_pointer);
+        data_pointer as i32
+    }
+}
+
+struct Big;
+
+impl Backend for Big {
+    fn name(&self) -> &'static str {
+        "big"
+    }
+
+    fn description(&self) -> &'static str {
+        "interprets the BigInsn form with fused moves and adjustments"
+    }
+
+    fn run(&self, program: &[Instruction], machine: &mut Machine, data_pointer: i32) -> i32 {
+        run_big(&lower_for(program, machine), machine, data_pointer)
+    }
+}
+
+struct BigClosure;
+
+impl Backend for BigClosure {
+    fn name(&self) -> &'static str {
+        "big-closure"
+    }
+
+    fn description(&self) -> &'static str {
+        "compiles the BigInsn form into a chain of closures"
+    }
+
+    fn run(&self, program: &[Instruction], machine: &mut Machine, data_pointer: i32) -> i32 {
+        let code = compile_big(&lower_for(program, machine));
+        code(machine, data_pointer)
+    }
+}
+
+struct Bytecode;
+
+impl Backend for Bytecode {
+    fn name(&self) -> &'static str {
+        "bytecode"
+    }
+
+    fn description(&self) -> &'static str {
+        "flattens the BigInsn form into bytecode with precomputed jumps"
+    }
+
+    fn run(&self, program: &[Instruction], machine: &mut Machine, data_pointer: i32) -> i32 {
+        let code = bytecode::compile(&lower_for(program, machine));
+        bytecode::run(&code, machine, data_pointer)
+    }
+}
+
+#[cfg(feature = "jit")]
+struct Jit;
+
+#[cfg(feature = "jit")]
+impl Backend for Jit {
+    fn name(&self) -> &'static str {
+        "jit"
+    }
+
+    fn description(&self) -> &'static str {
+        "compiles the BigInsn form to machine code, or big-closure where it can't"
+    }
+
+    fn run(&self, program: &[Instruction], machine: &mut Machine, data_pointer: i32) -> i32 {
+        let program = lower_for(program, machine);
+        let supervised = machine.is_supervised();
+
+        // The machine code grows the tape itself, any other policy is left
+        // to big-closure
+        let code = match machine.tape.policy() {
+            TapePolicy::Grow => jit::compile(&program, supervised),
+            _ => None,
+        };
+        match code {
+            Some(code) => {
+                machine.enter(data_pointer, fused_straight_steps(&program));
+                code.run(machine, data_pointer)
+            }
+            None => compile_big(&program)(machine, data_pointer),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::*;
+    use input::{Input, InputMode, RangePolicy};
+    use output::testing::Shared;
+    use output::{Output, OutputMode};
+    use tape::Tape;
+    use {lex, parse, START_POINTER, TAPE_SIZE};
+
+    fn output_of(backend: &dyn Backend, source: &str) -> Vec<u8> {
+        let sink = Shared::default();
+        let mut machine = Machine::new(
+            Tape::new(vec![0; TAPE_SIZE]),
+            Input::new(InputMode::Byte, RangePolicy::Error, Box::new(io::empty())),
+            Output::new(OutputMode::Char, Box::new(sink.clone())),
+        );
+        let program = parse(lex(source.to_string())).unwrap();
+        backend.run(&program, &mut machine, START_POINTER as i32);
+        machine.output.finish();
+        sink.bytes()
+    }
+
+    #[test]
+    fn every_backend_writes_the_same() {
+        let source = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.\
+                      +++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+        for &backend in BACKENDS {
+            assert_eq!(
+                output_of(backend, source),
+                b"Hello World!\n",
+                "{} backend",
+                backend.name()
+            );
+        }
+    }
+
+    #[test]
+    fn backends_are_found_by_name() {
+        for &backend in BACKENDS {
+            assert_eq!(find(backend.name()).unwrap().name(), backend.name());
+        }
+        assert!(find("nonesuch").is_none());
+    }
+}
@@ -0,0 +1,166 @@
+//! Timing a program on every backend, to see which one suits it and to
+//! catch a backend getting slower.
+//!
+//! Every run starts from scratch on a blank tape and the same input, and
+//! writes to nowhere, so what is timed is the backend compiling and running
+//! the program. Steps are counted once, by a run on the default backend
+//! under the step limit, or one it can't reach, as the timed runs don't
+//! count them. A program that doesn't stop is caught there, given a limit.
+
+use std::fmt;
+use std::io::{self, Cursor};
+use std::time::{Duration, Instant};
+
+use backend::{Backend, BACKENDS};
+use input::{EofBehavior, Input, InputMode, RangePolicy};
+use output::{Output, OutputMode};
+use tape::Tape;
+use {catch_stop, Instruction, Machine, START_POINTER, TAPE_SIZE};
+
+/// How many times a program runs on each backend
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BenchOptions {
+    /// Untimed runs first, to warm up caches and the allocator
+    pub warmup: usize,
+    pub runs: usize,
+    /// Most steps the program may take, checked before anything is timed
+    pub step_limit: Option<u64>,
+}
+
+impl Default for BenchOptions {
+    fn default() -> BenchOptions {
+        BenchOptions {
+            warmup: 1,
+            runs: 5,
+            step_limit: None,
+        }
+    }
+}
+
+/// The timed runs on one backend, fastest first
+#[derive(Debug, Clone, PartialEq)]
+pub struct Timing {
+    pub backend: &'static str,
+    pub runs: Vec<Duration>,
+}
+
+impl Timing {
+    pub fn min(&self) -> Duration {
+        self.runs[0]
+    }
+
+    pub fn median(&self) -> Duration {
+        self.runs[self.runs.len() / 2]
+    }
+
+    pub fn mean(&self) -> Duration {
+        self.runs.iter().sum::<Duration>() / self.runs.len() as u32
+    }
+}
+
+/// What benchmarking a program found
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    /// Steps the program takes, as a step limit counts them
+    pub steps: u64,
+    pub timings: Vec<Timing>,
+}
+
+/// Times the program on every backend, failing with the message of the first
+/// run that doesn't finish
+pub fn bench(
+    program: &[Instruction],
+    input: &[u8],
+    eof: EofBehavior,
+    opts: BenchOptions,
+) -> Result<Report, String> {
+    let mut counted = machine(input, eof);
+    counted.step_limit = Some(opts.step_limit.unwrap_or(u64::MAX));
+    run(BACKENDS[0], program, &mut counted)?;
+
+    let mut timings = Vec::new();
+    for &backend in BACKENDS {
+        for _ in 0..opts.warmup {
+            run(backend, program, &mut machine(input, eof))?;
+        }
+        let mut runs = Vec::new();
+        for _ in 0..opts.runs.max(1) {
+            let mut machine = machine(input, eof);
+            let start = Instant::now();
+            run(backend, program, &mut machine)?;
+            runs.push(start.elapsed());
+        }
+        runs.sort();
+        timings.push(Timing {
+            backend: backend.name(),
+            runs,
+        });
+    }
+
+    Ok(Report {
+        steps: counted.steps,
+        timings,
+    })
+}
+
+fn machine(input: &[u8], eof: EofBehavior) -> Machine {
+    let input = Input::new(
+        InputMode::Byte,
+        RangePolicy::Error,
+        Box::new(Cursor::new(input.to_vec())),
+    );
+    Machine::new(
+        Tape::new(vec![0; TAPE_SIZE]),
+        input.on_eof(eof),
+        Output::new(OutputMode::Char, Box::new(io::sink())),
+    )
+}
+
+fn run(
+    backend: &dyn Backend,
+    program: &[Instruction],
+    machine: &mut Machine,
+) -> Result<(), String> {
+    catch_stop(|| backend.run(program, machine, START_POINTER as i32))
+        .map(|_| ())
+        .map_err(|e| format!("{} backend: {}", backend.name(), e))
+}
+
+/// A duration in the unit that keeps it readable
+fn human(duration: Duration) -> String {
+    let seconds = duration.as_secs_f64();
+    if seconds >= 1.0 {
+        format!("{:.2}s", seconds)
+    } else if seconds >= 1e-3 {
+        format!("{:.2}ms", seconds * 1e3)
+    } else {
+        format!("{:.1}us", seconds * 1e6)
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<12} {:>10} {:>10} {:>10} {:>14}",
+            "backend", "min", "median", "mean", "steps/s"
+        )?;
+        for timing in &self.timings {
+            let rate = self.steps as f64 / timing.median().as_secs_f64().max(1e-9);
+            writeln!(
+                f,
+                "{:<12} {:>10} {:>10} {:>10} {:>14.0}",
+                timing.backend,
+                human(timing.min()),
+                human(timing.median()),
+                human(timing.mean()),
+                rate
+            )?;
+        }
+        write!(f, "{} steps, ", self.steps)?;
+        match self.timings.iter().min_by_key(|timing| timing.median()) {
+            Some(fastest) => writeln!(f, "fastest {}", fastest.backend),
+            None => writeln!(f, "no backends"),
+        }
+    }
+}
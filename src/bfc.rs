@@ -0,0 +1,239 @@
+//! Programs compiled ahead of time to bytecode and saved as `.bfc` files, so
+//! a large program is lexed, parsed and optimized once rather than on every
+//! run.
+//!
+//! A file is a header followed by the code, all integers little-endian:
+//!
+//! ```text
+//! magic         "BFC\0"
+//! version       u16, 2
+//! cell bits     u8, 8
+//! reserved      u8, 0
+//! tape size     u32, cells the tape starts with
+//! data pointer  u32, where on that tape the program starts
+//! ops           u32, how many follow
+//! ```
+//!
+//! and then every `Op` as a tag byte and its operands in order: `Move` 0,
+//! `Adj` 1, `Set` 2, `MulAdd` 3, `Seek` 4, `Write` 5, `Emit` 6, `Read` 7,
+//! `Open` 8, `Close` 9 and `Steps` 10. A tape that doesn't start out blank is set up by
+//! `Set`s at the start of the code.
+
+use std::fs;
+use std::path::Path;
+
+use bytecode::{self, Op};
+use BigInsn;
+
+const MAGIC: &[u8; 4] = b"BFC\0";
+const VERSION: u16 = 2;
+
+/// A program as a `.bfc` file holds it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Compiled {
+    pub tape_size: usize,
+    pub data_pointer: usize,
+    pub code: Vec<Op>,
+}
+
+impl Compiled {
+    /// Compiles the lowered program to start on `tape` at `data_pointer`
+    pub fn new(program: &[BigInsn], tape: &[u8], data_pointer: usize) -> Compiled {
+        let mut code: Vec<Op> = tape
+            .iter()
+            .enumerate()
+            .filter(|&(_, &cell)| cell != 0)
+            .map(|(i, &cell)| Op::Set(i as i32 - data_pointer as i32, cell))
+            .collect();
+        code.extend(bytecode::compile(program));
+
+        Compiled {
+            tape_size: tape.len(),
+            data_pointer,
+            code,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&[8, 0]);
+        bytes.extend_from_slice(&(self.tape_size as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.data_pointer as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.code.len() as u32).to_le_bytes());
+
+        for &op in &self.code {
+            let (tag, operands): (u8, &[i64]) = match op {
+                Op::Move(n) => (0, &[i64::from(n)]),
+                Op::Adj(offset, n) => (1, &[i64::from(offset), i64::from(n)]),
+                Op::Set(offset, value) => (2, &[i64::from(offset), i64::from(value)]),
+                Op::MulAdd { from, to, k } => (3, &[i64::from(from), i64::from(to), i64::from(k)]),
+                Op::Seek(stride) => (4, &[i64::from(stride)]),
+                Op::Write(offset) => (5, &[i64::from(offset)]),
+                Op::Emit(byte) => (6, &[i64::from(byte)]),
+                Op::Read(offset) => (7, &[i64::from(offset)]),
+                Op::Open { close, steps } => (8, &[i64::from(close), i64::from(steps)]),
+                Op::Close { open, steps } => (9, &[i64::from(open), i64::from(steps)]),
+                Op::Steps(steps) => (10, &[i64::from(steps)]),
+            };
+            bytes.push(tag);
+            // Bytes are written as one, everything else as four
+            let widths = operand_widths(tag);
+            for (&operand, &width) in operands.iter().zip(widths) {
+                bytes.extend_from_slice(&operand.to_le_bytes()[..width]);
+            }
+        }
+
+        bytes
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Compiled, String> {
+        let mut reader = Reader { bytes, at: 0 };
+        if reader.take(4).ok() != Some(&MAGIC[..]) {
+            return Err("not a compiled bf program".to_string());
+        }
+        let version = reader.u16()?;
+        if version != VERSION {
+            return Err(format!("unsupported .bfc version {}", version));
+        }
+        let cell_bits = reader.u8()?;
+        if cell_bits != 8 {
+            return Err(format!("unsupported cell size of {} bits", cell_bits));
+        }
+        reader.u8()?;
+        let tape_size = reader.u32()? as usize;
+        let data_pointer = reader.u32()? as usize;
+        if tape_size == 0 || data_pointer >= tape_size {
+            return Err("the data pointer isn't on the tape".to_string());
+        }
+
+        let count = reader.u32()? as usize;
+        // Every op takes at least two bytes, so a bogus count can't make
+        // this allocate much more than the file
+        let mut code = Vec::with_capacity(count.min(bytes.len() / 2));
+        for _ in 0..count {
+            let tag = reader.u8()?;
+            let mut operands = [0i64; 3];
+            for (operand, &width) in operands.iter_mut().zip(operand_widths(tag)) {
+                *operand = match width {
+                    1 => i64::from(reader.u8()?),
+                    _ => reader.i32()?.into(),
+                };
+            }
+            let [a, b, c] = operands;
+            code.push(match tag {
+                0 => Op::Move(a as i32),
+                1 => Op::Adj(a as i32, b as u8),
+                2 => Op::Set(a as i32, b as u8),
+                3 => Op::MulAdd {
+                    from: a as i32,
+                    to: b as i32,
+                    k: c as u8,
+                },
+                4 => Op::Seek(a as i32),
+                5 => Op::Write(a as i32),
+                6 => Op::Emit(a as u8),
+                7 => Op::Read(a as i32),
+                8 => Op::Open {
+                    close: a as u32,
+                    steps: b as u32,
+                },
+                9 => Op::Close {
+                    open: a as u32,
+                    steps: b as u32,
+                },
+                10 => Op::Steps(a as u32),
+                _ => return Err(format!("unknown op {} at byte {}", tag, reader.at - 1)),
+            });
+        }
+        if reader.at != bytes.len() {
+            return Err("trailing bytes after the code".to_string());
+        }
+        check_jumps(&code)?;
+
+        Ok(Compiled {
+            tape_size,
+            data_pointer,
+            code,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Compiled, String> {
+        let bytes = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Compiled::parse(&bytes).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+/// Bytes taken by each operand of the op with `tag`
+fn operand_widths(tag: u8) -> &'static [usize] {
+    match tag {
+        0 | 4 | 5 | 7 | 10 => &[4],
+        1 | 2 => &[4, 1],
+        3 => &[4, 4, 1],
+        6 => &[1],
+        8 | 9 => &[4, 4],
+        _ => &[],
+    }
+}
+
+/// Every `Open` and `Close` has to name the other as its partner, or else
+/// `bytecode::run` would jump somewhere it shouldn't
+fn check_jumps(code: &[Op]) -> Result<(), String> {
+    for (i, &op) in code.iter().enumerate() {
+        let partnered = match op {
+            Op::Open { close, steps } => {
+                code.get(close as usize)
+                    == Some(&Op::Close {
+                        open: i as u32,
+                        steps,
+                    })
+            }
+            Op::Close { open, steps } => {
+                code.get(open as usize)
+                    == Some(&Op::Open {
+                        close: i as u32,
+                        steps,
+                    })
+            }
+            _ => true,
+        };
+        if !partnered {
+            return Err(format!("op {} jumps to no matching op", i));
+        }
+    }
+    Ok(())
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        let taken = self
+            .bytes
+            .get(self.at..self.at + n)
+            .ok_or_else(|| "the file ends too soon".to_string())?;
+        self.at += n;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn i32(&mut self) -> Result<i32, String> {
+        self.u32().map(|n| n as i32)
+    }
+}
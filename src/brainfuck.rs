@@ -0,0 +1,137 @@
+use std::iter;
+
+use input::EofBehavior;
+use BigInsn;
+
+/// Translates an optimized program back to plain Brainfuck, for running on
+/// interpreters that don't optimize. Runs of `+`, `-`, `>` and `<` come out
+/// merged the short way round, what the optimizer dropped as dead stays
+/// dropped, and every clear is `[-]`. A seeded `tape` is set up by code at
+/// the start, and the data pointer starts on the first cell as Brainfuck
+/// interpreters expect, with the cells left of it only reachable on those
+/// whose tape goes that way too.
+///
+/// What `,` does at the end of input is up to the interpreter, so `eof`
+/// isn't kept.
+///
+/// Panics on a `MulAdd` that isn't followed by a `Clear` or `Set`, as
+/// Brainfuck has no way to multiply without using up the count; the
+/// optimizer always makes them in pairs.
+pub fn emit(program: &[BigInsn], tape: &[u8], data_pointer: usize, _eof: EofBehavior) -> String {
+    let mut emitter = Emitter { out: String::new() };
+
+    // Seeded cells, from the leftmost, and back to the start
+    let mut p = 0;
+    for (address, &cell) in tape.iter().enumerate().filter(|&(_, &cell)| cell != 0) {
+        let offset = address as i32 - data_pointer as i32;
+        emitter.move_by(offset - p);
+        emitter.adjust(i32::from(cell));
+        p = offset;
+    }
+    emitter.move_by(-p);
+
+    emitter.block(program);
+    emitter.out.push('\n');
+    emitter.out
+}
+
+struct Emitter {
+    out: String,
+}
+
+impl Emitter {
+    fn repeat(&mut self, symbol: char, count: usize) {
+        self.out.extend(iter::repeat_n(symbol, count));
+    }
+
+    fn move_by(&mut self, n: i32) {
+        match n {
+            n if n > 0 => self.repeat('>', n as usize),
+            n => self.repeat('<', -n as usize),
+        }
+    }
+
+    /// Adds `n` to the cell, modulo 256
+    fn adjust(&mut self, n: i32) {
+        match n as u8 {
+            n if n < 128 => self.repeat('+', usize::from(n)),
+            n => self.repeat('-', 256 - usize::from(n)),
+        }
+    }
+
+    fn block(&mut self, program: &[BigInsn]) {
+        let mut insns = program.iter();
+
+        while let Some(insn) = insns.next() {
+            match insn {
+                BigInsn::MulAdd(targets) => {
+                    // The loop leaves the count zero, what the cell is set to
+                    // next is added after it
+                    let value = match insns.next() {
+                        Some(BigInsn::Clear) => 0,
+                        Some(BigInsn::Set(value)) => *value,
+                        _ => panic!("a MulAdd without a Clear after it has no plain form"),
+                    };
+                    self.out.push_str("[-");
+                    let mut p = 0;
+                    for &(offset, k) in targets {
+                        self.move_by(offset - p);
+                        self.adjust(k);
+                        p = offset;
+                    }
+                    self.move_by(-p);
+                    self.out.push(']');
+                    self.adjust(i32::from(value));
+                }
+                insn => self.insn(insn),
+            }
+        }
+    }
+
+    fn insn(&mut self, insn: &BigInsn) {
+        match insn {
+            BigInsn::Move(n) => self.move_by(*n),
+            BigInsn::Adj(n) => self.adjust(*n),
+            BigInsn::Set(value) => {
+                self.out.push_str("[-]");
+                self.adjust(i32::from(*value));
+            }
+            BigInsn::Clear => self.out.push_str("[-]"),
+            BigInsn::MulAdd(_) => unreachable!("MulAdd is written with the Clear after it"),
+            BigInsn::Seek(stride) => {
+                self.out.push('[');
+                self.move_by(*stride);
+                self.out.push(']');
+            }
+            BigInsn::Write => self.out.push('.'),
+            BigInsn::Read => self.out.push(','),
+            BigInsn::Loop(body) => {
+                self.out.push('[');
+                self.block(body);
+                self.out.push(']');
+            }
+            // Only folding makes these, for the start of a program on a
+            // blank tape, so the cells they go through are zero
+            BigInsn::WriteBytes(bytes) => {
+                let mut cell = 0u8;
+                for &byte in bytes {
+                    self.adjust(i32::from(byte.wrapping_sub(cell)));
+                    self.out.push('.');
+                    cell = byte;
+                }
+                if cell != 0 {
+                    self.out.push_str("[-]");
+                }
+            }
+            BigInsn::Snapshot { cells, pointer } => {
+                let mut p = 0;
+                for &(offset, value) in cells {
+                    self.move_by(offset - p);
+                    self.adjust(i32::from(value));
+                    p = offset;
+                }
+                self.move_by(pointer - p);
+            }
+        }
+    }
+}
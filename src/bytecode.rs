@@ -0,0 +1,166 @@
+//! A flat form of the fused program and a dispatch loop to run it. Loops
+//! become a pair of jumps that know where the other one is, so the dispatch
+//! loop doesn't recurse, though parsing and lowering the program before it
+//! do, which is what `MAX_NESTING` bounds.
+//!
+//! It is no faster than the closure backends: on mandelbrot it takes about
+//! as long as `closure` and longer than `big-closure`.
+
+use {fused_iteration_steps, fused_straight_steps, BigInsn, Machine};
+
+/// One bytecode instruction. Cells are addressed by their offset from the
+/// data pointer, which only moves for `Move`, `Seek` and loops, and jump
+/// targets are indices into the code.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "json", derive(::serde::Serialize))]
+pub enum Op {
+    Move(i32),
+    Adj(i32, u8),
+    Set(i32, u8),
+    /// Adds the cell at `from` times `k` to the cell at `to`
+    MulAdd {
+        from: i32,
+        to: i32,
+        k: u8,
+    },
+    Seek(i32),
+    Write(i32),
+    /// Writes a constant byte
+    Emit(u8),
+    Read(i32),
+    /// Costs the steps of the commands outside of any loop, at the start of
+    /// the code
+    Steps(u32),
+    /// Jumps past the matching `Close` if the cell is zero. Every iteration
+    /// costs `steps`.
+    Open {
+        close: u32,
+        steps: u32,
+    },
+    /// Jumps back to just after the matching `Open` unless the cell is zero
+    Close {
+        open: u32,
+        steps: u32,
+    },
+}
+
+/// Flattens the fused form of a program
+pub fn compile(program: &[BigInsn]) -> Vec<Op> {
+    let mut code = vec![Op::Steps(fused_straight_steps(program) as u32)];
+    let offset = emit(program, 0, &mut code);
+    settle(offset, &mut code);
+    code
+}
+
+/// Moves the data pointer to where the code so far has been working
+fn settle(offset: i32, code: &mut Vec<Op>) {
+    if offset != 0 {
+        code.push(Op::Move(offset));
+    }
+}
+
+/// Emits a block with the data pointer `offset` cells behind where the
+/// program has it, returning how far behind it is at the end
+fn emit(program: &[BigInsn], mut offset: i32, code: &mut Vec<Op>) -> i32 {
+    for insn in program {
+        match insn {
+            BigInsn::Move(n) => offset += n,
+            BigInsn::Adj(n) => code.push(Op::Adj(offset, *n as u8)),
+            BigInsn::Set(value) => code.push(Op::Set(offset, *value)),
+            BigInsn::Clear => code.push(Op::Set(offset, 0)),
+            BigInsn::MulAdd(targets) => {
+                // The targets never include the current cell, so it still
+                // holds the count for every one of them
+                for &(target, k) in targets {
+                    code.push(Op::MulAdd {
+                        from: offset,
+                        to: offset + target,
+                        k: k as u8,
+                    });
+                }
+            }
+            BigInsn::Seek(stride) => {
+                settle(offset, code);
+                offset = 0;
+                code.push(Op::Seek(*stride));
+            }
+            BigInsn::Write => code.push(Op::Write(offset)),
+            BigInsn::Read => code.push(Op::Read(offset)),
+            BigInsn::WriteBytes(bytes) => code.extend(bytes.iter().map(|&byte| Op::Emit(byte))),
+            BigInsn::Snapshot { cells, pointer } => {
+                for &(cell, value) in cells {
+                    code.push(Op::Set(offset + cell, value));
+                }
+                offset += pointer;
+            }
+            BigInsn::Loop(body) => {
+                settle(offset, code);
+                offset = 0;
+
+                let steps = fused_iteration_steps(body) as u32;
+                let open = code.len() as u32;
+                code.push(Op::Open { close: 0, steps });
+
+                let end = emit(body, 0, code);
+                settle(end, code);
+
+                let close = code.len() as u32;
+                code.push(Op::Close { open, steps });
+                code[open as usize] = Op::Open { close, steps };
+            }
+        }
+    }
+
+    offset
+}
+
+/// Runs bytecode, returning the final data pointer
+pub fn run(code: &[Op], m: &mut Machine, mut p: i32) -> i32 {
+    let mut pc = 0;
+
+    while let Some(&op) = code.get(pc) {
+        match op {
+            Op::Move(n) => p += n,
+            Op::Adj(offset, n) => {
+                let q = p + offset;
+                m.tape.set(q, m.tape.get(q).wrapping_add(n));
+            }
+            Op::Set(offset, value) => m.tape.set(p + offset, value),
+            Op::MulAdd { from, to, k } => {
+                // A count of zero is a loop that isn't entered, which
+                // touches no other cell
+                let count = m.tape.get(p + from);
+                if count != 0 {
+                    let q = p + to;
+                    m.tape
+                        .set(q, m.tape.get(q).wrapping_add(count.wrapping_mul(k)));
+                }
+            }
+            Op::Seek(stride) => p = m.seek(p, stride),
+            Op::Write(offset) => m.output.write(m.tape.get(p + offset)),
+            Op::Emit(byte) => m.output.write(byte),
+            Op::Read(offset) => {
+                if let Some(value) = m.read() {
+                    m.tape.set(p + offset, value);
+                }
+            }
+            Op::Steps(steps) => m.enter(p, u64::from(steps)),
+            Op::Open { close, steps } => {
+                if m.tape.get(p) == 0 {
+                    pc = close as usize;
+                } else {
+                    m.back_edge(p, u64::from(steps));
+                }
+            }
+            Op::Close { open, steps } => {
+                if m.tape.get(p) != 0 {
+                    m.back_edge(p, u64::from(steps));
+                    pc = open as usize;
+                }
+            }
+        }
+        pc += 1;
+    }
+
+    p
+}
@@ -0,0 +1,132 @@
+use std::fmt::Write;
+
+use input::EofBehavior;
+use BigInsn;
+
+/// Translates a program to portable C for a standalone executable. The tape
+/// is a static array initialized from `tape`, the data pointer points into
+/// it, and I/O goes through `getchar`/`putchar`, flushing stdout before
+/// every read. Unlike the interpreter's tape the array doesn't grow.
+pub fn emit(program: &[BigInsn], tape: &[u8], data_pointer: usize, eof: EofBehavior) -> String {
+    let mut emitter = Emitter {
+        out: String::new(),
+        eof,
+        depth: 1,
+    };
+
+    emitter.header(tape, data_pointer);
+    emitter.block(program);
+    emitter.line("return 0;");
+    writeln!(emitter.out, "}}").unwrap();
+
+    emitter.out
+}
+
+struct Emitter {
+    out: String,
+    eof: EofBehavior,
+    /// How deeply the current line is indented
+    depth: usize,
+}
+
+impl Emitter {
+    fn header(&mut self, tape: &[u8], data_pointer: usize) {
+        writeln!(self.out, "/* Generated by bf --emit c */").unwrap();
+        writeln!(self.out).unwrap();
+        writeln!(self.out, "#include <stdio.h>").unwrap();
+        writeln!(self.out).unwrap();
+
+        if tape.iter().all(|&cell| cell == 0) {
+            writeln!(self.out, "static unsigned char tape[{}];", tape.len()).unwrap();
+        } else {
+            writeln!(self.out, "static unsigned char tape[{}] = {{", tape.len()).unwrap();
+            for row in tape.chunks(16) {
+                let cells: Vec<String> = row.iter().map(|cell| cell.to_string()).collect();
+                writeln!(self.out, "    {},", cells.join(", ")).unwrap();
+            }
+            writeln!(self.out, "}};").unwrap();
+        }
+
+        writeln!(self.out).unwrap();
+        writeln!(self.out, "int main(void) {{").unwrap();
+        self.line(&format!("unsigned char *p = tape + {};", data_pointer));
+        writeln!(self.out).unwrap();
+    }
+
+    fn line(&mut self, line: &str) {
+        writeln!(self.out, "{:width$}{}", "", line, width = 4 * self.depth).unwrap();
+    }
+
+    fn move_by(&mut self, n: i32) {
+        match n {
+            0 => (),
+            n if n > 0 => self.line(&format!("p += {};", n)),
+            n => self.line(&format!("p -= {};", -n)),
+        }
+    }
+
+    fn block(&mut self, program: &[BigInsn]) {
+        for insn in program {
+            self.insn(insn);
+        }
+    }
+
+    fn insn(&mut self, insn: &BigInsn) {
+        match insn {
+            BigInsn::Move(n) => self.move_by(*n),
+            BigInsn::Adj(n) => self.line(&format!("*p += {};", *n as u8)),
+            BigInsn::Set(value) => self.line(&format!("*p = {};", value)),
+            BigInsn::Clear => self.line("*p = 0;"),
+            BigInsn::MulAdd(targets) => {
+                for &(offset, k) in targets {
+                    self.line(&format!("p[{}] += *p * {};", offset, k as u8));
+                }
+            }
+            BigInsn::Seek(stride) => self.line(&format!("while (*p) p += {};", stride)),
+            BigInsn::Write => self.line("putchar(*p);"),
+            BigInsn::Read => {
+                self.line("fflush(stdout);");
+                self.line("{");
+                self.depth += 1;
+                self.line("int c = getchar();");
+                match self.eof {
+                    EofBehavior::Unchanged => self.line("if (c != EOF) *p = c;"),
+                    EofBehavior::Zero => self.line("*p = c == EOF ? 0 : c;"),
+                    EofBehavior::Max => self.line("*p = c == EOF ? 255 : c;"),
+                }
+                self.depth -= 1;
+                self.line("}");
+            }
+            BigInsn::Loop(body) => {
+                self.line("while (*p) {");
+                self.depth += 1;
+                self.block(body);
+                self.depth -= 1;
+                self.line("}");
+            }
+            BigInsn::WriteBytes(bytes) => {
+                // Octal escapes always take three digits, so nothing that
+                // follows one can be mistaken for part of it
+                let escaped: String = bytes
+                    .iter()
+                    .map(|&byte| match byte {
+                        b'"' | b'\\' | b'?' => format!("\\{}", byte as char),
+                        b' '..=b'~' => (byte as char).to_string(),
+                        _ => format!("\\{:03o}", byte),
+                    })
+                    .collect();
+                self.line(&format!(
+                    "fwrite(\"{}\", 1, {}, stdout);",
+                    escaped,
+                    bytes.len()
+                ));
+            }
+            BigInsn::Snapshot { cells, pointer } => {
+                for &(offset, value) in cells {
+                    self.line(&format!("p[{}] = {};", offset, value));
+                }
+                self.move_by(*pointer);
+            }
+        }
+    }
+}
@@ -0,0 +1,198 @@
+//! Cells wider than a byte, and cells that trap instead of wrapping around.
+//!
+//! The optimizer and the fast backends work on 8-bit cells that wrap, which
+//! is what nearly every program expects. Anything else runs here instead, on
+//! a tape of `Cell`s with the parsed program walked as is: every `+` and `-`
+//! steps one cell by one, with wrapping or trapping decided by `Overflow`.
+
+use std::fmt;
+use std::panic;
+
+use tape::{Tape, TapePolicy};
+use {iteration_steps, straight_steps, Instruction, Machine};
+
+/// A value the tape can hold
+pub trait Cell: Copy + Default + PartialEq + fmt::Debug {
+    const BITS: u32;
+    /// Largest value, what -1 wraps around to
+    const MAX: u64;
+
+    /// Keeps the low bits of `value`
+    fn truncate(value: u64) -> Self;
+    /// What `.` writes
+    fn low_byte(self) -> u8;
+    fn wrapping_step(self, up: bool) -> Self;
+    /// None where the step would wrap around
+    fn checked_step(self, up: bool) -> Option<Self>;
+}
+
+macro_rules! cell {
+    ($t:ty) => {
+        impl Cell for $t {
+            const BITS: u32 = <$t>::BITS;
+            const MAX: u64 = <$t>::MAX as u64;
+
+            fn truncate(value: u64) -> $t {
+                value as $t
+            }
+
+            fn low_byte(self) -> u8 {
+                self as u8
+            }
+
+            #[inline]
+            fn wrapping_step(self, up: bool) -> $t {
+                if up {
+                    self.wrapping_add(1)
+                } else {
+                    self.wrapping_sub(1)
+                }
+            }
+
+            #[inline]
+            fn checked_step(self, up: bool) -> Option<$t> {
+                if up {
+                    self.checked_add(1)
+                } else {
+                    self.checked_sub(1)
+                }
+            }
+        }
+    };
+}
+
+cell!(u8);
+cell!(u16);
+cell!(u32);
+
+/// How many bits a cell has
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CellWidth {
+    U8,
+    U16,
+    U32,
+}
+
+/// What `+` on the largest value and `-` on zero do
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Overflow {
+    /// Wrap around to zero, or to the largest value
+    Wrap,
+    /// Stop the program, by panicking with `CellOverflow`
+    Trap,
+}
+
+/// Panic payload of a program whose cell overflowed with `Overflow::Trap`
+#[derive(Debug, Clone, PartialEq)]
+pub struct CellOverflow {
+    pub address: i32,
+    pub bits: u32,
+    /// Whether it was `+` on the largest value rather than `-` on zero
+    pub up: bool,
+}
+
+impl fmt::Display for CellOverflow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (command, from) = match self.up {
+            true => ('+', u64::MAX >> (64 - self.bits)),
+            false => ('-', 0),
+        };
+        write!(
+            f,
+            "'{}' on cell {} overflows its {} bits, it holds {}",
+            command, self.address, self.bits, from
+        )
+    }
+}
+
+/// Whether a width and overflow are the usual ones, which the backends
+/// handle without this module
+pub fn is_plain(width: CellWidth, overflow: Overflow) -> bool {
+    width == CellWidth::U8 && overflow == Overflow::Wrap
+}
+
+/// Runs a program on a tape of `width` cells, set up from `cells` and
+/// `policy`, returning the final data pointer. The machine provides the I/O
+/// and the step limit; its own tape isn't touched.
+pub fn run_width(
+    width: CellWidth,
+    overflow: Overflow,
+    program: &[Instruction],
+    cells: &[u8],
+    policy: TapePolicy,
+    m: &mut Machine,
+    p: i32,
+) -> i32 {
+    fn widened<C: Cell>(cells: &[u8], policy: TapePolicy) -> Tape<C> {
+        let cells = cells.iter().map(|&cell| C::truncate(u64::from(cell)));
+        Tape::new(cells.collect()).with_policy(policy)
+    }
+
+    m.enter(p, straight_steps(program));
+    match width {
+        CellWidth::U8 => run(program, &mut widened::<u8>(cells, policy), overflow, m, p),
+        CellWidth::U16 => run(program, &mut widened::<u16>(cells, policy), overflow, m, p),
+        CellWidth::U32 => run(program, &mut widened::<u32>(cells, policy), overflow, m, p),
+    }
+}
+
+/// Runs a parsed program on `tape`, returning the final data pointer
+pub fn run<C: Cell>(
+    program: &[Instruction],
+    tape: &mut Tape<C>,
+    overflow: Overflow,
+    m: &mut Machine,
+    mut p: i32,
+) -> i32 {
+    for instr in program {
+        match instr {
+            Instruction::IncrementPointer => p += 1,
+            Instruction::DecrementPointer => p -= 1,
+            Instruction::Increment => step(tape, p, true, overflow),
+            Instruction::Decrement => step(tape, p, false, overflow),
+            Instruction::Write => m.output.write(tape.get(p).low_byte()),
+            Instruction::Read => {
+                m.output.flush();
+                if let Some(value) = m.input.read_cell() {
+                    tape.set(p, value);
+                }
+            }
+            Instruction::Loop(body) => {
+                let steps = iteration_steps(body);
+                while tape.get(p) != C::default() {
+                    m.back_edge(p, steps);
+                    p = run(body, tape, overflow, m, p);
+                }
+            }
+            Instruction::WriteBytes(bytes) => {
+                for &byte in bytes {
+                    m.output.write(byte);
+                }
+            }
+            Instruction::Snapshot { cells, pointer } => {
+                for &(offset, value) in cells {
+                    tape.set(p + offset, C::truncate(u64::from(value)));
+                }
+                p += pointer;
+            }
+        }
+    }
+
+    p
+}
+
+#[inline]
+fn step<C: Cell>(tape: &mut Tape<C>, p: i32, up: bool, overflow: Overflow) {
+    let cell = tape.get(p);
+    let value = match overflow {
+        Overflow::Wrap => cell.wrapping_step(up),
+        Overflow::Trap => cell.checked_step(up).unwrap_or_else(|| {
+            panic::panic_any(CellOverflow {
+                address: p,
+                bits: C::BITS,
+                up,
+            })
+        }),
+    };
+    tape.set(p, value);
+}
@@ -0,0 +1,204 @@
+//! Saved states of a program stepped through by a `Debugger`, so a long run
+//! that was interrupted can pick up where it was rather than start over.
+//!
+//! The debugger's commands know where their partner brackets are, so the
+//! index of the next command stands in for the stack of open loops. A
+//! checkpoint is a text file with one field per line:
+//!
+//! ```text
+//! bfstate 1
+//! program 9a3f5c0e12d4b867
+//! pc 1042
+//! pointer 3
+//! steps 1000000
+//! input 12
+//! output 40
+//! start 0
+//! cells 00010a...
+//! ```
+//!
+//! `program` is a fingerprint of the source, so a state isn't resumed on some
+//! other program, `input` and `output` are how many bytes had been read and
+//! written, and the tape is `cells` in hex from address `start` on.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+/// What there is of a run at a command boundary
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    /// `fingerprint` of the source
+    pub program: u64,
+    /// Index of the next command
+    pub pc: usize,
+    pub pointer: i32,
+    pub steps: u64,
+    /// Bytes of input read so far
+    pub input: u64,
+    /// Bytes written so far
+    pub output: u64,
+    /// Address of the first of `cells`
+    pub start: i32,
+    pub cells: Vec<u8>,
+}
+
+const MAGIC: &str = "bfstate 1";
+
+impl Checkpoint {
+    pub fn to_text(&self) -> String {
+        let cells: String = self
+            .cells
+            .iter()
+            .map(|cell| format!("{:02x}", cell))
+            .collect();
+
+        format!(
+            "{}\nprogram {:016x}\npc {}\npointer {}\nsteps {}\ninput {}\noutput {}\nstart {}\ncells {}\n",
+            MAGIC,
+            self.program,
+            self.pc,
+            self.pointer,
+            self.steps,
+            self.input,
+            self.output,
+            self.start,
+            cells
+        )
+    }
+
+    pub fn parse(text: &str) -> Result<Checkpoint, String> {
+        let mut lines = text.lines();
+        if lines.next() != Some(MAGIC) {
+            return Err("not a bf checkpoint".to_string());
+        }
+
+        // Every field, in order
+        let mut field = |name: &str| {
+            let line = lines.next().unwrap_or("");
+            match line.split_once(' ') {
+                Some((key, value)) if key == name => Ok(value.to_string()),
+                _ => Err(format!("expected the {} of the checkpoint", name)),
+            }
+        };
+        let program = field("program")?;
+        let program = u64::from_str_radix(&program, 16)
+            .map_err(|_| format!("invalid program fingerprint '{}'", program))?;
+        let pc = number(&field("pc")?)?;
+        let pointer = number(&field("pointer")?)?;
+        let steps = number(&field("steps")?)?;
+        let input = number(&field("input")?)?;
+        let output = number(&field("output")?)?;
+        let start = number(&field("start")?)?;
+
+        let cells = field("cells")?;
+        // A tape has at least the cell the pointer starts on
+        if cells.is_empty() {
+            return Err("no cells in checkpoint".to_string());
+        }
+        if cells.len() % 2 != 0 || !cells.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err("invalid cells in checkpoint".to_string());
+        }
+        let cells = (0..cells.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&cells[i..i + 2], 16).unwrap())
+            .collect();
+
+        Ok(Checkpoint {
+            program,
+            pc,
+            pointer,
+            steps,
+            input,
+            output,
+            start,
+            cells,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Checkpoint, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Checkpoint::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Writes the checkpoint next to `path` and then moves it over, so being
+    /// interrupted while saving leaves the last checkpoint as it was
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".partial");
+        fs::write(&partial, self.to_text())?;
+        fs::rename(&partial, path)
+    }
+}
+
+fn number<T: FromStr>(value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid number '{}' in checkpoint", value))
+}
+
+/// A 64-bit FNV-1a hash of a program's source, unlike `Hash` the same
+/// from one build to the next
+pub fn fingerprint(source: &[u8]) -> u64 {
+    source.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::*;
+    use debug::{Debugger, Stop};
+    use input::{Input, InputMode, RangePolicy};
+    use output::{Output, OutputMode};
+    use tape::Tape;
+    use Machine;
+
+    const SOURCE: &[u8] = b"++++[>+++++<-]>[>++<-]>.";
+
+    fn debugger() -> Debugger {
+        let machine = Machine::new(
+            Tape::new(vec![0; 16]),
+            Input::new(InputMode::Byte, RangePolicy::Error, Box::new(io::empty())),
+            Output::new(OutputMode::Char, Box::new(io::sink())).record(),
+        );
+        Debugger::new(SOURCE, machine, 0).unwrap()
+    }
+
+    #[test]
+    fn a_run_picks_up_where_its_checkpoint_left_it() {
+        let mut whole = debugger();
+        assert_eq!(whole.resume(), Ok(Stop::Finished));
+
+        let mut first = debugger();
+        assert_eq!(first.run_for(30), Ok(Stop::Done));
+        let text = first.checkpoint().to_text();
+
+        let mut second = debugger();
+        second.restore(&Checkpoint::parse(&text).unwrap()).unwrap();
+        assert_eq!(second.steps(), 30);
+        assert_eq!(second.resume(), Ok(Stop::Finished));
+
+        assert_eq!(second.steps(), whole.steps());
+        assert_eq!(second.pointer(), whole.pointer());
+        assert_eq!(second.machine().output.recording(), Some(&b"("[..]));
+        assert_eq!(
+            second.machine().output.recording(),
+            whole.machine().output.recording()
+        );
+        for p in 0..16 {
+            assert_eq!(second.machine().tape.get(p), whole.machine().tape.get(p));
+        }
+    }
+
+    #[test]
+    fn a_checkpoint_without_cells_is_refused() {
+        let mut checkpoint = debugger().checkpoint();
+        checkpoint.cells.clear();
+        let e = Checkpoint::parse(&checkpoint.to_text()).unwrap_err();
+        assert_eq!(e, "no cells in checkpoint");
+    }
+}
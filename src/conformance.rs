@@ -0,0 +1,449 @@
+//! What every backend must agree on, and a battery of edge cases to check it.
+//!
+//! * The tape starts with `TAPE_SIZE` cells of 8 bits, all zero, and the
+//!   data pointer starts on cell `START_POINTER`.
+//! * The tape grows on demand in both directions, so every cell can be used.
+//! * Cells wrap around: 255 + 1 is 0 and 0 - 1 is 255.
+//! * `.` writes the cell as exactly one byte, `,` reads exactly one byte.
+//! * Reading past the end of the input leaves the cell unchanged.
+//! * A loop is skipped entirely when its cell is zero on entry, and touches
+//!   none of the cells of its body, so it can't leave a tape that can't grow.
+//!
+//! Each case runs through every backend, both as parsed and after the
+//! constant prefix has been folded, and at the lower optimization levels,
+//! and all of them must produce the expected output and leave identical
+//! tapes behind. `compare` does the same for any program, with nothing
+//! expected but that they agree.
+
+use std::fmt;
+use std::io::{self, Cursor, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+
+use backend::{self, Backend, BACKENDS};
+use fold::fold_constant_prefix;
+use input::{EofBehavior, Input, InputMode, RangePolicy};
+use output::{Output, OutputMode};
+use tape::{Tape, TapePolicy};
+use {lex, parse, tape_bounds, Instruction, Machine, OptLevel, START_POINTER, TAPE_SIZE};
+
+pub enum Expected {
+    Output(&'static [u8]),
+}
+
+pub struct Case {
+    pub name: &'static str,
+    pub source: String,
+    pub input: &'static [u8],
+    pub expected: Expected,
+    /// Grow, unless the case is about a tape that can't
+    pub policy: TapePolicy,
+}
+
+fn case(name: &'static str, source: &str, input: &'static [u8], expected: Expected) -> Case {
+    Case {
+        name,
+        source: source.to_string(),
+        input,
+        expected,
+        policy: TapePolicy::Grow,
+    }
+}
+
+impl Case {
+    fn on_tape(self, policy: TapePolicy) -> Case {
+        Case { policy, ..self }
+    }
+}
+
+pub fn cases() -> Vec<Case> {
+    use self::Expected::Output;
+
+    let to_last_cell = ">".repeat(TAPE_SIZE - START_POINTER - 1);
+
+    vec![
+        case("increment wraps at 255", "-+.", b"", Output(b"\x00")),
+        case("decrement wraps at 0", "-.", b"", Output(b"\xff")),
+        case(
+            "256 increments return to zero",
+            &("+".repeat(256) + "."),
+            b"",
+            Output(b"\x00"),
+        ),
+        case(
+            "256 decrements return to zero",
+            &("-".repeat(256) + "."),
+            b"",
+            Output(b"\x00"),
+        ),
+        case("high bytes are written raw", "--.", b"", Output(b"\xfe")),
+        case(
+            "200 is written as one byte",
+            &("+".repeat(200) + "."),
+            b"",
+            Output(b"\xc8"),
+        ),
+        case("read echoes a byte", ",.", b"A", Output(b"A")),
+        case("read past end of input", "+++,.", b"", Output(b"\x03")),
+        case(
+            "cat stops at end of input",
+            ",[.[-],]",
+            b"hi",
+            Output(b"hi"),
+        ),
+        case("empty loop is skipped", "[].", b"", Output(b"\x00")),
+        case(
+            "comment loop is skipped",
+            "[this is a comment, really.]+.",
+            b"",
+            Output(b"\x01"),
+        ),
+        case(
+            "deep nesting",
+            &("+".to_string() + &"[".repeat(100) + "-" + &"]".repeat(100) + "."),
+            b"",
+            Output(b"\x00"),
+        ),
+        case(
+            "comment loop that reads is skipped",
+            "[read, this.]+.",
+            b"x",
+            Output(b"\x01"),
+        ),
+        case(
+            "loop right after a clear is skipped",
+            ",[-][.+]+.",
+            b"x",
+            Output(b"\x01"),
+        ),
+        case(
+            "loop right after a loop is skipped",
+            ",[.[-]][.]+.",
+            b"a",
+            Output(b"a\x01"),
+        ),
+        case("clear with [-]", "+++++[-].", b"", Output(b"\x00")),
+        case("clear with [+] wraps", "+++++[+].", b"", Output(b"\x00")),
+        case(
+            "multiply loop",
+            "++++[>+++<-]>.<.",
+            b"",
+            Output(b"\x0c\x00"),
+        ),
+        case(
+            "multiply loop with wrapping products",
+            ",[->++>+++<<]>.>.<<.",
+            b"\xc8",
+            Output(b"\x90\x58\x00"),
+        ),
+        case(
+            "nested output",
+            "++[>+++[>+.<-]<-]",
+            b"",
+            Output(b"\x01\x02\x03\x04\x05\x06"),
+        ),
+        case(
+            "transfer of a known count",
+            ",[-]+++++[->+<]>.<.",
+            b"x",
+            Output(b"\x05\x00"),
+        ),
+        case(
+            "transfer counting up",
+            ",[-]-[+>--<]>.",
+            b"x",
+            Output(b"\xfe"),
+        ),
+        case(
+            "transfer counting up by one",
+            ",[+>++<]>.",
+            b"\x03",
+            Output(b"\xfa"),
+        ),
+        case(
+            "transfer counting down by three",
+            ",[--->+<]>.",
+            b"\x04",
+            Output(b"\xac"),
+        ),
+        case("clear with [---]", ",[---].", b"\x04", Output(b"\x00")),
+        case("scan right", ">+>+>+<<[>].<.", b"", Output(b"\x00\x01")),
+        case("scan left", "<+<+<+>>[<].>.", b"", Output(b"\x00\x01")),
+        case(
+            "scan lands on the first zero",
+            ",>,>,<<[>]+<<<[.>]",
+            b"\x01\x01\x01",
+            Output(b"\x01\x01\x01\x01"),
+        ),
+        case(
+            "long scan right",
+            &("+>".repeat(20) + &"<".repeat(20) + "[>]<.>>[-]+[<]>."),
+            b"",
+            Output(b"\x01\x01"),
+        ),
+        case(
+            "long scan left",
+            &("+<".repeat(20) + &">".repeat(20) + "[<]>.<<[-]+[>]<."),
+            b"",
+            Output(b"\x01\x01"),
+        ),
+        case("strided scan", "+>>+>>+<<<<[>>]<<.", b"", Output(b"\x01")),
+        case(
+            "hello world",
+            "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.\
+             +++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.",
+            b"",
+            Output(b"Hello World!\n"),
+        ),
+        case(
+            "last cell is usable",
+            &(to_last_cell.clone() + "+."),
+            b"",
+            Output(b"\x01"),
+        ),
+        case(
+            "skipped multiply loop on the last cell",
+            &(to_last_cell.clone() + ",[->+<]+."),
+            b"\x00",
+            Output(b"\x01"),
+        )
+        .on_tape(TapePolicy::Error),
+        case(
+            "tape grows to the right",
+            &(to_last_cell + ">+.<."),
+            b"",
+            Output(b"\x01\x00"),
+        ),
+        case(
+            "tape grows to the left",
+            &("<".repeat(START_POINTER + 1) + "+.>."),
+            b"",
+            Output(b"\x01\x00"),
+        ),
+        case(
+            "scan across grown cells",
+            &("<+".repeat(START_POINTER + 100) + &">".repeat(START_POINTER) + "[<]>."),
+            b"",
+            Output(b"\x01"),
+        ),
+        case(
+            "wandering off and back is fine",
+            &("<".repeat(START_POINTER + 10) + &">".repeat(START_POINTER + 10) + "+."),
+            b"",
+            Output(b"\x01"),
+        ),
+    ]
+}
+
+/// What a run produced, as far as the spec is concerned
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Finished {
+        output: Vec<u8>,
+        tape: Tape,
+        pointer: i32,
+    },
+    Error,
+}
+
+pub struct Failure {
+    case: String,
+    pipeline: String,
+    problem: String,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} [{}]: {}", self.case, self.pipeline, self.problem)
+    }
+}
+
+/// Sink keeping everything written to it where the caller can still get at it
+#[derive(Clone, Default)]
+struct Captured(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn execute(
+    level: OptLevel,
+    backend: &dyn Backend,
+    program: &[Instruction],
+    input: &[u8],
+    eof: EofBehavior,
+    policy: TapePolicy,
+) -> Outcome {
+    // What actually reaches the sink, so the encoding is checked as well
+    let written = Captured::default();
+    let mut machine = Machine::new(
+        Tape::new(vec![0; TAPE_SIZE]).with_policy(policy),
+        Input::new(
+            InputMode::Byte,
+            RangePolicy::Error,
+            Box::new(Cursor::new(input.to_vec())),
+        )
+        .on_eof(eof),
+        Output::new(OutputMode::Char, Box::new(written.clone())),
+    );
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        backend::run_at(level, backend, program, &mut machine, START_POINTER as i32)
+    }));
+
+    match result {
+        Ok(pointer) => Outcome::Finished {
+            output: written.0.lock().unwrap().clone(),
+            tape: machine.tape,
+            pointer,
+        },
+        Err(_) => Outcome::Error,
+    }
+}
+
+fn check(case: &Case, failures: &mut Vec<Failure>) {
+    let parsed = parse(lex(case.source.clone())).expect("conformance case doesn't parse");
+    let Expected::Output(expected) = case.expected;
+    failures.extend(differ(
+        case.name,
+        &parsed,
+        case.input,
+        EofBehavior::Unchanged,
+        case.policy,
+        Some(expected),
+    ));
+}
+
+/// Runs a parsed program with the given input through every pipeline the
+/// battery uses, returning those that didn't finish the way the first one
+/// did: with the same output, tape and data pointer, or failing too
+pub fn compare(
+    name: &str,
+    program: &[Instruction],
+    input: &[u8],
+    eof: EofBehavior,
+) -> Vec<Failure> {
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let failures = differ(name, program, input, eof, TapePolicy::Grow, None);
+    panic::set_hook(hook);
+    failures
+}
+
+fn differ(
+    name: &str,
+    parsed: &[Instruction],
+    input: &[u8],
+    eof: EofBehavior,
+    policy: TapePolicy,
+    expected: Option<&[u8]>,
+) -> Vec<Failure> {
+    let parsed = parsed.to_vec();
+    let folded = fold_constant_prefix(parsed.clone(), tape_bounds());
+    let mut failures = Vec::new();
+    let mut reference: Option<(String, Outcome)> = None;
+
+    let mut pipelines = Vec::new();
+    for backend in BACKENDS {
+        for &(stage, program) in &[("parsed", &parsed), ("folded", &folded)] {
+            let name = format!("{} {}", backend.name(), stage);
+            pipelines.push((name, OptLevel::Full, *backend, program));
+        }
+    }
+    for &(name, level) in &[("opt none", OptLevel::None), ("opt basic", OptLevel::Basic)] {
+        pipelines.push((name.to_string(), level, BACKENDS[0], &parsed));
+    }
+
+    for (pipeline, level, backend, program) in pipelines {
+        let outcome = execute(level, backend, program, input, eof, policy);
+
+        let problem = match (expected, &outcome) {
+            (None, _) => None,
+            (Some(_), Outcome::Error) => Some("failed".to_string()),
+            (Some(expected), Outcome::Finished { output, .. }) => {
+                if output.as_slice() != expected {
+                    Some(format!("wrote {:?}, expected {:?}", output, expected))
+                } else {
+                    None
+                }
+            }
+        };
+
+        let problem = problem.or_else(|| match &reference {
+            Some((first, expected)) => divergence(first, expected, &outcome),
+            None => None,
+        });
+
+        match problem {
+            Some(problem) => failures.push(Failure {
+                case: name.to_string(),
+                pipeline,
+                problem,
+            }),
+            None if reference.is_none() => reference = Some((pipeline, outcome)),
+            None => (),
+        }
+    }
+
+    failures
+}
+
+/// How an outcome differs from that of the `first` pipeline, if it does
+fn divergence(first: &str, expected: &Outcome, outcome: &Outcome) -> Option<String> {
+    match (expected, outcome) {
+        _ if expected == outcome => None,
+        (Outcome::Finished { .. }, Outcome::Error) => Some(format!("failed, unlike {}", first)),
+        (Outcome::Error, Outcome::Finished { .. }) => Some(format!("finished, unlike {}", first)),
+        (Outcome::Finished { output: a, .. }, Outcome::Finished { output: b, .. }) if a != b => {
+            let at = a.iter().zip(b).take_while(|(a, b)| a == b).count();
+            Some(format!(
+                "wrote different output than {}, from byte {} on",
+                first, at
+            ))
+        }
+        _ => Some(format!("left a different tape than {}", first)),
+    }
+}
+
+/// Runs every case through every backend, returning what went wrong
+pub fn run_battery() -> Vec<Failure> {
+    // A failing run panics, report it as a failure rather than a backtrace
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+
+    let mut failures = Vec::new();
+    for case in cases() {
+        check(&case, &mut failures);
+    }
+
+    panic::set_hook(hook);
+    failures
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    #[test]
+    fn the_battery_passes() {
+        // On a stack the size of the main thread's, which `bf --conformance`
+        // runs on, as the deep nesting case recurses past a test thread's
+        let failures = thread::Builder::new()
+            .stack_size(8 << 20)
+            .spawn(run_battery)
+            .unwrap()
+            .join()
+            .unwrap();
+        let report: Vec<String> = failures.iter().map(|f| f.to_string()).collect();
+        assert!(failures.is_empty(), "{}", report.join("\n"));
+    }
+}
@@ -0,0 +1,200 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+
+use tape::Tape;
+use {compile_owned, Instruction, Machine};
+
+/// Where the program is, as seen from the controlling side
+#[derive(Debug, Clone, PartialEq)]
+pub enum RunState {
+    Running,
+    /// Parked at a loop boundary with a copy of the tape and the data pointer
+    Paused {
+        tape: Tape,
+        pointer: i32,
+    },
+    Finished,
+}
+
+struct Shared {
+    pause_requested: AtomicBool,
+    state: Mutex<RunState>,
+    changed: Condvar,
+}
+
+/// Control channel for a program running on another thread.
+///
+/// Pause requests are only noticed at loop boundaries, i.e. once per
+/// iteration of a `[...]`. A program that never loops again after the request
+/// runs to completion, in which case `pause` reports `Finished`.
+#[derive(Clone)]
+pub struct RunHandle {
+    shared: Arc<Shared>,
+}
+
+impl RunHandle {
+    pub fn new() -> RunHandle {
+        RunHandle {
+            shared: Arc::new(Shared {
+                pause_requested: AtomicBool::new(false),
+                state: Mutex::new(RunState::Running),
+                changed: Condvar::new(),
+            }),
+        }
+    }
+
+    /// Asks the program to pause and waits until it has parked or finished
+    pub fn pause(&self) -> RunState {
+        self.shared.pause_requested.store(true, Ordering::SeqCst);
+
+        let mut state = self.shared.state.lock().unwrap();
+        while *state == RunState::Running {
+            state = self.shared.changed.wait(state).unwrap();
+        }
+        state.clone()
+    }
+
+    /// Lets a paused program carry on
+    pub fn resume(&self) {
+        let _state = self.shared.state.lock().unwrap();
+        self.shared.pause_requested.store(false, Ordering::SeqCst);
+        self.shared.changed.notify_all();
+    }
+
+    /// Waits for the program to run to completion
+    pub fn wait(&self) {
+        let mut state = self.shared.state.lock().unwrap();
+        while *state != RunState::Finished {
+            state = self.shared.changed.wait(state).unwrap();
+        }
+    }
+
+    /// Called by the interpreter at every loop boundary
+    pub fn poll(&self, tape: &Tape, pointer: i32) {
+        if !self.shared.pause_requested.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let mut state = self.shared.state.lock().unwrap();
+        *state = RunState::Paused {
+            tape: tape.clone(),
+            pointer,
+        };
+        self.shared.changed.notify_all();
+
+        while self.shared.pause_requested.load(Ordering::SeqCst) {
+            state = self.shared.changed.wait(state).unwrap();
+        }
+        *state = RunState::Running;
+    }
+
+    /// Called by the interpreter once the program is done
+    pub fn finish(&self) {
+        let mut state = match self.shared.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        *state = RunState::Finished;
+        self.shared.changed.notify_all();
+    }
+}
+
+impl Default for RunHandle {
+    fn default() -> RunHandle {
+        RunHandle::new()
+    }
+}
+
+/// Runs a program on a new thread that can be controlled through the
+/// returned handle. Joining the thread gives the machine back.
+pub fn spawn(
+    program: Vec<Instruction>,
+    mut machine: Machine,
+    data_pointer: i32,
+) -> (RunHandle, JoinHandle<Machine>) {
+    let handle = RunHandle::new();
+    machine.control = Some(handle.clone());
+    let controller = handle.clone();
+
+    let thread = thread::spawn(move || {
+        // Report completion even if the program panics
+        let _guard = FinishOnDrop(handle.clone());
+
+        let code = compile_owned(&program);
+        code(&mut machine, data_pointer);
+        machine.output.finish();
+        machine
+    });
+
+    (controller, thread)
+}
+
+struct FinishOnDrop(RunHandle);
+
+impl Drop for FinishOnDrop {
+    fn drop(&mut self) {
+        self.0.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use input::{Input, InputMode, RangePolicy};
+    use output::{Output, OutputMode};
+    use std::io::{self, Read};
+    use std::sync::mpsc::{self, Receiver};
+    use std::time::Duration;
+    use {lex, parse, START_POINTER, TAPE_SIZE};
+
+    /// Input that arrives only when the test sends it
+    struct Typed(Receiver<u8>);
+
+    impl Read for Typed {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.recv() {
+                Ok(byte) => {
+                    buf[0] = byte;
+                    Ok(1)
+                }
+                Err(_) => Ok(0),
+            }
+        }
+    }
+
+    #[test]
+    fn pause_inspect_and_resume() {
+        let (typed, typing) = mpsc::channel();
+        let machine = Machine::new(
+            Tape::new(vec![0; TAPE_SIZE]),
+            Input::new(InputMode::Byte, RangePolicy::Error, Box::new(Typed(typing))),
+            Output::new(OutputMode::Char, Box::new(io::sink())),
+        );
+        let program = parse(lex(",[>+<,]".to_string())).unwrap();
+        let (handle, thread) = spawn(program, machine, START_POINTER as i32);
+
+        // The pause is only noticed once a byte gets the program round its
+        // loop, so keep typing until it takes
+        let pauser = handle.clone();
+        let paused = thread::spawn(move || pauser.pause());
+        while !paused.is_finished() {
+            typed.send(5).unwrap();
+            thread::sleep(Duration::from_millis(1));
+        }
+        let state = paused.join().unwrap();
+        match state {
+            RunState::Paused { ref tape, pointer } => {
+                assert_eq!(pointer, START_POINTER as i32);
+                assert_eq!(tape.get(pointer), 5);
+            }
+            _ => panic!("expected a pause, got {:?}", state),
+        }
+
+        handle.resume();
+        typed.send(0).unwrap();
+        handle.wait();
+        let machine = thread.join().unwrap();
+        assert_eq!(machine.tape.get(START_POINTER as i32), 0);
+    }
+}
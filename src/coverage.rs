@@ -0,0 +1,143 @@
+//! Which commands of the source a run got to, counted by a `Debugger` that
+//! was asked to, for finding the parts of a program that never run.
+//!
+//! `annotate` prints the source with how often each line ran in front of
+//! it, `#####` for lines where nothing did, and a `^` under every command
+//! that never ran on a line where others did:
+//!
+//! ```text
+//!        1 | +[>.<-]
+//!        1 | [-]
+//!          |  ^^
+//!    ##### | ,.
+//! ```
+//!
+//! `to_lcov` writes the same counts as an lcov tracefile, for the tools
+//! that show coverage. A line counts as often as its busiest command ran.
+
+use std::fmt::Write;
+
+/// How often every command of a source ran
+#[derive(Debug, Clone, PartialEq)]
+pub struct Coverage {
+    source: Vec<u8>,
+    /// Byte offset in the source of every command, and how many times it ran
+    pub commands: Vec<(usize, u64)>,
+}
+
+impl Coverage {
+    pub fn new(source: &[u8], commands: Vec<(usize, u64)>) -> Coverage {
+        Coverage {
+            source: source.to_vec(),
+            commands,
+        }
+    }
+
+    /// Commands that ran at least once
+    pub fn covered(&self) -> usize {
+        self.commands.iter().filter(|&&(_, hits)| hits > 0).count()
+    }
+
+    /// The line of every command, each with its column and count, in order.
+    /// Columns are counted in characters from 1, as `location_at` does, in
+    /// one pass over the source.
+    fn lines(&self) -> Vec<(usize, Vec<(usize, u64)>)> {
+        let mut lines: Vec<(usize, Vec<(usize, u64)>)> = Vec::new();
+        let (mut line, mut column, mut at) = (1, 1, 0);
+
+        for &(offset, hits) in &self.commands {
+            for c in String::from_utf8_lossy(&self.source[at..offset]).chars() {
+                match c {
+                    '\n' => (line, column) = (line + 1, 1),
+                    _ => column += 1,
+                }
+            }
+            at = offset;
+
+            match lines.last_mut() {
+                Some((last, columns)) if *last == line => columns.push((column, hits)),
+                _ => lines.push((line, vec![(column, hits)])),
+            }
+        }
+        lines
+    }
+
+    /// The source annotated as in the module documentation, with a summary
+    /// at the end
+    pub fn annotate(&self) -> String {
+        let text = String::from_utf8_lossy(&self.source);
+        let lines = self.lines();
+        let mut counted = lines.iter().peekable();
+        let mut out = String::new();
+
+        for (i, text) in text.lines().enumerate() {
+            let text = text.trim_end_matches('\r');
+            let columns = match counted.peek() {
+                Some((line, columns)) if *line == i + 1 => {
+                    counted.next();
+                    columns
+                }
+                _ => {
+                    writeln!(out, "          | {}", text).unwrap();
+                    continue;
+                }
+            };
+
+            let most = columns.iter().map(|&(_, hits)| hits).max().unwrap_or(0);
+            match most {
+                0 => writeln!(out, "    ##### | {}", text).unwrap(),
+                _ => writeln!(out, "{:>9} | {}", most, text).unwrap(),
+            }
+
+            // Marked where a command never ran, keeping tabs so that the
+            // marks line up under them
+            let never: Vec<usize> = columns
+                .iter()
+                .filter(|&&(_, hits)| hits == 0)
+                .map(|&(column, _)| column)
+                .collect();
+            if most > 0 && !never.is_empty() {
+                let last = *never.last().unwrap();
+                let marks: String = text
+                    .chars()
+                    .take(last)
+                    .enumerate()
+                    .map(|(j, c)| match c {
+                        _ if never.contains(&(j + 1)) => '^',
+                        '\t' => '\t',
+                        _ => ' ',
+                    })
+                    .collect();
+                writeln!(out, "          | {}", marks).unwrap();
+            }
+        }
+
+        let total = self.commands.len();
+        writeln!(
+            out,
+            "coverage: {} of {} commands ran ({:.1}%)",
+            self.covered(),
+            total,
+            100.0 * self.covered() as f64 / total.max(1) as f64
+        )
+        .unwrap();
+        out
+    }
+
+    /// An lcov tracefile for the source file at `path`
+    pub fn to_lcov(&self, path: &str) -> String {
+        let lines = self.lines();
+        let mut out = format!("TN:\nSF:{}\n", path);
+
+        let mut hit = 0;
+        for (line, columns) in &lines {
+            let most = columns.iter().map(|&(_, hits)| hits).max().unwrap_or(0);
+            if most > 0 {
+                hit += 1;
+            }
+            writeln!(out, "DA:{},{}", line, most).unwrap();
+        }
+        writeln!(out, "LF:{}\nLH:{}\nend_of_record", lines.len(), hit).unwrap();
+        out
+    }
+}
@@ -0,0 +1,398 @@
+//! Running a program one source command at a time, for a debugger.
+//!
+//! The source is kept as it was written, a flat list of commands with
+//! every bracket knowing where its partner is, so a run can stop after any
+//! command and pick up again from there. `#`, a comment everywhere else,
+//! is a breakpoint here.
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use checkpoint::{fingerprint, Checkpoint};
+use coverage::Coverage;
+use tape::Tape;
+use {catch_stop, decode, Location, Machine, OpCode, ParseError, RunError};
+
+/// One command of the source
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Command {
+    Right,
+    Left,
+    Increment,
+    Decrement,
+    Write,
+    Read,
+    /// Index of the matching `]`
+    Open(usize),
+    /// Index of the matching `[`
+    Close(usize),
+    /// A `#` in the source
+    Break,
+}
+
+/// Why a run stopped
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stop {
+    /// It did what it was asked to, and there is more to run
+    Done,
+    /// The next command has a breakpoint, or the last one was a `#`
+    Breakpoint,
+    /// The program has run to its end
+    Finished,
+}
+
+/// A program that runs as far as it is told to, and no further
+pub struct Debugger {
+    source: Vec<u8>,
+    commands: Vec<Command>,
+    /// Byte offset in the source of every command
+    offsets: Vec<usize>,
+    /// Steps an iteration of the loop costs at every `[`, see `loop_costs`
+    costs: Vec<u64>,
+    /// Steps the commands outside of any loop cost, charged before the first
+    outside_loops: u64,
+    breakpoints: BTreeSet<usize>,
+    /// Index of the next command to run
+    pc: usize,
+    pointer: i32,
+    machine: Machine,
+    /// Commands run so far
+    steps: u64,
+    /// Times every command ran, when counting them
+    hits: Option<Vec<u64>>,
+}
+
+/// Steps an iteration of the loop opened by every `[` costs, as the backends
+/// count them for the parsed loop: one, plus one for every command of its
+/// body but the loops nested in it, which count their own. Zero for
+/// everything but a `[`. Also the steps of the commands outside of any loop.
+fn loop_costs(commands: &[Command]) -> (Vec<u64>, u64) {
+    let mut costs = vec![0; commands.len()];
+    let mut outside_loops = 0;
+    let mut open = Vec::new();
+    for (i, &command) in commands.iter().enumerate() {
+        match command {
+            Command::Open(_) => {
+                costs[i] = 1;
+                open.push(i);
+            }
+            Command::Close(_) => {
+                open.pop();
+            }
+            Command::Break => (),
+            _ => match open.last() {
+                Some(&start) => costs[start] += 1,
+                None => outside_loops += 1,
+            },
+        }
+    }
+    (costs, outside_loops)
+}
+
+/// Why a checkpoint couldn't be restored
+#[derive(Debug, Clone, PartialEq)]
+pub enum RestoreError {
+    /// It was taken of some other program
+    OtherProgram,
+    /// The input the program had read is no longer there
+    Input(String),
+}
+
+impl fmt::Display for RestoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RestoreError::OtherProgram => write!(f, "the checkpoint is of another program"),
+            RestoreError::Input(e) => write!(f, "can't skip the input already read: {}", e),
+        }
+    }
+}
+
+impl Debugger {
+    /// Loads a program's source onto a machine with its data pointer at
+    /// `pointer`. Bracket errors count opcodes as `parse` does, so
+    /// `BfError` can place them.
+    pub fn new(source: &[u8], machine: Machine, pointer: i32) -> Result<Debugger, ParseError> {
+        let mut commands = Vec::new();
+        let mut offsets = Vec::new();
+        // Index of the `[`, and which opcode it was, of each open loop
+        let mut open = Vec::new();
+        let mut opcode = 0;
+
+        for (offset, &symbol) in source.iter().enumerate() {
+            let command = match decode(symbol) {
+                Some(OpCode::IncrementPointer) => Command::Right,
+                Some(OpCode::DecrementPointer) => Command::Left,
+                Some(OpCode::Increment) => Command::Increment,
+                Some(OpCode::Decrement) => Command::Decrement,
+                Some(OpCode::Write) => Command::Write,
+                Some(OpCode::Read) => Command::Read,
+                Some(OpCode::LoopBegin) => {
+                    open.push((commands.len(), opcode));
+                    Command::Open(0)
+                }
+                Some(OpCode::LoopEnd) => {
+                    let (start, _) = open.pop().ok_or(ParseError::UnmatchedEnd(opcode))?;
+                    commands[start] = Command::Open(commands.len());
+                    Command::Close(start)
+                }
+                None if symbol == b'#' => Command::Break,
+                None => continue,
+            };
+            if command != Command::Break {
+                opcode += 1;
+            }
+            commands.push(command);
+            offsets.push(offset);
+        }
+
+        if let Some(&(_, opcode)) = open.first() {
+            return Err(ParseError::UnmatchedBegin(opcode));
+        }
+
+        let (costs, outside_loops) = loop_costs(&commands);
+        Ok(Debugger {
+            source: source.to_vec(),
+            costs,
+            outside_loops,
+            commands,
+            offsets,
+            breakpoints: BTreeSet::new(),
+            pc: 0,
+            pointer,
+            machine,
+            steps: 0,
+            hits: None,
+        })
+    }
+
+    /// Counts how often every command runs, for `coverage`
+    pub fn with_coverage(mut self) -> Debugger {
+        self.hits = Some(vec![0; self.commands.len()]);
+        self
+    }
+
+    /// How often every command has run so far, leaving out the `#`s, if
+    /// counting them
+    pub fn coverage(&self) -> Option<Coverage> {
+        let hits = self.hits.as_ref()?;
+        let commands = self
+            .commands
+            .iter()
+            .zip(&self.offsets)
+            .zip(hits)
+            .filter(|&((&command, _), _)| command != Command::Break)
+            .map(|((_, &offset), &hits)| (offset, hits))
+            .collect();
+        Some(Coverage::new(&self.source, commands))
+    }
+
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    pub fn pointer(&self) -> i32 {
+        self.pointer
+    }
+
+    /// Commands run so far
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.pc >= self.commands.len()
+    }
+
+    /// Where the next command is in the source, None once finished
+    pub fn location(&self) -> Option<Location> {
+        let offset = *self.offsets.get(self.pc)?;
+        Some(location_at(&self.source, offset))
+    }
+
+    /// The next command as it is written, None once finished
+    pub fn command(&self) -> Option<char> {
+        let offset = *self.offsets.get(self.pc)?;
+        Some(self.source[offset] as char)
+    }
+
+    /// Sets a breakpoint on the first command at or after `line` and
+    /// `column`, counted from 1, returning where that is
+    pub fn set_breakpoint(&mut self, line: usize, column: usize) -> Option<Location> {
+        let index = self.offsets.iter().position(|&offset| {
+            let location = location_at(&self.source, offset);
+            (location.line, location.column) >= (line, column)
+        })?;
+        self.breakpoints.insert(index);
+        Some(location_at(&self.source, self.offsets[index]))
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    /// Runs the next command
+    pub fn step(&mut self) -> Result<Stop, RunError> {
+        catch_stop(|| self.execute())
+    }
+
+    /// Runs the next command, and if it enters a loop, the whole loop
+    pub fn step_over(&mut self) -> Result<Stop, RunError> {
+        let after = match self.commands.get(self.pc) {
+            Some(&Command::Open(close)) => close + 1,
+            _ => return self.step(),
+        };
+        catch_stop(|| self.run_until(|debugger| debugger.pc == after))
+    }
+
+    /// Runs until `count` more commands have run, or a breakpoint or the end
+    /// of the program comes first
+    pub fn run_for(&mut self, count: u64) -> Result<Stop, RunError> {
+        let end = self.steps.saturating_add(count);
+        catch_stop(|| self.run_until(|debugger| debugger.steps >= end))
+    }
+
+    /// Runs until a breakpoint or the end of the program
+    pub fn resume(&mut self) -> Result<Stop, RunError> {
+        catch_stop(|| self.run_until(|_| false))
+    }
+
+    /// Runs at least one command, then on until `done` or a breakpoint
+    fn run_until(&mut self, done: impl Fn(&Debugger) -> bool) -> Stop {
+        loop {
+            match self.execute() {
+                Stop::Done if done(self) => return Stop::Done,
+                Stop::Done if self.breakpoints.contains(&self.pc) => return Stop::Breakpoint,
+                Stop::Done => (),
+                stop => return stop,
+            }
+        }
+    }
+
+    fn execute(&mut self) -> Stop {
+        let m = &mut self.machine;
+        let p = self.pointer;
+
+        let command = match self.commands.get(self.pc) {
+            Some(&command) => command,
+            None => return Stop::Finished,
+        };
+        if self.steps == 0 {
+            m.enter(p, self.outside_loops);
+        }
+        if let Some(hits) = &mut self.hits {
+            hits[self.pc] += 1;
+        }
+        self.pc += 1;
+        self.steps += 1;
+
+        match command {
+            Command::Right => self.pointer += 1,
+            Command::Left => self.pointer -= 1,
+            Command::Increment => m.tape.set(p, m.tape.get(p).wrapping_add(1)),
+            Command::Decrement => m.tape.set(p, m.tape.get(p).wrapping_sub(1)),
+            Command::Write => {
+                m.output.write(m.tape.get(p));
+                m.output.flush();
+            }
+            Command::Read => {
+                if let Some(value) = m.read() {
+                    m.tape.set(p, value);
+                }
+            }
+            Command::Open(close) => {
+                if m.tape.get(p) == 0 {
+                    self.pc = close + 1;
+                } else {
+                    m.back_edge(p, self.costs[self.pc - 1]);
+                }
+            }
+            Command::Close(open) => {
+                if m.tape.get(p) != 0 {
+                    m.back_edge(p, self.costs[open]);
+                    self.pc = open + 1;
+                }
+            }
+            Command::Break => return Stop::Breakpoint,
+        }
+
+        if self.is_finished() {
+            Stop::Finished
+        } else {
+            Stop::Done
+        }
+    }
+
+    /// Saves where the run is, see `checkpoint`
+    pub fn checkpoint(&self) -> Checkpoint {
+        let tape = &self.machine.tape;
+        let range = tape.range();
+
+        Checkpoint {
+            program: fingerprint(&self.source),
+            pc: self.pc,
+            pointer: self.pointer,
+            steps: self.steps,
+            input: self.machine.input.consumed(),
+            output: self.machine.output.written(),
+            start: range.start,
+            cells: range.map(|p| tape.get(p)).collect(),
+        }
+    }
+
+    /// Picks up a run where a checkpoint of the same program left it. The
+    /// input has to be the same too, what was read of it already is skipped.
+    pub fn restore(&mut self, checkpoint: &Checkpoint) -> Result<(), RestoreError> {
+        if checkpoint.program != fingerprint(&self.source) || checkpoint.pc > self.commands.len() {
+            return Err(RestoreError::OtherProgram);
+        }
+        let skip = checkpoint
+            .input
+            .saturating_sub(self.machine.input.consumed());
+        self.machine
+            .input
+            .skip(skip)
+            .map_err(|e| RestoreError::Input(e.to_string()))?;
+
+        let policy = self.machine.tape.policy();
+        self.machine.tape = Tape::new(checkpoint.cells.clone())
+            .starting_at(checkpoint.start)
+            .with_policy(policy);
+        self.machine.output.continue_from(checkpoint.output);
+        self.pc = checkpoint.pc;
+        self.pointer = checkpoint.pointer;
+        self.steps = checkpoint.steps;
+        Ok(())
+    }
+
+    /// Terminates the output, see `Output::finish`
+    pub fn finish(mut self) {
+        self.machine.output.finish();
+    }
+}
+
+/// Line and column, counted from 1, of a byte of the source
+pub(crate) fn location_at(source: &[u8], offset: usize) -> Location {
+    let start = source[..offset]
+        .iter()
+        .rposition(|&byte| byte == b'\n')
+        .map_or(0, |i| i + 1);
+    let end = source[offset..]
+        .iter()
+        .position(|&byte| byte == b'\n')
+        .map_or(source.len(), |i| offset + i);
+
+    Location {
+        line: source[..start]
+            .iter()
+            .filter(|&&byte| byte == b'\n')
+            .count()
+            + 1,
+        column: String::from_utf8_lossy(&source[start..offset])
+            .chars()
+            .count()
+            + 1,
+        text: String::from_utf8_lossy(&source[start..end])
+            .trim_end_matches('\r')
+            .to_string(),
+    }
+}
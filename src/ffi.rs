@@ -0,0 +1,336 @@
+//! A C interface to the engine, exported from the crate's cdylib. In C:
+//!
+//! ```c
+//! typedef struct BfProgram BfProgram;
+//!
+//! typedef enum BfStatus {
+//!     BF_OK = 0,
+//!     BF_NULL_POINTER = 1,
+//!     BF_PARSE = 2,
+//!     BF_STEP_LIMIT = 3,
+//!     BF_OUT_OF_BOUNDS = 4,
+//!     BF_IO = 5,
+//!     BF_PANIC = 6,
+//! } BfStatus;
+//!
+//! /* A byte, or anything negative at the end of the input */
+//! typedef int (*bf_read_fn)(void *data);
+//! /* 0 once all of `bytes` are taken, anything else fails the run */
+//! typedef int (*bf_write_fn)(void *data, const uint8_t *bytes, size_t len);
+//!
+//! BfStatus bf_compile(const uint8_t *source, size_t len, BfProgram **program);
+//! BfStatus bf_run(const BfProgram *program, uint64_t step_limit,
+//!                 bf_read_fn read, bf_write_fn write, void *data);
+//! void bf_program_free(BfProgram *program);
+//! const char *bf_status_message(int status);
+//! ```
+//!
+//! `bf_compile` and `bf_run` return a `BfStatus`, and `bf_status_message`
+//! takes one as an `int`. The callbacks are only ever called on the thread that
+//! called `bf_run`, and `data` is passed to them as is.
+
+use std::io;
+use std::os::raw::{c_char, c_int, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+use input::BfInput;
+use output::BfOutput;
+use {Interpreter, Program, RunError};
+
+/// What `bf_compile` and `bf_run` return
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BfStatus {
+    Ok = 0,
+    /// A pointer that may not be null was
+    NullPointer = 1,
+    /// An unmatched `[` or `]`
+    Parse = 2,
+    StepLimit = 3,
+    /// The data pointer left the tape
+    OutOfBounds = 4,
+    /// A callback failed
+    Io = 5,
+    /// The engine itself panicked
+    Panic = 6,
+}
+
+impl BfStatus {
+    fn message(self) -> &'static [u8] {
+        match self {
+            BfStatus::Ok => b"ok\0",
+            BfStatus::NullPointer => b"a required pointer is null\0",
+            BfStatus::Parse => b"the program has unmatched or too deeply nested brackets\0",
+            BfStatus::StepLimit => b"the program ran out of steps\0",
+            BfStatus::OutOfBounds => b"the data pointer left the tape\0",
+            BfStatus::Io => b"a read or write callback failed\0",
+            BfStatus::Panic => b"the engine panicked\0",
+        }
+    }
+}
+
+impl From<RunError> for BfStatus {
+    fn from(e: RunError) -> BfStatus {
+        match e {
+            RunError::Parse(_) => BfStatus::Parse,
+            RunError::StepLimit(_) | RunError::TimeLimit(_) => BfStatus::StepLimit,
+            RunError::OutOfBounds(_) => BfStatus::OutOfBounds,
+            RunError::Input(_) | RunError::Output(_) => BfStatus::Io,
+            // 8-bit cells that wrap never overflow
+            RunError::CellOverflow(_) => BfStatus::Panic,
+        }
+    }
+}
+
+/// A parsed program, owned by the caller until `bf_program_free`
+pub struct BfProgram(Program);
+
+pub type BfReadFn = extern "C" fn(data: *mut c_void) -> c_int;
+pub type BfWriteFn = extern "C" fn(data: *mut c_void, bytes: *const u8, len: usize) -> c_int;
+
+/// The caller's end of the input or the output
+struct Callback<F> {
+    f: F,
+    data: *mut c_void,
+}
+
+// The interpreter never leaves the thread it runs on
+unsafe impl<F> Send for Callback<F> {}
+
+fn failed() -> io::Error {
+    io::Error::other("the callback failed")
+}
+
+impl BfInput for Callback<BfReadFn> {
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        match (self.f)(self.data) {
+            byte if byte < 0 => Ok(None),
+            byte if byte <= 0xff => Ok(Some(byte as u8)),
+            _ => Err(failed()),
+        }
+    }
+}
+
+impl BfOutput for Callback<BfWriteFn> {
+    fn write_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        match (self.f)(self.data, bytes.as_ptr(), bytes.len()) {
+            0 => Ok(()),
+            _ => Err(failed()),
+        }
+    }
+
+    fn flush_output(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Parses `len` bytes of `source`, storing the program in `*program` on
+/// success
+///
+/// # Safety
+///
+/// `source` must point to `len` readable bytes and `program` to a writable
+/// pointer.
+#[no_mangle]
+pub unsafe extern "C" fn bf_compile(
+    source: *const u8,
+    len: usize,
+    program: *mut *mut BfProgram,
+) -> BfStatus {
+    if source.is_null() || program.is_null() {
+        return BfStatus::NullPointer;
+    }
+    *program = ptr::null_mut();
+
+    let source = String::from_utf8_lossy(slice::from_raw_parts(source, len));
+    match Program::parse(&source) {
+        Ok(parsed) => {
+            *program = Box::into_raw(Box::new(BfProgram(parsed)));
+            BfStatus::Ok
+        }
+        Err(_) => BfStatus::Parse,
+    }
+}
+
+/// Runs a program on a blank tape, reading `,` from `read` and writing
+/// every `.` to `write`. A `step_limit` of 0 means none.
+///
+/// # Safety
+///
+/// `program` must come from `bf_compile` and not have been freed, and the
+/// callbacks must be safe to call with `data`.
+#[no_mangle]
+pub unsafe extern "C" fn bf_run(
+    program: *const BfProgram,
+    step_limit: u64,
+    read: Option<BfReadFn>,
+    write: Option<BfWriteFn>,
+    data: *mut c_void,
+) -> BfStatus {
+    let (program, read, write) = match (program.as_ref(), read, write) {
+        (Some(program), Some(read), Some(write)) => (program, read, write),
+        _ => return BfStatus::NullPointer,
+    };
+
+    let input = Callback { f: read, data };
+    let output = Callback { f: write, data };
+
+    // A failed callback comes back as an error, but a bug in the engine
+    // panics, and no panic may unwind into C
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut interpreter = Interpreter::new(input, output);
+        if step_limit > 0 {
+            interpreter = interpreter.with_step_limit(step_limit);
+        }
+        let result = interpreter.run(&program.0);
+        let finished = interpreter.finish();
+        result.and(finished)
+    }));
+
+    match result {
+        Ok(Ok(())) => BfStatus::Ok,
+        Ok(Err(e)) => e.into(),
+        Err(_) => BfStatus::Panic,
+    }
+}
+
+/// Frees a program from `bf_compile`, doing nothing for null
+///
+/// # Safety
+///
+/// `program` must be null or come from `bf_compile` and not have been freed
+/// already.
+#[no_mangle]
+pub unsafe extern "C" fn bf_program_free(program: *mut BfProgram) {
+    if !program.is_null() {
+        drop(Box::from_raw(program));
+    }
+}
+
+/// What a status code means, as a static string that must not be freed
+#[no_mangle]
+pub extern "C" fn bf_status_message(status: c_int) -> *const c_char {
+    let status = match status {
+        0 => BfStatus::Ok,
+        1 => BfStatus::NullPointer,
+        2 => BfStatus::Parse,
+        3 => BfStatus::StepLimit,
+        4 => BfStatus::OutOfBounds,
+        5 => BfStatus::Io,
+        6 => BfStatus::Panic,
+        _ => return b"unknown status\0".as_ptr() as *const c_char,
+    };
+    status.message().as_ptr() as *const c_char
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CStr;
+
+    use super::*;
+
+    /// What the callbacks of a run read from and write to
+    #[derive(Default)]
+    struct Io {
+        input: Vec<u8>,
+        read: usize,
+        output: Vec<u8>,
+        refuse_writes: bool,
+    }
+
+    extern "C" fn read(data: *mut c_void) -> c_int {
+        let io = unsafe { &mut *(data as *mut Io) };
+        let byte = io.input.get(io.read).map_or(-1, |&byte| c_int::from(byte));
+        io.read += 1;
+        byte
+    }
+
+    extern "C" fn write(data: *mut c_void, bytes: *const u8, len: usize) -> c_int {
+        let io = unsafe { &mut *(data as *mut Io) };
+        if io.refuse_writes {
+            return -1;
+        }
+        io.output
+            .extend_from_slice(unsafe { slice::from_raw_parts(bytes, len) });
+        0
+    }
+
+    fn compile(source: &str) -> Result<*mut BfProgram, BfStatus> {
+        let mut program = ptr::null_mut();
+        match unsafe { bf_compile(source.as_ptr(), source.len(), &mut program) } {
+            BfStatus::Ok => Ok(program),
+            status => Err(status),
+        }
+    }
+
+    /// Runs `source` on `io`, returning the status of the run
+    fn run(source: &str, step_limit: u64, io: &mut Io) -> BfStatus {
+        let program = compile(source).unwrap();
+        let data = io as *mut Io as *mut c_void;
+        let status = unsafe { bf_run(program, step_limit, Some(read), Some(write), data) };
+        unsafe { bf_program_free(program) };
+        status
+    }
+
+    #[test]
+    fn a_program_reads_and_writes_through_the_callbacks() {
+        // End of input leaves the cell alone, so a zero ends the loop
+        let mut io = Io {
+            input: b"hi\0".to_vec(),
+            ..Io::default()
+        };
+        assert_eq!(run(",[.,]", 0, &mut io), BfStatus::Ok);
+        assert_eq!(io.output, b"hi");
+    }
+
+    #[test]
+    fn null_pointers_are_refused() {
+        let mut program = ptr::null_mut();
+        let status = unsafe { bf_compile(ptr::null(), 0, &mut program) };
+        assert_eq!(status, BfStatus::NullPointer);
+
+        let program = compile("+").unwrap();
+        let status = unsafe { bf_run(program, 0, None, Some(write), ptr::null_mut()) };
+        assert_eq!(status, BfStatus::NullPointer);
+        let status = unsafe { bf_run(ptr::null(), 0, Some(read), Some(write), ptr::null_mut()) };
+        assert_eq!(status, BfStatus::NullPointer);
+        unsafe { bf_program_free(program) };
+    }
+
+    #[test]
+    fn unmatched_brackets_dont_compile() {
+        assert_eq!(compile("[").unwrap_err(), BfStatus::Parse);
+        assert_eq!(compile("]").unwrap_err(), BfStatus::Parse);
+    }
+
+    #[test]
+    fn the_step_limit_stops_a_run() {
+        assert_eq!(run("+[]", 1000, &mut Io::default()), BfStatus::StepLimit);
+    }
+
+    #[test]
+    fn a_failed_write_is_an_io_error() {
+        let mut io = Io {
+            refuse_writes: true,
+            ..Io::default()
+        };
+        assert_eq!(run("+[.]", 0, &mut io), BfStatus::Io);
+        // Finishing a program that wrote nothing doesn't write
+        assert_eq!(run("+", 0, &mut io), BfStatus::Ok);
+    }
+
+    #[test]
+    fn every_status_has_a_message() {
+        for status in 0..7 {
+            let message = unsafe { CStr::from_ptr(bf_status_message(status)) };
+            assert_ne!(message.to_str().unwrap(), "unknown status");
+        }
+        let message = unsafe { CStr::from_ptr(bf_status_message(7)) };
+        assert_eq!(message.to_str().unwrap(), "unknown status");
+    }
+}
@@ -0,0 +1,227 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use Instruction;
+
+/// Upper bound on the work spent evaluating a program at compile time
+const STEP_BUDGET: usize = 1 << 20;
+
+/// Partially evaluates the input-free prefix of a program that starts on a
+/// blank tape. The prefix (loops included, as long as they finish within the
+/// step budget) is replaced by a single `WriteBytes` with everything it
+/// printed, followed by a `Snapshot` of the tape it left behind.
+///
+/// `bounds` are the cell offsets from the starting pointer that exist on the
+/// tape; anything touching other cells is left for the backend to report.
+/// Steps taken here are none of the program's, so a run with a step or time
+/// limit isn't folded.
+pub fn fold_constant_prefix(program: Vec<Instruction>, bounds: Range<i32>) -> Vec<Instruction> {
+    let mut state = Evaluator {
+        bounds,
+        tape: BTreeMap::new(),
+        pointer: 0,
+        output: Vec::new(),
+        steps: 0,
+        undo: Vec::new(),
+    };
+    let mut folded = 0;
+
+    for instr in &program {
+        // Stops at the first `,` that would actually run, a loop that is
+        // skipped can read all it likes. What the instruction did before it
+        // got stuck is undone, the instructions before it stay folded.
+        let (pointer, written) = (state.pointer, state.output.len());
+        state.undo.clear();
+        if !state.eval(instr) {
+            state.roll_back(pointer, written);
+            break;
+        }
+
+        folded += 1;
+    }
+
+    if folded == 0 {
+        return program;
+    }
+
+    let rest: Vec<Instruction> = program.into_iter().skip(folded).collect();
+    let mut result = Vec::new();
+
+    if !state.output.is_empty() {
+        result.push(Instruction::WriteBytes(state.output));
+    }
+
+    let cells: Vec<(i32, u8)> = state
+        .tape
+        .into_iter()
+        .filter(|&(_, value)| value != 0)
+        .collect();
+
+    if !cells.is_empty() || state.pointer != 0 {
+        result.push(Instruction::Snapshot {
+            cells,
+            pointer: state.pointer,
+        });
+    }
+
+    result.extend(rest);
+    result
+}
+
+/// Drops the `Snapshot` of a program that was folded all the way to its
+/// end. Nothing can look at the tape of a compiled program once it has
+/// finished, so one that never reads is left with nothing but printing
+/// what it prints.
+pub fn drop_final_snapshot(mut program: Vec<Instruction>) -> Vec<Instruction> {
+    if let Some(Instruction::Snapshot { .. }) = program.last() {
+        program.pop();
+    }
+    program
+}
+
+/// Interpreter over a sparse tape that gives up once the step budget is spent
+struct Evaluator {
+    bounds: Range<i32>,
+    tape: BTreeMap<i32, u8>,
+    pointer: i32,
+    output: Vec<u8>,
+    steps: usize,
+    /// Every cell changed since it was last cleared, with what it held before
+    undo: Vec<(i32, u8)>,
+}
+
+impl Evaluator {
+    /// The current cell, for changing it
+    fn cell(&mut self) -> Option<&mut u8> {
+        self.cell_at(self.pointer)
+    }
+
+    fn cell_at(&mut self, index: i32) -> Option<&mut u8> {
+        if self.bounds.contains(&index) {
+            let cell = self.tape.entry(index).or_insert(0);
+            self.undo.push((index, *cell));
+            Some(cell)
+        } else {
+            None
+        }
+    }
+
+    /// The current cell, for looking at it
+    fn value(&self) -> Option<u8> {
+        match self.bounds.contains(&self.pointer) {
+            true => Some(self.tape.get(&self.pointer).cloned().unwrap_or(0)),
+            false => None,
+        }
+    }
+
+    /// Puts back the tape as `undo` has it, the pointer and the output
+    fn roll_back(&mut self, pointer: i32, written: usize) {
+        for (index, value) in self.undo.drain(..).rev() {
+            self.tape.insert(index, value);
+        }
+        self.pointer = pointer;
+        self.output.truncate(written);
+    }
+
+    /// Returns false if the instruction could not be evaluated, because it
+    /// ran out of budget or left the tape
+    fn eval(&mut self, instr: &Instruction) -> bool {
+        self.steps += 1;
+        if self.steps > STEP_BUDGET {
+            return false;
+        }
+
+        match instr {
+            Instruction::IncrementPointer => self.pointer += 1,
+            Instruction::DecrementPointer => self.pointer -= 1,
+            Instruction::Increment => match self.cell() {
+                Some(cell) => *cell = cell.wrapping_add(1),
+                None => return false,
+            },
+            Instruction::Decrement => match self.cell() {
+                Some(cell) => *cell = cell.wrapping_sub(1),
+                None => return false,
+            },
+            Instruction::Write => match self.value() {
+                Some(value) => self.output.push(value),
+                None => return false,
+            },
+            Instruction::Read => return false,
+            Instruction::Loop(nested_instructions) => loop {
+                match self.value() {
+                    Some(0) => break,
+                    Some(_) => (),
+                    None => return false,
+                }
+                // Every iteration costs a step, even of an empty loop like `[]`
+                self.steps += 1;
+                if self.steps > STEP_BUDGET {
+                    return false;
+                }
+                for nested in nested_instructions {
+                    if !self.eval(nested) {
+                        return false;
+                    }
+                }
+            },
+            Instruction::WriteBytes(bytes) => self.output.extend(bytes),
+            Instruction::Snapshot { cells, pointer } => {
+                for &(offset, value) in cells {
+                    match self.cell_at(self.pointer + offset) {
+                        Some(cell) => *cell = value,
+                        None => return false,
+                    }
+                }
+                self.pointer += pointer;
+            }
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {lex, parse, tape_bounds};
+
+    fn folded(source: &str) -> Vec<Instruction> {
+        let program = parse(lex(source.to_string())).unwrap();
+        fold_constant_prefix(program, tape_bounds())
+    }
+
+    #[test]
+    fn hello_world_is_one_write() {
+        let program = folded(
+            "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.\
+             +++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.",
+        );
+        assert_eq!(
+            drop_final_snapshot(program),
+            [Instruction::WriteBytes(b"Hello World!\n".to_vec())]
+        );
+    }
+
+    #[test]
+    fn a_loop_that_reads_is_left_as_it_was() {
+        let program = folded("++.[>+,<-]");
+        assert_eq!(program[0], Instruction::WriteBytes(vec![2]));
+        assert_eq!(
+            program[1],
+            Instruction::Snapshot {
+                cells: vec![(0, 2)],
+                pointer: 0,
+            }
+        );
+        assert!(matches!(program[2], Instruction::Loop(_)));
+    }
+
+    #[test]
+    fn a_leading_comment_loop_is_dropped() {
+        let program = folded("[This is a comment, with a . and a +]++.");
+        assert_eq!(
+            drop_final_snapshot(program),
+            [Instruction::WriteBytes(vec![2])]
+        );
+    }
+}
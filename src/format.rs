@@ -0,0 +1,117 @@
+use std::slice;
+
+use Instruction;
+
+/// How `bf fmt` lays a program out
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Style {
+    /// Every command on one line
+    Minify,
+    /// A line for each `[` and `]`, with loop bodies indented
+    Pretty,
+    /// The program's own lines, without the comments
+    Strip,
+}
+
+/// Spaces a loop body is indented by
+const INDENT: usize = 2;
+
+/// Prints a parsed program back out as Brainfuck, with nothing but its
+/// eight commands. Panics on `WriteBytes` and `Snapshot`, which only the
+/// optimizer makes and which have no plain form.
+pub fn minify(program: &[Instruction]) -> String {
+    let mut out = String::new();
+    write(program, &mut out);
+    out
+}
+
+/// Prints a parsed program with every loop's brackets on lines of their
+/// own and its body indented inside them
+pub fn pretty(program: &[Instruction]) -> String {
+    let mut lines = Vec::new();
+    indent(program, 0, &mut lines);
+    lines.join("\n")
+}
+
+/// The commands of `source`, its lines kept as they were but those with no
+/// commands on them left out
+pub fn strip(source: &[u8]) -> String {
+    let commands = |line: &[u8]| -> String {
+        line.iter()
+            .filter(|byte| b"><+-.,[]".contains(byte))
+            .map(|&byte| byte as char)
+            .collect()
+    };
+    let lines: Vec<String> = source
+        .split(|&byte| byte == b'\n')
+        .map(commands)
+        .filter(|line| !line.is_empty())
+        .collect();
+    lines.join("\n")
+}
+
+/// Breaks every line of `text` longer than `width` columns, the pieces
+/// keeping the line's indentation
+pub fn wrap(text: &str, width: usize) -> String {
+    let mut lines = Vec::new();
+
+    for line in text.split('\n') {
+        let commands = line.trim_start();
+        let indent = &line[..line.len() - commands.len()];
+        // However deep the line is, it gets a command or more per piece
+        let room = width.saturating_sub(indent.len()).max(1);
+        if commands.is_empty() {
+            lines.push(line.to_string());
+        }
+        for piece in commands.as_bytes().chunks(room) {
+            lines.push(format!("{}{}", indent, String::from_utf8_lossy(piece)));
+        }
+    }
+
+    lines.join("\n")
+}
+
+fn write(program: &[Instruction], out: &mut String) {
+    for insn in program {
+        match insn {
+            Instruction::IncrementPointer => out.push('>'),
+            Instruction::DecrementPointer => out.push('<'),
+            Instruction::Increment => out.push('+'),
+            Instruction::Decrement => out.push('-'),
+            Instruction::Write => out.push('.'),
+            Instruction::Read => out.push(','),
+            Instruction::Loop(body) => {
+                out.push('[');
+                write(body, out);
+                out.push(']');
+            }
+            Instruction::WriteBytes(_) | Instruction::Snapshot { .. } => {
+                panic!("optimized programs can't be printed as Brainfuck")
+            }
+        }
+    }
+}
+
+fn indent(program: &[Instruction], depth: usize, lines: &mut Vec<String>) {
+    let margin = " ".repeat(depth * INDENT);
+    let mut run = String::new();
+
+    for insn in program {
+        match insn {
+            Instruction::Loop(body) => {
+                if !run.is_empty() {
+                    lines.push(format!("{}{}", margin, run));
+                    run.clear();
+                }
+                lines.push(format!("{}[", margin));
+                indent(body, depth + 1, lines);
+                lines.push(format!("{}]", margin));
+            }
+            _ => write(slice::from_ref(insn), &mut run),
+        }
+    }
+
+    if !run.is_empty() {
+        lines.push(format!("{}{}", margin, run));
+    }
+}
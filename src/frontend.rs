@@ -0,0 +1,142 @@
+//! Dialects that are Brainfuck with its eight commands spelled some other
+//! way, like Ook! and Blub. A `TokenMap` translates them to Brainfuck
+//! source, which everything else then takes as it would any other.
+//!
+//! A token is one or more words, and where it has more than one any run of
+//! whitespace between them will do, so `Ook. Ook?` is found across a line
+//! break too. Whatever isn't a token is a comment, as in Brainfuck. Line
+//! breaks are kept, so errors are reported on the lines they are on.
+//!
+//! A token map file has a line for each command, the command and then its
+//! token, like `> Ook. Ook?`. Blank lines and lines starting with `#` are
+//! skipped.
+
+use std::fs;
+
+/// The commands, in the order `TokenMap` keeps their tokens
+const COMMANDS: &[u8; 8] = b"><+-.,[]";
+
+/// The token of each command, as words
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenMap {
+    tokens: Vec<Vec<String>>,
+}
+
+impl TokenMap {
+    /// Ook!, whose tokens are pairs of `Ook.`, `Ook?` and `Ook!`
+    pub fn ook() -> TokenMap {
+        TokenMap::pairs("Ook")
+    }
+
+    /// Blub, which is Ook! with `Blub` for `Ook`
+    pub fn blub() -> TokenMap {
+        TokenMap::pairs("Blub")
+    }
+
+    fn pairs(word: &str) -> TokenMap {
+        let pairs = [".?", "?.", "..", "!!", "!.", ".!", "!?", "?!"];
+        TokenMap {
+            tokens: pairs
+                .iter()
+                .map(|pair| pair.chars().map(|c| format!("{}{}", word, c)).collect())
+                .collect(),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<TokenMap, String> {
+        let mut tokens = vec![Vec::new(); COMMANDS.len()];
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut words = line.split_whitespace();
+            let command = words.next().unwrap_or_default();
+            let index = match command.as_bytes() {
+                [symbol] => COMMANDS.iter().position(|c| c == symbol),
+                _ => None,
+            };
+            let index = index
+                .ok_or_else(|| format!("line {}: '{}' isn't a command", number + 1, command))?;
+
+            let token: Vec<String> = words.map(str::to_string).collect();
+            if token.is_empty() {
+                return Err(format!("line {}: '{}' has no token", number + 1, command));
+            }
+            if !tokens[index].is_empty() {
+                return Err(format!("line {}: '{}' is given twice", number + 1, command));
+            }
+            tokens[index] = token;
+        }
+
+        if let Some(index) = tokens.iter().position(Vec::is_empty) {
+            return Err(format!("no token for '{}'", COMMANDS[index] as char));
+        }
+        Ok(TokenMap { tokens })
+    }
+
+    pub fn load(path: &str) -> Result<TokenMap, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        TokenMap::parse(&text).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    /// The Brainfuck a program in this dialect amounts to
+    pub fn translate(&self, source: &[u8]) -> Vec<u8> {
+        let mut result = Vec::new();
+        let mut rest = source;
+
+        while let Some(&byte) = rest.first() {
+            // The longest token wins, as some could start others
+            let found = self
+                .tokens
+                .iter()
+                .zip(COMMANDS)
+                .filter_map(|(token, &command)| Some((matches(token, rest)?, command)))
+                .max_by_key(|&(len, _)| len);
+
+            match found {
+                Some((len, command)) => {
+                    result.push(command);
+                    let lines = rest[..len].iter().filter(|&&byte| byte == b'\n');
+                    result.extend(lines);
+                    rest = &rest[len..];
+                }
+                None => {
+                    if byte == b'\n' {
+                        result.push(b'\n');
+                    }
+                    rest = &rest[1..];
+                }
+            }
+        }
+
+        result
+    }
+}
+
+/// How many bytes at the start of `source` the token takes up, if it is
+/// there
+fn matches(token: &[String], source: &[u8]) -> Option<usize> {
+    let mut len = 0;
+
+    for (i, word) in token.iter().enumerate() {
+        if i > 0 {
+            let space = source[len..]
+                .iter()
+                .take_while(|byte| byte.is_ascii_whitespace())
+                .count();
+            if space == 0 {
+                return None;
+            }
+            len += space;
+        }
+        if !source[len..].starts_with(word.as_bytes()) {
+            return None;
+        }
+        len += word.len();
+    }
+
+    Some(len)
+}
@@ -0,0 +1,103 @@
+//! Writing Brainfuck that prints a given text, as short as a few simple
+//! tricks make it.
+//!
+//! A loop first multiplies a row of cells up to around the values the text
+//! needs, one cell per multiple of the loop's count that the text comes
+//! close to, then each byte is printed from whichever cell is cheapest to
+//! get to and adjust. Every count from 2 to `MAX_FACTOR`, and no loop at
+//! all, is tried, and the shortest program wins. It is built as `BigInsn`s
+//! and written out by `brainfuck::emit`.
+
+use brainfuck;
+use input::EofBehavior;
+use BigInsn;
+
+/// Largest loop count tried
+const MAX_FACTOR: u8 = 24;
+
+/// A program that prints `text` and stops, on a blank tape
+pub fn text(text: &[u8]) -> String {
+    let mut best = emit(&naive(text));
+    for factor in 2..=MAX_FACTOR {
+        let candidate = emit(&multiplied(text, factor));
+        if candidate.len() < best.len() {
+            best = candidate;
+        }
+    }
+    best
+}
+
+fn emit(program: &[BigInsn]) -> String {
+    brainfuck::emit(program, &[], 0, EofBehavior::Unchanged)
+}
+
+/// Steps for getting a cell from one value to another
+fn adjust_cost(from: u8, to: u8) -> usize {
+    let up = usize::from(to.wrapping_sub(from));
+    up.min(256 - up)
+}
+
+/// Every byte from the one cell
+fn naive(text: &[u8]) -> Vec<BigInsn> {
+    prints(text, vec![0], 0)
+}
+
+/// A loop of `factor` iterations first sets up a cell for every multiple of
+/// `factor` the text rounds to, in the order the text first needs them
+fn multiplied(text: &[u8], factor: u8) -> Vec<BigInsn> {
+    let mut multiples = Vec::new();
+    for &byte in text {
+        let multiple = (u16::from(byte) + u16::from(factor) / 2) / u16::from(factor);
+        let multiple = multiple as u8;
+        if multiple > 0 && !multiples.contains(&multiple) {
+            multiples.push(multiple);
+        }
+    }
+    if multiples.is_empty() {
+        return naive(text);
+    }
+
+    // The counter is left at zero, and is a cell to print from like the rest
+    let mut body = Vec::new();
+    for &multiple in &multiples {
+        body.push(BigInsn::Move(1));
+        body.push(BigInsn::Adj(i32::from(multiple)));
+    }
+    body.push(BigInsn::Move(-(multiples.len() as i32)));
+    body.push(BigInsn::Adj(-1));
+
+    let mut program = vec![BigInsn::Adj(i32::from(factor)), BigInsn::Loop(body)];
+    let mut cells = vec![0];
+    cells.extend(
+        multiples
+            .iter()
+            .map(|&multiple| multiple.wrapping_mul(factor)),
+    );
+    program.extend(prints(text, cells, 0));
+    program
+}
+
+/// Prints every byte from the cell that costs the fewest commands to move
+/// to and adjust, starting on cell `p` of `cells`
+fn prints(text: &[u8], mut cells: Vec<u8>, mut p: usize) -> Vec<BigInsn> {
+    let mut program = Vec::new();
+
+    for &byte in text {
+        let cost =
+            |q: usize| (q as i32 - p as i32).unsigned_abs() as usize + adjust_cost(cells[q], byte);
+        let q = (0..cells.len()).min_by_key(|&q| cost(q)).unwrap();
+
+        if q != p {
+            program.push(BigInsn::Move(q as i32 - p as i32));
+        }
+        let adjustment = byte.wrapping_sub(cells[q]);
+        if adjustment != 0 {
+            program.push(BigInsn::Adj(i32::from(adjustment)));
+        }
+        program.push(BigInsn::Write);
+        cells[q] = byte;
+        p = q;
+    }
+
+    program
+}
@@ -0,0 +1,299 @@
+use std::error;
+use std::fmt;
+use std::io::{self, IsTerminal, Read, Write};
+use std::panic;
+
+use cell::Cell;
+
+/// How `,` interprets the input stream
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputMode {
+    /// Every byte of input is one value
+    Byte,
+    /// Whitespace separated decimal numbers, one per `,`
+    Numeric,
+}
+
+/// What to do with a numeric input value that doesn't fit in a cell
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RangePolicy {
+    /// Keep the low bits, so 300 becomes 44 in an 8-bit cell
+    Truncate,
+    /// Stop the program, by panicking with `InputError::OutOfRange`
+    Error,
+    /// Clamp to the nearest representable value, so 300 becomes 255 in an
+    /// 8-bit cell
+    Saturate,
+}
+
+/// What `,` does once the input is exhausted
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EofBehavior {
+    /// Leave the cell as it was
+    Unchanged,
+    /// Store 0
+    Zero,
+    /// Store -1, which is 255 in an 8-bit cell
+    Max,
+}
+
+/// Panic payload of a `,` that couldn't come up with a value
+#[derive(Debug, Clone, PartialEq)]
+pub enum InputError {
+    /// Reading failed, with the error it failed with
+    Read(String),
+    /// A word of numeric input that isn't a number
+    Invalid(String),
+    /// A numeric input value that doesn't fit in a cell, under
+    /// `RangePolicy::Error`
+    OutOfRange(String),
+}
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InputError::Read(e) => write!(f, "failed to read input: {}", e),
+            InputError::Invalid(token) => write!(f, "invalid numeric input '{}'", token),
+            InputError::OutOfRange(token) => {
+                write!(f, "input value {} does not fit in a cell", token)
+            }
+        }
+    }
+}
+
+impl error::Error for InputError {}
+
+/// Where the bytes of an `Input` come from. Everything that is `Read` is
+/// one, and embedders can implement it for whatever else feeds a program,
+/// like a channel or a text box.
+pub trait BfInput {
+    /// The next byte, None at the end of the input
+    fn read_byte(&mut self) -> io::Result<Option<u8>>;
+}
+
+impl<R: Read> BfInput for R {
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0];
+        loop {
+            match self.read(&mut byte) {
+                Ok(0) => return Ok(None),
+                Ok(_) => return Ok(Some(byte[0])),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Source of the bytes a program reads
+pub struct Input {
+    mode: InputMode,
+    policy: RangePolicy,
+    eof: EofBehavior,
+    source: Box<dyn BfInput + Send>,
+    /// Where to tell the user we are waiting on them, before the first read
+    hint: Option<Box<dyn Write + Send>>,
+    /// Bytes taken from the source so far
+    consumed: u64,
+}
+
+impl Input {
+    pub fn new(mode: InputMode, policy: RangePolicy, source: Box<dyn BfInput + Send>) -> Input {
+        Input {
+            mode,
+            policy,
+            eof: EofBehavior::Unchanged,
+            source,
+            hint: None,
+            consumed: 0,
+        }
+    }
+
+    pub fn on_eof(mut self, eof: EofBehavior) -> Input {
+        self.eof = eof;
+        self
+    }
+
+    /// Reads standard input, with a hint on stderr if that turns out to be
+    /// an interactive terminal, where the program would otherwise appear to
+    /// hang waiting for the first keypress
+    pub fn stdin(mode: InputMode, policy: RangePolicy) -> Input {
+        let input = Input::new(mode, policy, Box::new(io::stdin()));
+        if io::stdin().is_terminal() {
+            input.hint_to(Box::new(io::stderr()))
+        } else {
+            input
+        }
+    }
+
+    /// Writes the hint `stdin` gives a terminal to `hint` instead
+    fn hint_to(mut self, hint: Box<dyn Write + Send>) -> Input {
+        self.hint = Some(hint);
+        self
+    }
+
+    /// Bytes taken from the source so far, however many values they made
+    pub fn consumed(&self) -> u64 {
+        self.consumed
+    }
+
+    /// Throws away the next `count` bytes, as if they had been read
+    pub fn skip(&mut self, count: u64) -> io::Result<()> {
+        for _ in 0..count {
+            if self.next_byte()?.is_none() {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "the input is shorter than it was",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// The value `,` stores, or None if it should leave the cell alone.
+    /// Input that can't be read, or numeric input that isn't a number or
+    /// doesn't fit, panics with an `InputError`.
+    pub fn read(&mut self) -> Option<u8> {
+        self.read_cell()
+    }
+
+    /// `read` for cells of any width
+    pub fn read_cell<C: Cell>(&mut self) -> Option<C> {
+        if let Some(mut hint) = self.hint.take() {
+            // Only a hint, so failing to give it is no reason to stop
+            let _ = writeln!(
+                hint,
+                "bf: waiting for input on stdin; type characters or pipe a file"
+            );
+        }
+
+        let value = match self.mode {
+            InputMode::Byte => match self.next_byte() {
+                Ok(byte) => byte.map(|byte| C::truncate(u64::from(byte))),
+                Err(e) => panic::panic_any(InputError::Read(e.to_string())),
+            },
+            InputMode::Numeric => self.next_token().map(|token| match parse_number(&token) {
+                Some(value) => self.fit(value, &token),
+                None => panic::panic_any(InputError::Invalid(token)),
+            }),
+        };
+
+        match (value, self.eof) {
+            (Some(value), _) => Some(value),
+            (None, EofBehavior::Unchanged) => None,
+            (None, EofBehavior::Zero) => Some(C::default()),
+            (None, EofBehavior::Max) => Some(C::truncate(C::MAX)),
+        }
+    }
+
+    fn next_byte(&mut self) -> io::Result<Option<u8>> {
+        let byte = self.source.read_byte()?;
+        if byte.is_some() {
+            self.consumed += 1;
+        }
+        Ok(byte)
+    }
+
+    /// Reads the next whitespace separated word, None at end of input
+    fn next_token(&mut self) -> Option<String> {
+        let mut token = String::new();
+
+        loop {
+            let byte = match self.next_byte() {
+                Ok(Some(byte)) => byte,
+                Ok(None) => {
+                    if token.is_empty() {
+                        return None;
+                    }
+                    break;
+                }
+                Err(e) => panic::panic_any(InputError::Read(e.to_string())),
+            };
+
+            if byte.is_ascii_whitespace() {
+                if token.is_empty() {
+                    continue;
+                }
+                break;
+            }
+            token.push(byte as char);
+        }
+
+        Some(token)
+    }
+
+    fn fit<C: Cell>(&self, value: i128, token: &str) -> C {
+        if value >= 0 && value <= i128::from(C::MAX) {
+            return C::truncate(value as u64);
+        }
+
+        match self.policy {
+            RangePolicy::Truncate => C::truncate(value as u64),
+            RangePolicy::Saturate if value < 0 => C::default(),
+            RangePolicy::Saturate => C::truncate(C::MAX),
+            RangePolicy::Error => panic::panic_any(InputError::OutOfRange(token.to_string())),
+        }
+    }
+}
+
+/// Parses an optionally negative decimal number, saturating absurdly long
+/// ones so they are still reported as out of range rather than invalid
+fn parse_number(token: &str) -> Option<i128> {
+    let (negative, digits) = match token.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, token),
+    };
+
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let magnitude = digits.parse::<i128>().unwrap_or(i128::MAX);
+    Some(if negative { -magnitude } else { magnitude })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use output::testing::Shared;
+    use {catch_stop, RunError};
+
+    fn read_300(policy: RangePolicy) -> Result<Option<u8>, RunError> {
+        let source = Box::new(Cursor::new(b"300".to_vec()));
+        let mut input = Input::new(InputMode::Numeric, policy, source);
+        catch_stop(|| input.read())
+    }
+
+    #[test]
+    fn truncate_keeps_the_low_bits() {
+        assert_eq!(read_300(RangePolicy::Truncate), Ok(Some(44)));
+    }
+
+    #[test]
+    fn saturate_clamps() {
+        assert_eq!(read_300(RangePolicy::Saturate), Ok(Some(255)));
+    }
+
+    #[test]
+    fn error_stops_the_program() {
+        let e = InputError::OutOfRange("300".to_string());
+        assert_eq!(read_300(RangePolicy::Error), Err(RunError::Input(e)));
+    }
+
+    #[test]
+    fn a_terminal_gets_the_hint_once() {
+        let hint = Shared::default();
+        let source = Box::new(Cursor::new(b"ab".to_vec()));
+        let mut input =
+            Input::new(InputMode::Byte, RangePolicy::Error, source).hint_to(Box::new(hint.clone()));
+        assert_eq!(input.read(), Some(b'a'));
+        assert_eq!(input.read(), Some(b'b'));
+        assert_eq!(input.read(), None);
+        assert_eq!(
+            hint.text(),
+            "bf: waiting for input on stdin; type characters or pipe a file\n"
+        );
+    }
+}
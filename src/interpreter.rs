@@ -0,0 +1,119 @@
+use std::time::Duration;
+
+use backend::{self, Backend, BACKENDS};
+use input::{BfInput, Input, InputMode, RangePolicy};
+use output::{BfOutput, Buffered, Output, OutputMode};
+use tape::Tape;
+use {catch_stop, lex, parse, Instruction, Machine, OptLevel, ParseError, RunError};
+use {TimeLimit, START_POINTER, TAPE_SIZE};
+
+/// A parsed program, ready to be run any number of times
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    instructions: Vec<Instruction>,
+}
+
+impl Program {
+    pub fn parse(source: &str) -> Result<Program, ParseError> {
+        Ok(Program {
+            instructions: parse(lex(source.to_string()))?,
+        })
+    }
+
+    pub fn from_instructions(instructions: Vec<Instruction>) -> Program {
+        Program { instructions }
+    }
+
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+}
+
+/// A machine that runs programs one after the other, each one starting on
+/// the tape and data pointer the previous one left behind
+pub struct Interpreter {
+    machine: Machine,
+    pointer: i32,
+    backend: &'static dyn Backend,
+    opt: OptLevel,
+}
+
+impl Interpreter {
+    /// Starts on a blank tape, reading `,` from `input` byte by byte and
+    /// writing every `.` to `output` as is
+    pub fn new(
+        input: impl BfInput + Send + 'static,
+        output: impl BfOutput + Send + 'static,
+    ) -> Interpreter {
+        let machine = Machine::new(
+            Tape::new(vec![0; TAPE_SIZE]),
+            Input::new(InputMode::Byte, RangePolicy::Error, Box::new(input)),
+            Output::new(OutputMode::Char, Box::new(Buffered::new(output))),
+        );
+        Interpreter::from_machine(machine, START_POINTER as i32)
+    }
+
+    /// Takes over a machine set up by hand, with the data pointer at `pointer`
+    pub fn from_machine(machine: Machine, pointer: i32) -> Interpreter {
+        Interpreter {
+            machine,
+            pointer,
+            backend: BACKENDS[0],
+            opt: OptLevel::Full,
+        }
+    }
+
+    /// Runs on `backend` rather than the default one, at `OptLevel::Full`
+    pub fn with_backend(mut self, backend: &'static dyn Backend) -> Interpreter {
+        self.backend = backend;
+        self
+    }
+
+    pub fn with_opt(mut self, opt: OptLevel) -> Interpreter {
+        self.opt = opt;
+        self
+    }
+
+    /// Limits the steps of every program run from now on, counted together
+    pub fn with_step_limit(mut self, limit: u64) -> Interpreter {
+        self.machine.step_limit = Some(limit);
+        self
+    }
+
+    /// Limits the time of every program run from now on, counted together
+    /// from now
+    pub fn with_time_limit(mut self, limit: Duration) -> Interpreter {
+        self.machine.time_limit = Some(TimeLimit::start(limit));
+        self
+    }
+
+    /// Runs a program to completion and flushes what it wrote. One stopped by
+    /// a limit leaves its tape as it was at that point, but the data
+    /// pointer back where it started.
+    pub fn run(&mut self, program: &Program) -> Result<(), RunError> {
+        let (opt, backend) = (self.opt, self.backend);
+        let machine = &mut self.machine;
+        let pointer = self.pointer;
+
+        let result =
+            catch_stop(|| backend::run_at(opt, backend, &program.instructions, machine, pointer));
+        let flushed = catch_stop(|| self.machine.output.flush());
+
+        self.pointer = result?;
+        flushed?;
+        Ok(())
+    }
+
+    pub fn tape(&self) -> &Tape {
+        &self.machine.tape
+    }
+
+    pub fn pointer(&self) -> i32 {
+        self.pointer
+    }
+
+    /// Terminates the output, see `Output::finish`
+    pub fn finish(mut self) -> Result<(), RunError> {
+        catch_stop(|| self.machine.output.finish())
+    }
+}
@@ -0,0 +1,494 @@
+//! A backend that compiles the BigInsn form to machine code with Cranelift
+//! and runs that, for when no interpreter is fast enough.
+//!
+//! The generated code reads and writes the tape's memory directly, through a
+//! window onto it kept in a `Context`. Rather than checking every access, each
+//! stretch of code between loops checks once that the memory covers every cell
+//! it will touch, and grows the tape if it doesn't. That, I/O, seeks and the
+//! step limit are left to calls back into Rust, after which the window is
+//! reloaded since the tape may have moved. Those calls can't unwind through
+//! generated code, so a panic in one of them is caught and stops the run, and
+//! it carries on unwinding once the generated code has returned.
+
+use std::any::Any;
+use std::mem::{self, ManuallyDrop};
+use std::panic::{self, AssertUnwindSafe};
+
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{
+    types, AbiParam, Block, InstBuilder, MemFlagsData, SigRef, Signature, Type, UserFuncName, Value,
+};
+use cranelift_codegen::isa::{OwnedTargetIsa, TargetFrontendConfig};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Linkage, Module};
+
+use {fused_iteration_steps, BigInsn, Machine};
+
+/// What the generated code gets a pointer to. It only touches the fields
+/// before `machine`.
+#[repr(C)]
+struct Context<'a> {
+    /// Where address 0 is in memory, whether the tape covers it or not
+    zero: *mut u8,
+    /// The addresses the tape's memory covers, `high` excluded
+    low: isize,
+    high: isize,
+    /// Set once a call back into Rust has panicked
+    stopped: u8,
+    machine: &'a mut Machine,
+    panic: Option<Box<dyn Any + Send>>,
+}
+
+impl<'a> Context<'a> {
+    fn new(machine: &'a mut Machine) -> Context<'a> {
+        let mut context = Context {
+            zero: std::ptr::null_mut(),
+            low: 0,
+            high: 0,
+            stopped: 0,
+            machine,
+            panic: None,
+        };
+        context.refresh();
+        context
+    }
+
+    fn refresh(&mut self) {
+        let (cells, origin) = self.machine.tape.memory();
+        self.zero = cells.as_mut_ptr().wrapping_offset(origin);
+        self.low = -origin;
+        self.high = cells.len() as isize - origin;
+    }
+
+    /// Runs `f` on the machine, stopping the run if it panics
+    fn call<T: Default>(&mut self, f: impl FnOnce(&mut Machine) -> T) -> T {
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(self.machine)));
+        self.refresh();
+
+        result.unwrap_or_else(|payload| {
+            self.panic = Some(payload);
+            self.stopped = 1;
+            T::default()
+        })
+    }
+}
+
+// What the generated code calls. Their arguments are all pointer sized, which
+// spares it from having to extend them the way the platform ABI wants.
+
+extern "C" fn cover(context: *mut Context, low: isize, high: isize) {
+    let context = unsafe { &mut *context };
+    context.call(|m| m.tape.cover(low as i32, high as i32));
+}
+
+extern "C" fn write(context: *mut Context, value: isize) {
+    let context = unsafe { &mut *context };
+    context.call(|m| m.output.write(value as u8));
+}
+
+extern "C" fn read(context: *mut Context, p: isize) {
+    let context = unsafe { &mut *context };
+    context.call(|m| {
+        if let Some(value) = m.read() {
+            m.tape.set(p as i32, value);
+        }
+    });
+}
+
+extern "C" fn seek(context: *mut Context, p: isize, stride: isize) -> isize {
+    let context = unsafe { &mut *context };
+    context.call(|m| m.seek(p as i32, stride as i32) as isize)
+}
+
+extern "C" fn back_edge(context: *mut Context, p: isize, steps: isize) {
+    let context = unsafe { &mut *context };
+    context.call(|m| m.back_edge(p as i32, steps as u64));
+}
+
+/// The generated function, taking the context and the data pointer and
+/// returning the final data pointer
+type Entry = unsafe extern "C" fn(*mut Context, isize) -> isize;
+
+/// Compiled code, which lives as long as the module holding it
+pub struct Compiled {
+    module: ManuallyDrop<JITModule>,
+    entry: Entry,
+}
+
+impl Compiled {
+    /// Runs the program against the machine, returning the final data pointer
+    pub fn run(&self, machine: &mut Machine, data_pointer: i32) -> i32 {
+        let mut context = Context::new(machine);
+        let p = unsafe { (self.entry)(&mut context, data_pointer as isize) };
+
+        if let Some(payload) = context.panic {
+            panic::resume_unwind(payload);
+        }
+        p as i32
+    }
+}
+
+impl Drop for Compiled {
+    fn drop(&mut self) {
+        // Nothing refers to the code once it is no longer reachable from here
+        unsafe { ManuallyDrop::take(&mut self.module).free_memory() };
+    }
+}
+
+/// The host's instruction set, if Cranelift can generate code for it
+fn host() -> Option<OwnedTargetIsa> {
+    let mut flags = settings::builder();
+    flags.set("opt_level", "speed").ok()?;
+    flags.set("use_colocated_libcalls", "false").ok()?;
+    flags.set("is_pic", "false").ok()?;
+
+    cranelift_native::builder()
+        .ok()?
+        .finish(settings::Flags::new(flags))
+        .ok()
+}
+
+/// Compiles the program, or returns `None` when the host isn't supported.
+/// When `supervised` the code calls `Machine::back_edge` at every loop
+/// iteration, which it has to for a step limit or a controller to work.
+pub fn compile(program: &[BigInsn], supervised: bool) -> Option<Compiled> {
+    let isa = host()?;
+    let mut module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+    let config = module.target_config();
+    let ptr = config.pointer_type();
+
+    let mut context = module.make_context();
+    context.func.signature.params.push(AbiParam::new(ptr));
+    context.func.signature.params.push(AbiParam::new(ptr));
+    context.func.signature.returns.push(AbiParam::new(ptr));
+
+    let id = module
+        .declare_function("bf", Linkage::Local, &context.func.signature)
+        .expect("failed to declare the generated function");
+    context.func.name = UserFuncName::user(0, id.as_u32());
+
+    let mut function_context = FunctionBuilderContext::new();
+    {
+        let builder = FunctionBuilder::new(&mut context.func, &mut function_context);
+        Codegen::new(builder, ptr, supervised).function(program, config);
+    }
+
+    module
+        .define_function(id, &mut context)
+        .expect("failed to generate code");
+    module.clear_context(&mut context);
+    module
+        .finalize_definitions()
+        .expect("failed to generate code");
+
+    let code = module.get_finalized_function(id);
+    let entry = unsafe { mem::transmute::<*const u8, Entry>(code) };
+    Some(Compiled {
+        module: ManuallyDrop::new(module),
+        entry,
+    })
+}
+
+struct Codegen<'a> {
+    b: FunctionBuilder<'a>,
+    ptr: Type,
+    supervised: bool,
+    context: Value,
+    /// The data pointer and the window onto the tape
+    p: Variable,
+    zero: Variable,
+    low: Variable,
+    high: Variable,
+    /// Where everything goes once a call has stopped the run
+    exit: Block,
+    /// Signatures of the calls back into Rust without a result, by the
+    /// number of arguments after the context
+    calls: [SigRef; 2],
+    /// Signature of `seek`, the only one with a result
+    seek: SigRef,
+}
+
+impl<'a> Codegen<'a> {
+    fn new(mut b: FunctionBuilder<'a>, ptr: Type, supervised: bool) -> Codegen<'a> {
+        let entry = b.create_block();
+        b.append_block_params_for_function_params(entry);
+        b.switch_to_block(entry);
+
+        let context = b.block_params(entry)[0];
+        let data_pointer = b.block_params(entry)[1];
+
+        let p = b.declare_var(ptr);
+        let zero = b.declare_var(ptr);
+        let low = b.declare_var(ptr);
+        let high = b.declare_var(ptr);
+        b.def_var(p, data_pointer);
+
+        let call_conv = b.func.signature.call_conv;
+        let mut signature = |arguments: usize, result: bool| {
+            let mut signature = Signature::new(call_conv);
+            signature.params = vec![AbiParam::new(ptr); 1 + arguments];
+            if result {
+                signature.returns.push(AbiParam::new(ptr));
+            }
+            b.import_signature(signature)
+        };
+        let calls = [signature(1, false), signature(2, false)];
+        let seek = signature(2, true);
+
+        let exit = b.create_block();
+        b.set_cold_block(exit);
+
+        let mut codegen = Codegen {
+            b,
+            ptr,
+            supervised,
+            context,
+            p,
+            zero,
+            low,
+            high,
+            exit,
+            calls,
+            seek,
+        };
+        codegen.reload();
+        codegen
+    }
+
+    fn function(mut self, program: &[BigInsn], config: TargetFrontendConfig) {
+        self.block(program);
+        self.b.ins().jump(self.exit, &[]);
+
+        self.b.switch_to_block(self.exit);
+        let p = self.b.use_var(self.p);
+        self.b.ins().return_(&[p]);
+
+        self.b.seal_all_blocks();
+        self.b.finalize(config);
+    }
+
+    fn block(&mut self, program: &[BigInsn]) {
+        self.cover(program);
+
+        for (i, insn) in program.iter().enumerate() {
+            match insn {
+                BigInsn::Move(n) => self.move_by(*n),
+                BigInsn::Adj(n) => {
+                    let value = self.load(0);
+                    let value = self.b.ins().iadd_imm_u(value, i64::from(*n as u8));
+                    self.store(0, value);
+                }
+                BigInsn::Set(value) => self.store_constant(0, *value),
+                BigInsn::Clear => self.store_constant(0, 0),
+                BigInsn::MulAdd(targets) => {
+                    let count = self.load(0);
+                    for &(offset, k) in targets {
+                        let product = self.b.ins().imul_imm_u(count, i64::from(k as u8));
+                        let value = self.load(offset);
+                        let value = self.b.ins().iadd(value, product);
+                        self.store(offset, value);
+                    }
+                }
+                BigInsn::Seek(stride) => {
+                    let p = self.b.use_var(self.p);
+                    let stride = self.b.ins().iconst(self.ptr, i64::from(*stride));
+                    let seek_to = self.seek;
+                    let results = self.call(seek_to, seek as *const (), &[p, stride]);
+                    self.b.def_var(self.p, results[0]);
+                    self.cover(&program[i + 1..]);
+                }
+                BigInsn::Write => {
+                    let value = self.load(0);
+                    let value = self.b.ins().uextend(self.ptr, value);
+                    self.procedure(write as *const (), &[value]);
+                }
+                BigInsn::WriteBytes(bytes) => {
+                    for &byte in bytes {
+                        let value = self.b.ins().iconst(self.ptr, i64::from(byte));
+                        self.procedure(write as *const (), &[value]);
+                    }
+                }
+                BigInsn::Read => {
+                    let p = self.b.use_var(self.p);
+                    self.procedure(read as *const (), &[p]);
+                }
+                BigInsn::Snapshot { cells, pointer } => {
+                    for &(offset, value) in cells {
+                        self.store_constant(offset, value);
+                    }
+                    self.move_by(*pointer);
+                }
+                BigInsn::Loop(body) => {
+                    self.loop_over(body);
+                    self.cover(&program[i + 1..]);
+                }
+            }
+        }
+    }
+
+    fn loop_over(&mut self, body: &[BigInsn]) {
+        let header = self.b.create_block();
+        let iteration = self.b.create_block();
+        let done = self.b.create_block();
+
+        self.b.ins().jump(header, &[]);
+        self.b.switch_to_block(header);
+        let value = self.load(0);
+        self.b.ins().brif(value, iteration, &[], done, &[]);
+
+        self.b.switch_to_block(iteration);
+        if self.supervised {
+            let p = self.b.use_var(self.p);
+            let steps = self
+                .b
+                .ins()
+                .iconst(self.ptr, fused_iteration_steps(body) as i64);
+            self.procedure(back_edge as *const (), &[p, steps]);
+        }
+        self.block(body);
+        self.b.ins().jump(header, &[]);
+
+        self.b.switch_to_block(done);
+    }
+
+    fn move_by(&mut self, n: i32) {
+        let p = self.b.use_var(self.p);
+        let p = self.b.ins().iadd_imm_s(p, i64::from(n));
+        self.b.def_var(self.p, p);
+    }
+
+    /// Loads the window onto the tape from the context
+    fn reload(&mut self) {
+        let flags = MemFlagsData::trusted();
+        let context = self.context;
+
+        for &(variable, offset) in &[
+            (self.zero, mem::offset_of!(Context, zero)),
+            (self.low, mem::offset_of!(Context, low)),
+            (self.high, mem::offset_of!(Context, high)),
+        ] {
+            let value = self.b.ins().load(self.ptr, flags, context, offset as i32);
+            self.b.def_var(variable, value);
+        }
+    }
+
+    /// `call` for the functions without a result
+    fn procedure(&mut self, function: *const (), arguments: &[Value]) {
+        let signature = self.calls[arguments.len() - 1];
+        self.call(signature, function, arguments);
+    }
+
+    /// Calls back into Rust, leaving the function if that stopped the run
+    fn call(&mut self, signature: SigRef, function: *const (), arguments: &[Value]) -> Vec<Value> {
+        let mut with_context = vec![self.context];
+        with_context.extend_from_slice(arguments);
+        let callee = self.b.ins().iconst(self.ptr, function as i64);
+        let call = self.b.ins().call_indirect(signature, callee, &with_context);
+        let results = self.b.inst_results(call).to_vec();
+
+        self.reload();
+        let stopped = self.b.ins().load(
+            types::I8,
+            MemFlagsData::trusted(),
+            self.context,
+            mem::offset_of!(Context, stopped) as i32,
+        );
+        let carry_on = self.b.create_block();
+        self.b.ins().brif(stopped, self.exit, &[], carry_on, &[]);
+        self.b.switch_to_block(carry_on);
+
+        results
+    }
+
+    /// Makes sure the tape's memory covers every cell the code touches up to
+    /// the next loop or seek, including where the pointer ends up
+    fn cover(&mut self, program: &[BigInsn]) {
+        let (low, high) = reach(program);
+
+        let p = self.b.use_var(self.p);
+        let first = self.b.ins().iadd_imm_s(p, i64::from(low));
+        let last = self.b.ins().iadd_imm_s(p, i64::from(high));
+        let covered_low = self.b.use_var(self.low);
+        let covered_high = self.b.use_var(self.high);
+        let below = self.b.ins().icmp(IntCC::SignedLessThan, first, covered_low);
+        let above = self
+            .b
+            .ins()
+            .icmp(IntCC::SignedGreaterThanOrEqual, last, covered_high);
+        let outside = self.b.ins().bor(below, above);
+
+        let grow = self.b.create_block();
+        let done = self.b.create_block();
+        self.b.set_cold_block(grow);
+        self.b.ins().brif(outside, grow, &[], done, &[]);
+
+        self.b.switch_to_block(grow);
+        self.procedure(cover as *const (), &[first, last]);
+        self.b.ins().jump(done, &[]);
+
+        self.b.switch_to_block(done);
+    }
+
+    /// Where the current cell is in memory. `cover` has made sure the cells
+    /// the code touches are in memory too.
+    fn current(&mut self) -> Value {
+        let p = self.b.use_var(self.p);
+        let zero = self.b.use_var(self.zero);
+        self.b.ins().iadd(zero, p)
+    }
+
+    fn load(&mut self, offset: i32) -> Value {
+        let current = self.current();
+        self.b
+            .ins()
+            .load(types::I8, MemFlagsData::trusted(), current, offset)
+    }
+
+    fn store(&mut self, offset: i32, value: Value) {
+        let current = self.current();
+        self.b
+            .ins()
+            .store(MemFlagsData::trusted(), value, current, offset);
+    }
+
+    fn store_constant(&mut self, offset: i32, value: u8) {
+        let value = self.b.ins().iconst(types::I8, i64::from(value));
+        self.store(offset, value);
+    }
+}
+
+/// The lowest and highest offsets from the pointer of the cells the code
+/// touches up to the next loop or seek, including where the pointer ends up
+fn reach(program: &[BigInsn]) -> (i32, i32) {
+    let mut position = 0;
+    let (mut low, mut high) = (0, 0);
+    let mut touch = |offset: i32| {
+        low = low.min(offset);
+        high = high.max(offset);
+    };
+
+    for insn in program {
+        match insn {
+            BigInsn::Move(n) => position += n,
+            BigInsn::MulAdd(targets) => {
+                for &(offset, _) in targets {
+                    touch(position + offset);
+                }
+            }
+            BigInsn::Snapshot { cells, pointer } => {
+                for &(offset, _) in cells {
+                    touch(position + offset);
+                }
+                position += pointer;
+            }
+            BigInsn::Loop(_) | BigInsn::Seek(_) => break,
+            _ => (),
+        }
+        touch(position);
+    }
+    touch(position);
+
+    (low, high)
+}
@@ -0,0 +1,65 @@
+//! Programs as JSON, so external tools can analyze or rewrite them without
+//! their own parser.
+//!
+//! A program is an array of instructions. The plain ones are strings:
+//! `"IncrementPointer"`, `"DecrementPointer"`, `"Increment"`, `"Decrement"`,
+//! `"Write"` and `"Read"`. The others are single-key objects:
+//!
+//! * `{"Loop": [...]}` holds the loop body as a nested program
+//! * `{"WriteBytes": [72, 105]}` writes those bytes
+//! * `{"Snapshot": {"cells": [[0, 7], [2, 1]], "pointer": 2}}` stores each
+//!   `[offset, value]` relative to the pointer, then moves the pointer
+//!
+//! For example `+[->+<]` is
+//! `["Increment",{"Loop":["Decrement","IncrementPointer","Increment","DecrementPointer"]}]`.
+//!
+//! The later stages, which are only ever written, follow the same scheme:
+//! `BigInsn`s like `{"Move": 2}`, `"Clear"` and `{"MulAdd": [[1, 2]]}`, and
+//! bytecode `Op`s like `{"Adj": [0, 5]}` and `{"Open": {"close": 9, "steps": 4}}`.
+
+use std::io::Read;
+
+use serde_json;
+
+use bytecode::Op;
+use {BigInsn, Instruction};
+
+pub fn to_json(program: &[Instruction]) -> String {
+    serde_json::to_string(program).expect("instructions always serialize")
+}
+
+pub fn lowered_to_json(program: &[BigInsn]) -> String {
+    serde_json::to_string(program).expect("instructions always serialize")
+}
+
+pub fn bytecode_to_json(code: &[Op]) -> String {
+    serde_json::to_string(code).expect("bytecode always serializes")
+}
+
+pub fn from_json<R: Read>(reader: R) -> Result<Vec<Instruction>, String> {
+    serde_json::from_reader(reader).map_err(|e| format!("invalid JSON program: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {fold_constant_prefix, lex, parse, tape_bounds};
+
+    #[test]
+    fn programs_survive_a_round_trip() {
+        let program = parse(lex("+[->+<],.[-]++.".to_string())).unwrap();
+        let folded = fold_constant_prefix(program.clone(), tape_bounds());
+        for program in [program, folded] {
+            let json = to_json(&program);
+            assert_eq!(from_json(json.as_bytes()).unwrap(), program);
+        }
+    }
+
+    #[test]
+    fn the_documented_example_reads_back() {
+        let json = r#"["Increment",{"Loop":["Decrement","IncrementPointer","Increment","DecrementPointer"]}]"#;
+        let program = parse(lex("+[->+<]".to_string())).unwrap();
+        assert_eq!(from_json(json.as_bytes()).unwrap(), program);
+        assert_eq!(to_json(&program), json);
+    }
+}
@@ -0,0 +1,1473 @@
+//! A Brainfuck interpreter with several execution backends.
+//!
+//! Programs are lexed into opcodes, parsed into a tree of `Instruction`s and
+//! then either run directly or lowered to the fused `BigInsn` form and
+//! through the passes in `pass`, see `backend` for the ways of running them.
+//! `run_program` does all of that with the default settings, `interpret`
+//! does it in memory, and an `Interpreter` keeps its tape from one `Program`
+//! to the next.
+
+#[cfg(feature = "jit")]
+extern crate cranelift_codegen;
+#[cfg(feature = "jit")]
+extern crate cranelift_frontend;
+#[cfg(feature = "jit")]
+extern crate cranelift_jit;
+#[cfg(feature = "jit")]
+extern crate cranelift_module;
+#[cfg(feature = "jit")]
+extern crate cranelift_native;
+#[cfg(all(unix, feature = "terminal"))]
+extern crate libc;
+#[cfg(feature = "json")]
+extern crate serde;
+#[cfg(feature = "json")]
+extern crate serde_json;
+#[cfg(feature = "wasm")]
+extern crate wasm_bindgen;
+
+use std::collections::BTreeMap;
+use std::error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Once;
+use std::time::{Duration, Instant};
+
+pub mod analysis;
+pub mod backend;
+pub mod bench;
+pub mod bfc;
+pub mod brainfuck;
+pub mod bytecode;
+pub mod c;
+pub mod cell;
+pub mod checkpoint;
+pub mod conformance;
+pub mod control;
+pub mod coverage;
+pub mod debug;
+pub mod ffi;
+pub mod fold;
+pub mod format;
+pub mod frontend;
+pub mod generate;
+pub mod input;
+pub mod interpreter;
+#[cfg(feature = "jit")]
+pub mod jit;
+#[cfg(feature = "json")]
+pub mod json;
+#[cfg(feature = "llvm")]
+pub mod llvm;
+pub mod output;
+pub mod pass;
+pub mod pbrain;
+#[cfg(feature = "wasm")]
+pub mod playground;
+pub mod profile;
+mod propagate;
+pub mod rust;
+pub mod seed;
+pub mod sexpr;
+pub mod tape;
+pub mod terminal;
+pub mod trace;
+pub mod visualize;
+pub mod wasm;
+
+use backend::BACKENDS;
+use cell::{CellOverflow, CellWidth, Overflow};
+use control::RunHandle;
+use fold::fold_constant_prefix;
+pub use input::BfInput;
+use input::{EofBehavior, Input, InputError, InputMode, RangePolicy};
+pub use interpreter::{Interpreter, Program};
+pub use output::BfOutput;
+use output::{Buffered, Output, OutputError, OutputMode};
+use pass::PASSES;
+pub use tape::{OutOfBounds, Tape, TapePolicy};
+
+/// Opcodes determined by the lexer
+#[derive(Debug, Clone)]
+pub enum OpCode {
+    IncrementPointer,
+    DecrementPointer,
+    Increment,
+    Decrement,
+    Write,
+    Read,
+    LoopBegin,
+    LoopEnd,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub enum Instruction {
+    IncrementPointer,
+    DecrementPointer,
+    Increment,
+    Decrement,
+    Write,
+    Read,
+    Loop(Vec<Instruction>),
+
+    // Only produced by the optimizer
+    /// Writes a fixed sequence of bytes
+    WriteBytes(Vec<u8>),
+    /// Stores values at offsets from the pointer, then moves the pointer
+    Snapshot {
+        cells: Vec<(i32, u8)>,
+        pointer: i32,
+    },
+}
+
+/// Higher level instructions where runs of pointer moves and cell
+/// adjustments are fused into one
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub enum BigInsn {
+    Move(i32),
+    Adj(i32),
+    /// Stores a constant in the current cell
+    Set(u8),
+    /// Zeroes the current cell, what `[-]`, `[+]` and `[---]` amount to
+    Clear,
+    /// Adds the current cell times the multiplier to each (offset,
+    /// multiplier) cell, leaving the current cell alone
+    MulAdd(Vec<(i32, i32)>),
+    /// Moves the pointer by the stride until it lands on a zero cell, what
+    /// scans like `[>]` and `[<<]` amount to
+    Seek(i32),
+    Write,
+    Read,
+    Loop(Vec<BigInsn>),
+    WriteBytes(Vec<u8>),
+    Snapshot {
+        cells: Vec<(i32, u8)>,
+        pointer: i32,
+    },
+}
+
+/// Maps a source byte to its opcode, or None for comment characters
+fn decode(symbol: u8) -> Option<OpCode> {
+    match symbol {
+        b'>' => Some(OpCode::IncrementPointer),
+        b'<' => Some(OpCode::DecrementPointer),
+        b'+' => Some(OpCode::Increment),
+        b'-' => Some(OpCode::Decrement),
+        b'.' => Some(OpCode::Write),
+        b',' => Some(OpCode::Read),
+        b'[' => Some(OpCode::LoopBegin),
+        b']' => Some(OpCode::LoopEnd),
+        _ => None,
+    }
+}
+
+/// Lexer turns the source code into a sequence of opcodes
+pub fn lex(source: String) -> Vec<OpCode> {
+    // Non-opcode characters are simply comments
+    source.bytes().filter_map(decode).collect()
+}
+
+/// Streaming lexer that pulls the source from a reader one byte at a time.
+/// Nothing is buffered per line, so a minified program that is one huge
+/// line costs no more memory than its opcodes.
+pub fn lex_reader<R: Read>(reader: R) -> io::Result<Vec<OpCode>> {
+    let mut operations = Vec::new();
+
+    for symbol in BufReader::new(reader).bytes() {
+        if let Some(op) = decode(symbol?) {
+            operations.push(op);
+        }
+    }
+
+    Ok(operations)
+}
+
+/// Brackets that don't pair up, or nest deeper than `MAX_NESTING`, with the
+/// index of the offending opcode
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    UnmatchedEnd(usize),
+    UnmatchedBegin(usize),
+    TooDeep(usize),
+}
+
+impl error::Error for ParseError {}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::UnmatchedEnd(i) => write!(f, "unmatched ']' at #{}", i),
+            ParseError::UnmatchedBegin(i) => write!(f, "unmatched '[' at #{}", i),
+            ParseError::TooDeep(i) => write!(
+                f,
+                "'[' at #{} nests loops more than {} deep",
+                i, MAX_NESTING
+            ),
+        }
+    }
+}
+
+/// Why a program couldn't be loaded, with enough context to point the user
+/// at the problem in their source
+#[derive(Debug, Clone, PartialEq)]
+pub enum BfError {
+    /// The source couldn't be read
+    Io { file: String, message: String },
+    /// The brackets don't pair up, `location` is None only if the source
+    /// changed while it was being read
+    Parse {
+        file: String,
+        error: ParseError,
+        location: Option<Location>,
+    },
+}
+
+/// Where in its source an opcode is, line and column counted from 1
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub line: usize,
+    /// In characters, not bytes
+    pub column: usize,
+    /// The whole line, for showing the opcode in context
+    pub text: String,
+}
+
+impl error::Error for BfError {}
+
+impl fmt::Display for BfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (file, error, location) = match self {
+            BfError::Io { file, message } => return write!(f, "{}: {}", file, message),
+            BfError::Parse {
+                file,
+                error,
+                location,
+            } => (file, error, location),
+        };
+        let location = match location {
+            Some(location) => location,
+            None => return write!(f, "{}: {}", file, error),
+        };
+
+        let what = match error {
+            ParseError::UnmatchedEnd(_) => "unmatched ']'",
+            ParseError::UnmatchedBegin(_) => "unmatched '['",
+            ParseError::TooDeep(_) => "loops nested too deep",
+        };
+        // Tabs are kept so the caret lines up however they are rendered
+        let indent: String = location
+            .text
+            .chars()
+            .take(location.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+
+        writeln!(
+            f,
+            "{}:{}:{}: {}",
+            file, location.line, location.column, what
+        )?;
+        writeln!(f, "    {}", location.text)?;
+        write!(f, "    {}^", indent)
+    }
+}
+
+impl BfError {
+    /// Locates the opcode a parse error is about in `source`
+    fn parse(file: &str, error: ParseError, source: &[u8]) -> BfError {
+        let opcode = match error {
+            ParseError::UnmatchedEnd(i)
+            | ParseError::UnmatchedBegin(i)
+            | ParseError::TooDeep(i) => i,
+        };
+
+        BfError::Parse {
+            file: file.to_string(),
+            error,
+            location: locate(source, opcode),
+        }
+    }
+}
+
+/// Finds the `opcode`th opcode of a source
+fn locate(source: &[u8], opcode: usize) -> Option<Location> {
+    let source = String::from_utf8_lossy(source);
+    let mut seen = 0;
+
+    for (number, text) in source.lines().enumerate() {
+        for (column, c) in text.chars().enumerate() {
+            if !c.is_ascii() || decode(c as u8).is_none() {
+                continue;
+            }
+            if seen == opcode {
+                return Some(Location {
+                    line: number + 1,
+                    column: column + 1,
+                    text: text.to_string(),
+                });
+            }
+            seen += 1;
+        }
+    }
+
+    None
+}
+
+/// Parses a program held in memory, `file` naming it in errors
+pub fn parse_source(file: &str, source: &[u8]) -> Result<Vec<Instruction>, BfError> {
+    let opcodes = source.iter().cloned().filter_map(decode).collect();
+    parse(opcodes).map_err(|e| BfError::parse(file, e, source))
+}
+
+/// Parses a program file, streaming it through `lex_reader`. Positions
+/// aren't kept while lexing, so on an error the file is read again to find
+/// where it is.
+pub fn parse_file(path: &str) -> Result<Vec<Instruction>, BfError> {
+    let io_error = |e: io::Error| BfError::Io {
+        file: path.to_string(),
+        message: e.to_string(),
+    };
+
+    let opcodes = File::open(path).and_then(lex_reader).map_err(io_error)?;
+    parse(opcodes).map_err(|e| match fs::read(path) {
+        Ok(source) => BfError::parse(path, e, &source),
+        Err(_) => BfError::Parse {
+            file: path.to_string(),
+            error: e,
+            location: None,
+        },
+    })
+}
+
+/// How deep loops may nest. Every stage after parsing, down to running the
+/// program on most backends, recurses into loops, and this is as deep as
+/// they all go on the stack of the main thread.
+pub const MAX_NESTING: usize = 4096;
+
+pub fn parse(opcodes: Vec<OpCode>) -> Result<Vec<Instruction>, ParseError> {
+    parse_block(&opcodes, 0, 0)
+}
+
+/// Parses opcodes that start at index `base` of the whole program, inside
+/// `depth` loops
+fn parse_block(
+    opcodes: &[OpCode],
+    base: usize,
+    depth: usize,
+) -> Result<Vec<Instruction>, ParseError> {
+    let mut program: Vec<Instruction> = Vec::new();
+    let mut loop_stack = 0;
+    let mut loop_start = 0;
+
+    for (i, op) in opcodes.iter().enumerate() {
+        if loop_stack == 0 {
+            let instr = match op {
+                OpCode::IncrementPointer => Some(Instruction::IncrementPointer),
+                OpCode::DecrementPointer => Some(Instruction::DecrementPointer),
+                OpCode::Increment => Some(Instruction::Increment),
+                OpCode::Decrement => Some(Instruction::Decrement),
+                OpCode::Write => Some(Instruction::Write),
+                OpCode::Read => Some(Instruction::Read),
+
+                OpCode::LoopBegin => {
+                    if depth == MAX_NESTING {
+                        return Err(ParseError::TooDeep(base + i));
+                    }
+                    loop_start = i;
+                    loop_stack += 1;
+                    None
+                }
+
+                OpCode::LoopEnd => return Err(ParseError::UnmatchedEnd(base + i)),
+            };
+
+            if let Some(instr) = instr {
+                program.push(instr);
+            }
+        } else {
+            match op {
+                OpCode::LoopBegin => {
+                    // Caught here, so a program far too deep is not
+                    // scanned once for every level
+                    if depth + loop_stack == MAX_NESTING {
+                        return Err(ParseError::TooDeep(base + i));
+                    }
+                    loop_stack += 1;
+                }
+                OpCode::LoopEnd => {
+                    loop_stack -= 1;
+
+                    if loop_stack == 0 {
+                        program.push(Instruction::Loop(parse_block(
+                            &opcodes[loop_start + 1..i],
+                            base + loop_start + 1,
+                            depth + 1,
+                        )?));
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    if loop_stack != 0 {
+        return Err(ParseError::UnmatchedBegin(base + loop_start));
+    }
+
+    Ok(program)
+}
+
+/// The BigInsn form with clears, scans and multiply loops lowered to their
+/// own instructions, but no values propagated
+pub fn raise_abstraction(instructions: &[Instruction]) -> Vec<BigInsn> {
+    pass::lower_loops(fuse(instructions))
+}
+
+/// The BigInsn form with nothing but runs of moves and adjustments fused,
+/// every loop is kept as it is. A run is only fused in one direction, and an
+/// adjustment no further than it goes without wrapping around, so that every
+/// `Move(n)` and `Adj(n)` still stands for `n` commands, which is what a
+/// step limit counts.
+pub fn fuse(instructions: &[Instruction]) -> Vec<BigInsn> {
+    let mut program = Vec::new();
+
+    for instr in instructions {
+        let insn = match instr {
+            Instruction::IncrementPointer => BigInsn::Move(1),
+            Instruction::DecrementPointer => BigInsn::Move(-1),
+            Instruction::Increment => BigInsn::Adj(1),
+            Instruction::Decrement => BigInsn::Adj(-1),
+            Instruction::Write => BigInsn::Write,
+            Instruction::Read => BigInsn::Read,
+            Instruction::Loop(nested_instructions) => BigInsn::Loop(fuse(nested_instructions)),
+            Instruction::WriteBytes(bytes) => BigInsn::WriteBytes(bytes.clone()),
+            Instruction::Snapshot { cells, pointer } => BigInsn::Snapshot {
+                cells: cells.clone(),
+                pointer: *pointer,
+            },
+        };
+
+        // Fuse with the previous instruction where possible
+        match (program.last_mut(), &insn) {
+            (Some(BigInsn::Move(n)), BigInsn::Move(m)) if n.signum() == m.signum() => *n += m,
+            (Some(BigInsn::Adj(n)), BigInsn::Adj(m))
+                if n.signum() == m.signum() && (*n + m).abs() <= i32::from(i8::MAX) =>
+            {
+                *n += m
+            }
+            _ => program.push(insn),
+        }
+    }
+
+    program
+}
+
+/// Net adjustment per iteration of each cell a loop body touches, by offset,
+/// if the body only moves and adjusts and ends up where it started
+fn balanced_adjustments(body: &[BigInsn]) -> Option<BTreeMap<i32, i32>> {
+    let mut offset = 0;
+    let mut totals = BTreeMap::new();
+
+    for insn in body {
+        match insn {
+            BigInsn::Move(n) => offset += n,
+            BigInsn::Adj(n) => *totals.entry(offset).or_insert(0) += n,
+            _ => return None,
+        }
+    }
+
+    if offset != 0 {
+        return None;
+    }
+    Some(totals)
+}
+
+/// The (offset, multiplier) pairs of a copy/multiply loop like `[->+>+++<<]`,
+/// one that steps its own cell by an odd amount, so that it always reaches
+/// zero. The multipliers apply to the cell's value on entry, which for a
+/// step other than -1 isn't the number of iterations: `[+>+<]` runs -n times.
+fn mul_add_targets(body: &[BigInsn]) -> Option<Vec<(i32, i32)>> {
+    let mut totals = balanced_adjustments(body)?;
+
+    let step = totals.remove(&0)? as u8;
+    // Counting n down by `step` takes n / -step iterations, modulo 256
+    let per_unit = inverse(step.wrapping_neg())?;
+
+    let targets: Vec<(i32, i32)> = totals
+        .into_iter()
+        .map(|(offset, k)| (offset, i32::from((k as u8).wrapping_mul(per_unit) as i8)))
+        .filter(|&(_, k)| k != 0)
+        .collect();
+
+    if targets.is_empty() {
+        return None;
+    }
+    Some(targets)
+}
+
+/// The multiplicative inverse modulo 256, which only odd numbers have
+fn inverse(n: u8) -> Option<u8> {
+    (1..=255).step_by(2).find(|&x: &u8| x.wrapping_mul(n) == 1)
+}
+
+/// The BigInsn form of a program after every pass in `PASSES`
+pub fn lower(instructions: &[Instruction]) -> Vec<BigInsn> {
+    pass::run_passes(fuse(instructions), PASSES)
+}
+
+/// `lower` for running on the machine, which under a step limit is only
+/// `fuse`: a loop rewritten into straight-line code would take no steps
+fn lower_for(instructions: &[Instruction], machine: &Machine) -> Vec<BigInsn> {
+    match machine.step_limit {
+        Some(_) => fuse(instructions),
+        None => lower(instructions),
+    }
+}
+
+/// How much a program is rewritten before it runs. The lower levels are for
+/// pinning down a miscompilation by comparing against them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OptLevel {
+    /// Walk the parsed program as it is, with the `tree` backend
+    None,
+    /// Only fuse runs of moves and adjustments, see `fuse`
+    Basic,
+    /// Fold the constant prefix and use every lowering, on any backend,
+    /// short of rewriting loops away under a step limit
+    Full,
+}
+
+/// The BigInsn form of a program as far as `level` rewrites it. `None` has
+/// no BigInsn form of its own and gets the `Basic` one.
+pub fn lower_at(instructions: &[Instruction], level: OptLevel) -> Vec<BigInsn> {
+    match level {
+        OptLevel::None | OptLevel::Basic => fuse(instructions),
+        OptLevel::Full => lower(instructions),
+    }
+}
+
+/// Number of cells on the tape
+pub const TAPE_SIZE: usize = 1024;
+/// Cell the data pointer starts on
+pub const START_POINTER: usize = 512;
+
+/// Offsets from the starting pointer that are on the tape
+pub fn tape_bounds() -> std::ops::Range<i32> {
+    -(START_POINTER as i32)..(TAPE_SIZE - START_POINTER) as i32
+}
+
+/// Everything a running program touches apart from the data pointer
+pub struct Machine {
+    pub tape: Tape,
+    pub input: Input,
+    pub output: Output,
+    /// Checked at every loop boundary when the program is being controlled
+    pub control: Option<RunHandle>,
+    /// Primitive operations executed so far, counted while there is a step
+    /// limit: every command of the program as written, plus one per loop
+    /// iteration. The commands outside of any loop are counted all at once
+    /// before the program starts, and those of a loop body at the start of
+    /// every iteration, so every backend counts the same.
+    pub steps: u64,
+    /// Stops the program, by panicking with `StepLimitExceeded`, once it has
+    /// taken more steps than this
+    pub step_limit: Option<u64>,
+    /// Stops the program, by panicking with `TimeLimitExceeded`, once this
+    /// has run out. Like the step limit it is checked at loop boundaries.
+    pub time_limit: Option<TimeLimit>,
+}
+
+/// Loop boundaries between readings of the clock for a `TimeLimit`
+const CLOCK_EVERY: u32 = 1024;
+
+/// A deadline for `Machine::time_limit`. The clock is read at one loop
+/// boundary in every `CLOCK_EVERY`, so that checking it costs about as
+/// little as counting steps.
+#[derive(Debug, Clone)]
+pub struct TimeLimit {
+    limit: Duration,
+    /// None for a limit too far off to ever be reached
+    deadline: Option<Instant>,
+    /// Boundaries left until the clock is read again
+    countdown: u32,
+}
+
+impl TimeLimit {
+    /// Starts the clock
+    pub fn start(limit: Duration) -> TimeLimit {
+        TimeLimit {
+            limit,
+            deadline: Instant::now().checked_add(limit),
+            countdown: 0,
+        }
+    }
+
+    pub fn limit(&self) -> Duration {
+        self.limit
+    }
+
+    /// Called at a loop boundary, true once the deadline has passed
+    pub fn is_expired(&mut self) -> bool {
+        if self.countdown > 0 {
+            self.countdown -= 1;
+            return false;
+        }
+        self.countdown = CLOCK_EVERY;
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
+
+/// Panic payload of a program stopped by `Machine::step_limit`
+#[derive(Debug, Clone, PartialEq)]
+pub struct StepLimitExceeded {
+    pub limit: u64,
+}
+
+impl fmt::Display for StepLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "step limit of {} exceeded", self.limit)
+    }
+}
+
+/// Panic payload of a program stopped by `Machine::time_limit`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimeLimitExceeded {
+    pub limit: Duration,
+}
+
+impl fmt::Display for TimeLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "time limit of {}s exceeded", self.limit.as_secs_f64())
+    }
+}
+
+/// Keeps the panic hook quiet about `StepLimitExceeded`, `TimeLimitExceeded`,
+/// `OutOfBounds`, `CellOverflow`, `InputError` and `OutputError`, which stop
+/// a run rather than report a bug. Only the first call installs anything.
+pub fn silence_step_limit_panics() {
+    static INSTALL: Once = Once::new();
+
+    INSTALL.call_once(|| {
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let payload = info.payload();
+            if !payload.is::<StepLimitExceeded>()
+                && !payload.is::<TimeLimitExceeded>()
+                && !payload.is::<OutOfBounds>()
+                && !payload.is::<CellOverflow>()
+                && !payload.is::<InputError>()
+                && !payload.is::<OutputError>()
+            {
+                default_hook(info);
+            }
+        }));
+    });
+}
+
+/// Runs `f`, turning a `StepLimitExceeded`, `TimeLimitExceeded`,
+/// `OutOfBounds`, `CellOverflow`, `InputError` or `OutputError` it panics
+/// with, whatever stopped the program, into an error
+fn catch_stop<R>(f: impl FnOnce() -> R) -> Result<R, RunError> {
+    silence_step_limit_panics();
+
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| {
+        let payload = match payload.downcast::<StepLimitExceeded>() {
+            Ok(e) => return RunError::StepLimit(*e),
+            Err(payload) => payload,
+        };
+        let payload = match payload.downcast::<TimeLimitExceeded>() {
+            Ok(e) => return RunError::TimeLimit(*e),
+            Err(payload) => payload,
+        };
+        let payload = match payload.downcast::<OutOfBounds>() {
+            Ok(e) => return RunError::OutOfBounds(*e),
+            Err(payload) => payload,
+        };
+        let payload = match payload.downcast::<CellOverflow>() {
+            Ok(e) => return RunError::CellOverflow(*e),
+            Err(payload) => payload,
+        };
+        let payload = match payload.downcast::<InputError>() {
+            Ok(e) => return RunError::Input(*e),
+            Err(payload) => payload,
+        };
+        match payload.downcast::<OutputError>() {
+            Ok(e) => RunError::Output(*e),
+            Err(payload) => panic::resume_unwind(payload),
+        }
+    })
+}
+
+impl Machine {
+    /// A machine that has taken no steps yet, with no limits and no
+    /// controller. Everything else is set on the fields it returns, as in
+    /// `Machine { step_limit, ..Machine::new(tape, input, output) }`, so
+    /// that every field gets a value in one place.
+    pub fn new(tape: Tape, input: Input, output: Output) -> Machine {
+        Machine {
+            tape,
+            input,
+            output,
+            control: None,
+            steps: 0,
+            step_limit: None,
+            time_limit: None,
+        }
+    }
+
+    /// Reads for `,`, first flushing the output so that a prompt is visible
+    /// before the program waits for an answer
+    pub fn read(&mut self) -> Option<u8> {
+        self.output.flush();
+        self.input.read()
+    }
+
+    /// Whether there is a limit or a controller that loop boundaries have to
+    /// answer to
+    #[inline]
+    fn is_supervised(&self) -> bool {
+        self.step_limit.is_some() || self.time_limit.is_some() || self.control.is_some()
+    }
+
+    /// Called once before the program runs, costing the steps of the
+    /// commands outside of its loops
+    #[inline]
+    fn enter(&mut self, p: i32, steps: u64) {
+        self.back_edge(p, steps);
+    }
+
+    /// Called at the start of every loop iteration, costing `steps`, which is
+    /// where the limits are enforced and a controller gets its chance to
+    /// pause the run. Kept to a quick check when none of them is in use.
+    #[inline]
+    fn back_edge(&mut self, p: i32, steps: u64) {
+        if self.is_supervised() {
+            self.supervise(p, steps);
+        }
+    }
+
+    /// Runs a `Seek` from `p`, returning where it lands. Without a limit or
+    /// a controller to answer to, it scans the tape's memory directly.
+    #[inline]
+    fn seek(&mut self, mut p: i32, stride: i32) -> i32 {
+        if !self.is_supervised() {
+            return self.tape.seek_zero(p, stride);
+        }
+        while self.tape.get(p) != 0 {
+            self.supervise(p, SEEK_STEPS);
+            p += stride;
+        }
+        p
+    }
+
+    #[cold]
+    #[inline(never)]
+    fn supervise(&mut self, p: i32, steps: u64) {
+        if let Some(limit) = self.step_limit {
+            self.steps += steps;
+            if self.steps > limit {
+                panic::panic_any(StepLimitExceeded { limit });
+            }
+        }
+        if let Some(time_limit) = &mut self.time_limit {
+            if time_limit.is_expired() {
+                let limit = time_limit.limit();
+                panic::panic_any(TimeLimitExceeded { limit });
+            }
+        }
+        if let Some(control) = &self.control {
+            control.poll(&self.tape, p);
+        }
+    }
+}
+
+/// Steps the commands of a block cost, one each, except those of inner loops
+/// which count their own
+fn straight_steps(block: &[Instruction]) -> u64 {
+    block
+        .iter()
+        .filter(|instr| !matches!(instr, Instruction::Loop(_)))
+        .count() as u64
+}
+
+/// Steps one iteration of a loop costs: one for the iteration itself and
+/// the `straight_steps` of its body
+fn iteration_steps(body: &[Instruction]) -> u64 {
+    1 + straight_steps(body)
+}
+
+/// `straight_steps` of the fused form, where a move or an adjustment by `n`
+/// is `n` commands
+fn fused_straight_steps(block: &[BigInsn]) -> u64 {
+    block
+        .iter()
+        .map(|insn| match insn {
+            BigInsn::Move(n) | BigInsn::Adj(n) => u64::from(n.unsigned_abs()),
+            BigInsn::Loop(_) => 0,
+            _ => 1,
+        })
+        .sum()
+}
+
+/// `iteration_steps` of the fused form
+fn fused_iteration_steps(body: &[BigInsn]) -> u64 {
+    1 + fused_straight_steps(body)
+}
+
+/// Steps a `Seek` costs per cell, the same as an iteration of the `[>]` it
+/// replaces
+const SEEK_STEPS: u64 = 2;
+
+/// A compiled program fragment: takes the data pointer and returns its new value
+pub type Code = Box<dyn Fn(&mut Machine, i32) -> i32>;
+
+/// Compiles a program into a closure that owns everything it needs, so it can
+/// be stored and run long after the instructions are gone.
+///
+/// The closure takes the machine to run on and the data pointer to start
+/// from, and returns where the data pointer ends up. It can be run any number
+/// of times, on the same machine or on others.
+pub fn compile_owned(program: &[Instruction]) -> Code {
+    let steps = straight_steps(program);
+    let code = compile(program, 0);
+    Box::new(move |m, p| {
+        m.enter(p, steps);
+        code(m, p)
+    })
+}
+
+fn compile(instructions: &[Instruction], delta_p: i32) -> Code {
+    if instructions.is_empty() {
+        return Box::new(move |_m, p| p + delta_p);
+    }
+
+    match &instructions[0] {
+        Instruction::IncrementPointer => compile(&instructions[1..], delta_p + 1),
+        Instruction::DecrementPointer => compile(&instructions[1..], delta_p - 1),
+        Instruction::Increment => {
+            let rest = compile(&instructions[1..], 0);
+
+            Box::new(move |m, mut p| {
+                p += delta_p;
+                m.tape.set(p, m.tape.get(p).wrapping_add(1));
+                rest(m, p)
+            })
+        }
+        Instruction::Decrement => {
+            let rest = compile(&instructions[1..], 0);
+
+            Box::new(move |m, mut p| {
+                p += delta_p;
+                m.tape.set(p, m.tape.get(p).wrapping_sub(1));
+                rest(m, p)
+            })
+        }
+        Instruction::Write => {
+            let rest = compile(&instructions[1..], 0);
+
+            Box::new(move |m, mut p| {
+                p += delta_p;
+                m.output.write(m.tape.get(p));
+                rest(m, p)
+            })
+        }
+        Instruction::Read => {
+            let rest = compile(&instructions[1..], 0);
+
+            Box::new(move |m, mut p| {
+                p += delta_p;
+                if let Some(value) = m.read() {
+                    m.tape.set(p, value);
+                }
+                rest(m, p)
+            })
+        }
+        Instruction::WriteBytes(bytes) => {
+            let rest = compile(&instructions[1..], 0);
+            let bytes = bytes.clone();
+
+            Box::new(move |m, mut p| {
+                p += delta_p;
+                for &byte in &bytes {
+                    m.output.write(byte);
+                }
+                rest(m, p)
+            })
+        }
+        Instruction::Snapshot { cells, pointer } => {
+            let rest = compile(&instructions[1..], *pointer);
+            let cells = cells.clone();
+
+            Box::new(move |m, mut p| {
+                p += delta_p;
+                for &(offset, value) in &cells {
+                    m.tape.set(p + offset, value);
+                }
+                rest(m, p)
+            })
+        }
+
+        Instruction::Loop(nested_instructions) => {
+            let rest = compile(&instructions[1..], 0);
+            let steps = iteration_steps(nested_instructions);
+
+            let inner = compile(nested_instructions, 0);
+            Box::new(move |m, mut p| {
+                p += delta_p;
+                while m.tape.get(p) != 0 {
+                    m.back_edge(p, steps);
+                    p = inner(m, p);
+                }
+                rest(m, p)
+            })
+        }
+    }
+}
+
+/// Compiles the fused form of a program into closures. Like `compile`, pointer
+/// moves are carried into the next instruction rather than run on their own.
+fn compile_big(program: &[BigInsn]) -> Code {
+    let steps = fused_straight_steps(program);
+    let code = compile_fused(program, 0);
+    Box::new(move |m, p| {
+        m.enter(p, steps);
+        code(m, p)
+    })
+}
+
+fn compile_fused(program: &[BigInsn], delta_p: i32) -> Code {
+    let (insn, program) = match program.split_first() {
+        Some(split) => split,
+        None => return Box::new(move |_m, p| p + delta_p),
+    };
+
+    match insn {
+        BigInsn::Move(n) => compile_fused(program, delta_p + n),
+        BigInsn::Adj(n) => {
+            let rest = compile_fused(program, 0);
+            let n = *n as u8;
+
+            Box::new(move |m, mut p| {
+                p += delta_p;
+                m.tape.set(p, m.tape.get(p).wrapping_add(n));
+                rest(m, p)
+            })
+        }
+        BigInsn::Set(value) => {
+            let rest = compile_fused(program, 0);
+            let value = *value;
+
+            Box::new(move |m, mut p| {
+                p += delta_p;
+                m.tape.set(p, value);
+                rest(m, p)
+            })
+        }
+        BigInsn::Clear => {
+            let rest = compile_fused(program, 0);
+
+            Box::new(move |m, mut p| {
+                p += delta_p;
+                m.tape.set(p, 0);
+                rest(m, p)
+            })
+        }
+        BigInsn::MulAdd(targets) => {
+            let rest = compile_fused(program, 0);
+            let targets: Vec<(i32, u8)> = targets.iter().map(|&(o, k)| (o, k as u8)).collect();
+
+            Box::new(move |m, mut p| {
+                p += delta_p;
+                // Zero is a loop that isn't entered, touching no other cell
+                let count = m.tape.get(p);
+                if count != 0 {
+                    for &(offset, k) in &targets {
+                        let cell = m.tape.get(p + offset);
+                        m.tape
+                            .set(p + offset, cell.wrapping_add(count.wrapping_mul(k)));
+                    }
+                }
+                rest(m, p)
+            })
+        }
+        BigInsn::Write => {
+            let rest = compile_fused(program, 0);
+
+            Box::new(move |m, mut p| {
+                p += delta_p;
+                m.output.write(m.tape.get(p));
+                rest(m, p)
+            })
+        }
+        BigInsn::Read => {
+            let rest = compile_fused(program, 0);
+
+            Box::new(move |m, mut p| {
+                p += delta_p;
+                if let Some(value) = m.read() {
+                    m.tape.set(p, value);
+                }
+                rest(m, p)
+            })
+        }
+        BigInsn::WriteBytes(bytes) => {
+            let rest = compile_fused(program, 0);
+            let bytes = bytes.clone();
+
+            Box::new(move |m, mut p| {
+                p += delta_p;
+                for &byte in &bytes {
+                    m.output.write(byte);
+                }
+                rest(m, p)
+            })
+        }
+        BigInsn::Snapshot { cells, pointer } => {
+            let rest = compile_fused(program, *pointer);
+            let cells = cells.clone();
+
+            Box::new(move |m, mut p| {
+                p += delta_p;
+                for &(offset, value) in &cells {
+                    m.tape.set(p + offset, value);
+                }
+                rest(m, p)
+            })
+        }
+        BigInsn::Seek(stride) => {
+            let rest = compile_fused(program, 0);
+            let stride = *stride;
+
+            Box::new(move |m, p| {
+                let p = m.seek(p + delta_p, stride);
+                rest(m, p)
+            })
+        }
+        BigInsn::Loop(body) => {
+            let rest = compile_fused(program, 0);
+            let steps = fused_iteration_steps(body);
+            let inner = compile_fused(body, 0);
+            Box::new(move |m, mut p| {
+                p += delta_p;
+                while m.tape.get(p) != 0 {
+                    m.back_edge(p, steps);
+                    p = inner(m, p);
+                }
+                rest(m, p)
+            })
+        }
+    }
+}
+
+/// Executes a program that was previously parsed
+// This is the original code, available as the `tree` backend
+fn run(instructions: &[Instruction], m: &mut Machine, data_pointer: &mut usize) {
+    for instr in instructions {
+        // Wrapped around below zero the pointer is still the right i32
+        let p = *data_pointer as i32;
+        match instr {
+            Instruction::IncrementPointer => *data_pointer = data_pointer.wrapping_add(1),
+            Instruction::DecrementPointer => *data_pointer = data_pointer.wrapping_sub(1),
+            Instruction::Increment => m.tape.set(p, m.tape.get(p).wrapping_add(1)),
+            Instruction::Decrement => m.tape.set(p, m.tape.get(p).wrapping_sub(1)),
+            Instruction::Write => m.output.write(m.tape.get(p)),
+            Instruction::Read => {
+                if let Some(value) = m.read() {
+                    m.tape.set(p, value);
+                }
+            }
+            Instruction::Loop(nested_instructions) => {
+                let steps = iteration_steps(nested_instructions);
+                while m.tape.get(*data_pointer as i32) != 0 {
+                    m.back_edge(*data_pointer as i32, steps);
+                    run(nested_instructions, m, data_pointer)
+                }
+            }
+            Instruction::WriteBytes(bytes) => {
+                for &byte in bytes {
+                    m.output.write(byte);
+                }
+            }
+            Instruction::Snapshot { cells, pointer } => {
+                for &(offset, value) in cells {
+                    m.tape.set(p + offset, value);
+                }
+                *data_pointer = (*data_pointer as isize + *pointer as isize) as usize;
+            }
+        }
+    }
+}
+
+/// Executes the fused form of a program, returning the final data pointer
+fn run_big(program: &[BigInsn], m: &mut Machine, p: i32) -> i32 {
+    m.enter(p, fused_straight_steps(program));
+    run_fused(program, m, p)
+}
+
+fn run_fused(program: &[BigInsn], m: &mut Machine, mut p: i32) -> i32 {
+    for insn in program {
+        match insn {
+            BigInsn::Move(n) => p += n,
+            BigInsn::Adj(n) => {
+                m.tape.set(p, m.tape.get(p).wrapping_add(*n as u8));
+            }
+            BigInsn::Set(value) => m.tape.set(p, *value),
+            BigInsn::Clear => m.tape.set(p, 0),
+            BigInsn::MulAdd(targets) => {
+                let count = m.tape.get(p);
+                if count != 0 {
+                    for &(offset, k) in targets {
+                        let cell = m.tape.get(p + offset);
+                        m.tape
+                            .set(p + offset, cell.wrapping_add(count.wrapping_mul(k as u8)));
+                    }
+                }
+            }
+            BigInsn::Seek(stride) => p = m.seek(p, *stride),
+            BigInsn::Write => m.output.write(m.tape.get(p)),
+            BigInsn::Read => {
+                if let Some(value) = m.read() {
+                    m.tape.set(p, value);
+                }
+            }
+            BigInsn::Loop(body) => {
+                let steps = fused_iteration_steps(body);
+                while m.tape.get(p) != 0 {
+                    m.back_edge(p, steps);
+                    p = run_fused(body, m, p);
+                }
+            }
+            BigInsn::WriteBytes(bytes) => {
+                for &byte in bytes {
+                    m.output.write(byte);
+                }
+            }
+            BigInsn::Snapshot { cells, pointer } => {
+                for &(offset, value) in cells {
+                    m.tape.set(p + offset, value);
+                }
+                p += pointer;
+            }
+        }
+    }
+
+    p
+}
+
+/// Why `run_program`, `interpret` or an `Interpreter` couldn't run a program
+#[derive(Debug, Clone, PartialEq)]
+pub enum RunError {
+    Parse(ParseError),
+    StepLimit(StepLimitExceeded),
+    TimeLimit(TimeLimitExceeded),
+    OutOfBounds(OutOfBounds),
+    CellOverflow(CellOverflow),
+    Input(InputError),
+    Output(OutputError),
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RunError::Parse(e) => e.fmt(f),
+            RunError::StepLimit(e) => e.fmt(f),
+            RunError::TimeLimit(e) => e.fmt(f),
+            RunError::OutOfBounds(e) => e.fmt(f),
+            RunError::CellOverflow(e) => e.fmt(f),
+            RunError::Input(e) => e.fmt(f),
+            RunError::Output(e) => e.fmt(f),
+        }
+    }
+}
+
+impl error::Error for RunError {}
+
+impl From<ParseError> for RunError {
+    fn from(e: ParseError) -> RunError {
+        RunError::Parse(e)
+    }
+}
+
+/// Runs a program on a blank tape with the default backend, reading `,` from
+/// `input` byte by byte and writing every `.` to `output` as is
+pub fn run_program(
+    source: &str,
+    input: impl BfInput + Send + 'static,
+    output: impl BfOutput + Send + 'static,
+) -> Result<(), RunError> {
+    let program = parse(lex(source.to_string()))?;
+    let program = fold_constant_prefix(program, tape_bounds());
+
+    let mut machine = Machine::new(
+        Tape::new(vec![0; TAPE_SIZE]),
+        Input::new(InputMode::Byte, RangePolicy::Error, Box::new(input)),
+        Output::new(OutputMode::Char, Box::new(Buffered::new(output))),
+    );
+    catch_stop(|| {
+        BACKENDS[0].run(&program, &mut machine, START_POINTER as i32);
+        machine.output.finish();
+    })
+}
+
+/// Settings for `interpret`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RunOptions {
+    /// Cells on the tape at first, the data pointer starts in the middle
+    pub tape_size: usize,
+    pub tape_policy: TapePolicy,
+    /// Anything but 8-bit cells that wrap runs on `cell::run`, whatever `opt`
+    /// says
+    pub cell_width: CellWidth,
+    pub overflow: Overflow,
+    pub eof: EofBehavior,
+    /// Fail with `RunError::StepLimit` after this many steps
+    pub step_limit: Option<u64>,
+    /// Fail with `RunError::TimeLimit` after running this long
+    pub time_limit: Option<Duration>,
+    pub opt: OptLevel,
+}
+
+impl Default for RunOptions {
+    fn default() -> RunOptions {
+        RunOptions {
+            tape_size: TAPE_SIZE,
+            tape_policy: TapePolicy::Grow,
+            cell_width: CellWidth::U8,
+            overflow: Overflow::Wrap,
+            eof: EofBehavior::Unchanged,
+            step_limit: None,
+            time_limit: None,
+            opt: OptLevel::Full,
+        }
+    }
+}
+
+/// Runs a program to completion on a blank tape, reading `,` from `input`
+/// and returning every byte `.` wrote, without going near the real stdin
+/// and stdout. Meant for property tests and fuzzing, e.g. comparing the
+/// output at different `OptLevel`s.
+///
+/// The step limit and the tape's bounds are enforced by unwinding, so they
+/// only come back as errors where panics unwind.
+pub fn interpret(source: &str, input: &[u8], opts: RunOptions) -> Result<Vec<u8>, RunError> {
+    let mut program = parse(lex(source.to_string()))?;
+
+    // Folding works out 8-bit cells that wrap, and would take steps the
+    // limits don't see
+    let plain = cell::is_plain(opts.cell_width, opts.overflow);
+    let limited = opts.step_limit.is_some() || opts.time_limit.is_some();
+    let data_pointer = opts.tape_size / 2;
+    if opts.opt == OptLevel::Full && plain && !limited {
+        let bounds = -(data_pointer as i32)..(opts.tape_size - data_pointer) as i32;
+        program = fold_constant_prefix(program, bounds);
+    }
+
+    let input = Input::new(
+        InputMode::Byte,
+        RangePolicy::Error,
+        Box::new(io::Cursor::new(input.to_vec())),
+    );
+    let mut machine = Machine {
+        step_limit: opts.step_limit,
+        time_limit: opts.time_limit.map(TimeLimit::start),
+        ..Machine::new(
+            Tape::new(vec![0; opts.tape_size]).with_policy(opts.tape_policy),
+            input.on_eof(opts.eof),
+            Output::new(OutputMode::Char, Box::new(io::sink())).record(),
+        )
+    };
+
+    catch_stop(|| match plain {
+        true => backend::run_at(
+            opts.opt,
+            BACKENDS[0],
+            &program,
+            &mut machine,
+            data_pointer as i32,
+        ),
+        false => cell::run_width(
+            opts.cell_width,
+            opts.overflow,
+            &program,
+            &vec![0; opts.tape_size],
+            opts.tape_policy,
+            &mut machine,
+            data_pointer as i32,
+        ),
+    })?;
+
+    Ok(machine.output.take_recording().unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read};
+
+    use super::*;
+
+    /// A program of `len` bytes on one line, made up as it is read, that
+    /// remembers the most it was ever asked for at once
+    struct OneLine {
+        len: usize,
+        at: usize,
+        largest_read: usize,
+    }
+
+    impl Read for OneLine {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.largest_read = self.largest_read.max(buf.len());
+            let n = buf.len().min(self.len - self.at);
+            for (i, byte) in buf[..n].iter_mut().enumerate() {
+                *byte = if (self.at + i).is_multiple_of(64) {
+                    b'+'
+                } else {
+                    b'x'
+                };
+            }
+            self.at += n;
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn lexing_a_huge_line_reads_it_in_small_pieces() {
+        let mut source = OneLine {
+            len: 10 << 20,
+            at: 0,
+            largest_read: 0,
+        };
+        let opcodes = lex_reader(&mut source).unwrap();
+        assert_eq!(opcodes.len(), (10 << 20) / 64);
+        assert!(source.largest_read <= 8192, "read {}", source.largest_read);
+    }
+
+    /// A blank machine with no input that keeps what the program writes
+    fn machine() -> Machine {
+        Machine::new(
+            Tape::new(vec![0; TAPE_SIZE]),
+            Input::new(InputMode::Byte, RangePolicy::Error, Box::new(io::empty())),
+            Output::new(OutputMode::Char, Box::new(io::sink())).record(),
+        )
+    }
+
+    fn program(source: &str) -> Vec<Instruction> {
+        parse(lex(source.to_string())).unwrap()
+    }
+
+    /// A compiled program kept for later, with nothing of its source left
+    struct Stored {
+        code: Code,
+    }
+
+    impl Stored {
+        fn new(source: &str) -> Stored {
+            Stored {
+                code: compile_owned(&program(source)),
+            }
+        }
+    }
+
+    #[test]
+    fn a_program_compiled_owned_can_be_stored_and_rerun() {
+        let stored = Stored::new("+++[>++<-]>.");
+        for _ in 0..2 {
+            let mut machine = machine();
+            let p = (stored.code)(&mut machine, START_POINTER as i32);
+            assert_eq!(p, START_POINTER as i32 + 1);
+            assert_eq!(machine.output.take_recording().unwrap(), [6]);
+        }
+    }
+
+    /// The machine after running `source` from the start on every backend
+    fn on_every_backend(source: &str, check: &dyn Fn(&str, Machine)) {
+        for &backend in BACKENDS {
+            let mut machine = machine();
+            backend.run(&program(source), &mut machine, START_POINTER as i32);
+            check(backend.name(), machine);
+        }
+    }
+
+    #[test]
+    fn cells_wrap_on_the_way_down() {
+        on_every_backend(&"-".repeat(256), &|backend, machine| {
+            assert_eq!(machine.tape.get(START_POINTER as i32), 0, "{}", backend);
+        });
+        on_every_backend("-", &|backend, machine| {
+            assert_eq!(machine.tape.get(START_POINTER as i32), 255, "{}", backend);
+        });
+    }
+
+    #[test]
+    fn a_value_over_127_is_written_as_one_byte() {
+        on_every_backend(&("+".repeat(200) + "."), &|backend, mut machine| {
+            let written = machine.output.take_recording().unwrap();
+            assert_eq!(written, [200], "{}", backend);
+        });
+    }
+
+    #[test]
+    fn a_seek_stops_on_the_first_zero() {
+        let program = raise_abstraction(&program("[>]"));
+        assert_eq!(program, [BigInsn::Seek(1)]);
+
+        let mut machine = machine();
+        let start = START_POINTER as i32;
+        for p in start..start + 3 {
+            machine.tape.set(p, 1);
+        }
+        assert_eq!(run_big(&program, &mut machine, start), start + 3);
+    }
+
+    #[test]
+    fn stride_two_loops_end_where_the_plain_interpreter_does() {
+        // Six ones on every other cell, with the pointer back on the first
+        let setup = "+>>+>>+>>+>>+>>+<<<<<<<<<<";
+        for body in &["[>>]", "[->>]", "[>>]<<[<<]", "[-<+>>>]"] {
+            let source = setup.to_string() + body;
+            let tree = BACKENDS
+                .iter()
+                .find(|backend| backend.name() == "tree")
+                .unwrap();
+            let mut expected = machine();
+            let end = tree.run(&program(&source), &mut expected, START_POINTER as i32);
+
+            for &backend in BACKENDS {
+                let mut machine = machine();
+                let at = backend.run(&program(&source), &mut machine, START_POINTER as i32);
+                assert_eq!(at, end, "{} on {}", backend.name(), body);
+                for p in START_POINTER as i32 - 4..START_POINTER as i32 + 16 {
+                    assert_eq!(
+                        machine.tape.get(p),
+                        expected.tape.get(p),
+                        "cell {} after {} on {}",
+                        p,
+                        body,
+                        backend.name()
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn loops_nest_as_deep_as_the_limit_and_no_deeper() {
+        let nested = |depth| "[".repeat(depth) + &"]".repeat(depth);
+        // On a stack the size of the main thread's, which the limit is for
+        let parsed = std::thread::Builder::new()
+            .stack_size(8 << 20)
+            .spawn(move || parse(lex(nested(MAX_NESTING))).is_ok())
+            .unwrap()
+            .join()
+            .unwrap();
+        assert!(parsed);
+        assert_eq!(
+            parse(lex(nested(MAX_NESTING + 1))),
+            Err(ParseError::TooDeep(MAX_NESTING))
+        );
+    }
+}
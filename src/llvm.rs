@@ -0,0 +1,215 @@
+use std::fmt::Write;
+
+use input::EofBehavior;
+use BigInsn;
+
+/// Lowers a program to textual LLVM IR for a standalone executable. The tape
+/// is a global array initialized from `tape`, the data pointer is an index
+/// held in a stack slot, and I/O goes through `putchar`/`getchar`. Unlike the
+/// interpreter's tape the array doesn't grow.
+///
+/// Pointers are opaque `ptr`s, which LLVM reads from version 15 on, and
+/// version 14 with `-opaque-pointers`.
+pub fn emit(program: &[BigInsn], tape: &[u8], data_pointer: usize, eof: EofBehavior) -> String {
+    let mut emitter = Emitter {
+        out: String::new(),
+        tape_type: format!("[{} x i8]", tape.len()),
+        eof,
+        temps: 0,
+        loops: 0,
+    };
+
+    emitter.header(tape);
+    writeln!(emitter.out, "define i32 @main() {{").unwrap();
+    writeln!(emitter.out, "entry:").unwrap();
+    writeln!(emitter.out, "  %ptr = alloca i64").unwrap();
+    writeln!(emitter.out, "  store i64 {}, ptr %ptr", data_pointer).unwrap();
+    emitter.block(program);
+    writeln!(emitter.out, "  ret i32 0").unwrap();
+    writeln!(emitter.out, "}}").unwrap();
+
+    emitter.out
+}
+
+struct Emitter {
+    out: String,
+    tape_type: String,
+    eof: EofBehavior,
+    temps: usize,
+    loops: usize,
+}
+
+impl Emitter {
+    fn header(&mut self, tape: &[u8]) {
+        let init = if tape.iter().all(|&cell| cell == 0) {
+            "zeroinitializer".to_string()
+        } else {
+            let mut init = String::from("c\"");
+            for cell in tape {
+                write!(init, "\\{:02X}", cell).unwrap();
+            }
+            init.push('"');
+            init
+        };
+
+        writeln!(
+            self.out,
+            "@tape = internal global {} {}",
+            self.tape_type, init
+        )
+        .unwrap();
+        writeln!(self.out).unwrap();
+        writeln!(self.out, "declare i32 @putchar(i32)").unwrap();
+        writeln!(self.out, "declare i32 @getchar()").unwrap();
+        writeln!(self.out).unwrap();
+    }
+
+    fn temp(&mut self) -> String {
+        self.temps += 1;
+        format!("%t{}", self.temps)
+    }
+
+    /// Address of the cell `offset` away from the data pointer
+    fn cell(&mut self, offset: i32) -> String {
+        let index = self.temp();
+        writeln!(self.out, "  {} = load i64, ptr %ptr", index).unwrap();
+
+        let index = if offset != 0 {
+            let moved = self.temp();
+            writeln!(self.out, "  {} = add i64 {}, {}", moved, index, offset).unwrap();
+            moved
+        } else {
+            index
+        };
+
+        let address = self.temp();
+        writeln!(
+            self.out,
+            "  {} = getelementptr inbounds {}, ptr @tape, i64 0, i64 {}",
+            address, self.tape_type, index
+        )
+        .unwrap();
+        address
+    }
+
+    fn load(&mut self, address: &str) -> String {
+        let value = self.temp();
+        writeln!(self.out, "  {} = load i8, ptr {}", value, address).unwrap();
+        value
+    }
+
+    fn putchar(&mut self, value: &str) {
+        let wide = self.temp();
+        writeln!(self.out, "  {} = zext i8 {} to i32", wide, value).unwrap();
+        let result = self.temp();
+        writeln!(self.out, "  {} = call i32 @putchar(i32 {})", result, wide).unwrap();
+    }
+
+    fn block(&mut self, program: &[BigInsn]) {
+        for insn in program {
+            self.insn(insn);
+        }
+    }
+
+    fn insn(&mut self, insn: &BigInsn) {
+        match insn {
+            BigInsn::Move(n) => {
+                let old = self.temp();
+                let new = self.temp();
+                writeln!(self.out, "  {} = load i64, ptr %ptr", old).unwrap();
+                writeln!(self.out, "  {} = add i64 {}, {}", new, old, n).unwrap();
+                writeln!(self.out, "  store i64 {}, ptr %ptr", new).unwrap();
+            }
+            BigInsn::Adj(n) => {
+                let address = self.cell(0);
+                let old = self.load(&address);
+                let new = self.temp();
+                writeln!(self.out, "  {} = add i8 {}, {}", new, old, *n as i8).unwrap();
+                writeln!(self.out, "  store i8 {}, ptr {}", new, address).unwrap();
+            }
+            BigInsn::Set(value) => {
+                let address = self.cell(0);
+                writeln!(self.out, "  store i8 {}, ptr {}", *value as i8, address).unwrap();
+            }
+            BigInsn::Clear => {
+                let address = self.cell(0);
+                writeln!(self.out, "  store i8 0, ptr {}", address).unwrap();
+            }
+            BigInsn::MulAdd(targets) => {
+                let address = self.cell(0);
+                let count = self.load(&address);
+                for &(offset, k) in targets {
+                    let address = self.cell(offset);
+                    let old = self.load(&address);
+                    let product = self.temp();
+                    let new = self.temp();
+                    writeln!(self.out, "  {} = mul i8 {}, {}", product, count, k as i8).unwrap();
+                    writeln!(self.out, "  {} = add i8 {}, {}", new, old, product).unwrap();
+                    writeln!(self.out, "  store i8 {}, ptr {}", new, address).unwrap();
+                }
+            }
+            BigInsn::Seek(stride) => self.insn(&BigInsn::Loop(vec![BigInsn::Move(*stride)])),
+            BigInsn::Write => {
+                let address = self.cell(0);
+                let value = self.load(&address);
+                self.putchar(&value);
+            }
+            BigInsn::Read => {
+                let address = self.cell(0);
+                let old = match self.eof {
+                    EofBehavior::Unchanged => self.load(&address),
+                    EofBehavior::Zero => "0".to_string(),
+                    EofBehavior::Max => "-1".to_string(),
+                };
+                let input = self.temp();
+                let byte = self.temp();
+                let eof = self.temp();
+                let new = self.temp();
+                writeln!(self.out, "  {} = call i32 @getchar()", input).unwrap();
+                writeln!(self.out, "  {} = trunc i32 {} to i8", byte, input).unwrap();
+                writeln!(self.out, "  {} = icmp eq i32 {}, -1", eof, input).unwrap();
+                writeln!(
+                    self.out,
+                    "  {} = select i1 {}, i8 {}, i8 {}",
+                    new, eof, old, byte
+                )
+                .unwrap();
+                writeln!(self.out, "  store i8 {}, ptr {}", new, address).unwrap();
+            }
+            BigInsn::Loop(body) => {
+                self.loops += 1;
+                let label = format!("loop{}", self.loops);
+
+                writeln!(self.out, "  br label %{}.head", label).unwrap();
+                writeln!(self.out, "{}.head:", label).unwrap();
+                let address = self.cell(0);
+                let value = self.load(&address);
+                let nonzero = self.temp();
+                writeln!(self.out, "  {} = icmp ne i8 {}, 0", nonzero, value).unwrap();
+                writeln!(
+                    self.out,
+                    "  br i1 {}, label %{}.body, label %{}.end",
+                    nonzero, label, label
+                )
+                .unwrap();
+
+                writeln!(self.out, "{}.body:", label).unwrap();
+                self.block(body);
+                writeln!(self.out, "  br label %{}.head", label).unwrap();
+                writeln!(self.out, "{}.end:", label).unwrap();
+            }
+            BigInsn::WriteBytes(bytes) => {
+                for byte in bytes {
+                    self.putchar(&(*byte as i8).to_string());
+                }
+            }
+            BigInsn::Snapshot { cells, pointer } => {
+                for &(offset, value) in cells {
+                    let address = self.cell(offset);
+                    writeln!(self.out, "  store i8 {}, ptr {}", value as i8, address).unwrap();
+                }
+                self.insn(&BigInsn::Move(*pointer));
+            }
+        }
+    }
+}
@@ -1,243 +1,1913 @@
+extern crate bf;
+
+use std::any::Any;
 use std::env;
-use std::fs::File;
-use std::io::Read;
-
-/// Opcodes determined by the lexer
-#[derive(Debug, Clone)]
-enum OpCode {
-    IncrementPointer,
-    DecrementPointer,
-    Increment,
-    Decrement,
-    Write,
-    Read,
-    LoopBegin,
-    LoopEnd,
-}
-
-#[derive(Debug, Clone, PartialEq)]
-enum Instruction {
-    IncrementPointer,
-    DecrementPointer,
-    Increment,
-    Decrement,
-    Write,
-    Read,
-    Loop(Vec<Instruction>),
-}
-
-/// Lexer turns the source code into a sequence of opcodes
-fn lex(source: String) -> Vec<OpCode> {
-    let mut operations = Vec::new();
-
-    for symbol in source.chars() {
-        let op = match symbol {
-            '>' => Some(OpCode::IncrementPointer),
-            '<' => Some(OpCode::DecrementPointer),
-            '+' => Some(OpCode::Increment),
-            '-' => Some(OpCode::Decrement),
-            '.' => Some(OpCode::Write),
-            ',' => Some(OpCode::Read),
-            '[' => Some(OpCode::LoopBegin),
-            ']' => Some(OpCode::LoopEnd),
-            _ => None,
-        };
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+use bf::analysis::reads_input;
+use bf::backend::{self, Backend, BACKENDS};
+use bf::bench::{self, BenchOptions};
+use bf::bfc::Compiled;
+use bf::brainfuck;
+use bf::bytecode;
+use bf::c;
+use bf::cell::{self, CellOverflow, CellWidth, Overflow};
+use bf::checkpoint::Checkpoint;
+use bf::conformance;
+use bf::debug::{Debugger, Stop};
+use bf::fold::{drop_final_snapshot, fold_constant_prefix};
+use bf::format::{self, Style};
+use bf::frontend::TokenMap;
+use bf::generate;
+use bf::input::{EofBehavior, Input, InputError, InputMode, RangePolicy};
+#[cfg(feature = "json")]
+use bf::json;
+#[cfg(feature = "llvm")]
+use bf::llvm;
+use bf::output::{Flushing, Output, OutputError, OutputMode, Sink, Tee};
+use bf::pbrain;
+use bf::profile;
+use bf::rust;
+use bf::seed::Seed;
+use bf::sexpr;
+use bf::tape::{OutOfBounds, Tape, TapePolicy};
+use bf::terminal::RawMode;
+use bf::trace::{TraceFilter, TraceFormat, Tracer};
+use bf::visualize;
+use bf::wasm;
+use bf::{
+    fuse, lower_at, parse_file, parse_source, BfError, BigInsn, Instruction, Interpreter, Location,
+    Machine, OptLevel, ParseError, Program, RunError, StepLimitExceeded, TimeLimit,
+    TimeLimitExceeded, TAPE_SIZE,
+};
+
+/// How long a program gets under --sandbox, unless --timeout says
+const SANDBOX_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What to do with the program
+#[derive(Clone, Copy, PartialEq)]
+enum Command {
+    Run,
+    Build,
+    Debug,
+    Dump,
+    Fmt,
+    Repl,
+    Selftest,
+    Gen,
+    Bench,
+}
+
+/// Which form of the program `bf dump` prints
+#[derive(Clone, Copy, PartialEq)]
+enum Stage {
+    /// The tree of `Instruction`s, as parsed and folded
+    Ast,
+    /// The `BigInsn` form, as far as `--opt` lowers it
+    Ir,
+    /// What the bytecode backend runs
+    Bytecode,
+}
+
+/// How `bf dump` prints it
+#[derive(Clone, Copy, PartialEq)]
+enum DumpFormat {
+    Debug,
+    Json,
+    Sexpr,
+}
+
+/// Which language the program is in
+#[derive(Clone, PartialEq)]
+enum Dialect {
+    Bf,
+    /// Brainfuck with procedures, see `bf::pbrain`
+    Pbrain,
+    /// Brainfuck with its commands spelled otherwise, like Ook!
+    Tokens(TokenMap),
+}
+
+fn usage() -> ! {
+    println!("usage: bf [COMMAND] [options] <file.bf>");
+    println!("       bf [COMMAND] [options] -c <program>");
+    println!("       bf --list-backends");
+    println!("       bf repl [options]");
+    println!("       bf gen [--text TEXT] [-o FILE]");
+    println!("       bf --conformance");
+    println!();
+    println!("commands:");
+    println!("  run                              run the program, the default");
+    println!("  build                            translate the program, to C unless --emit");
+    println!("                                   says otherwise");
+    println!("  debug                            step through the program, with breakpoints");
+    println!("                                   and '#' stopping it");
+    println!("  dump                             print the program as the optimizer left it,");
+    println!("                                   see --stage and --format");
+    println!("  fmt                              print the program without its comments, see");
+    println!("                                   --style");
+    println!("  repl                             run lines as they are typed, on one tape");
+    println!("  selftest                         run the program on every backend and report");
+    println!("                                   where they disagree");
+    println!("  gen                              print a program that prints --text, or else");
+    println!("                                   what is read from stdin");
+    println!("  bench                            time the program on every backend and print");
+    println!("                                   a table, see --runs and --warmup");
+    println!();
+    println!("options, also written --name=value:");
+    println!("  --backend NAME                   execution backend, see --list-backends");
+    println!("  --jit                            compile to machine code, same as --backend jit");
+    println!("  --opt none|basic|full            how much to optimize, below full the backend");
+    println!("                                   is fixed (default full)");
+    println!("  --input-mode byte|numeric        how ',' reads its input");
+    println!("  --input-range truncate|error|saturate");
+    println!("                                   numeric input that doesn't fit a cell");
+    println!("  --eof unchanged|zero|neg1        what ',' stores at end of input, neg1 being");
+    println!("                                   -1, or 255 (default unchanged)");
+    println!("  --output-mode char|hex|hexdump   how '.' presents its output");
+    println!("  --input FILE                     read ',' from FILE instead of stdin");
+    println!("  --input-str TEXT                 read ',' from TEXT instead of stdin");
+    println!("  --bang-input                     read ',' from after the first '!' in the");
+    println!("                                   source, which ends the program there");
+    println!("  --output FILE                    write '.' to FILE instead of stdout");
+    println!("  --interactive                    read ',' a keypress at a time, without echo,");
+    println!("                                   and show every '.' as soon as it is written,");
+    println!("                                   with the terminal feature on Unix");
+    println!("  --tee FILE                       also write the output to FILE");
+    println!("  --feedback                       run again with the first run's output as input");
+    println!("  --quine-check                    verify the output equals the program source");
+    println!("  --tape-size N                    cells on the tape at first, the pointer starts");
+    println!(
+        "                                   in the middle (default {})",
+        TAPE_SIZE
+    );
+    println!("  --dialect bf|pbrain|ook|blub|tokens:FILE");
+    println!("                                   the language of the program, pbrain adding");
+    println!("                                   procedures defined by ( ) and called by :, and");
+    println!("                                   FILE giving each command's token (default by");
+    println!("                                   extension, .ook and .blub, else bf)");
+    println!("  --cell-size 8|16|32              bits in a cell (default 8)");
+    println!("  --cell-overflow wrap|trap        what '+' on the largest value and '-' on zero");
+    println!("                                   do (default wrap)");
+    println!("                                   Any cells but 8 bits that wrap are run as");
+    println!("                                   parsed, on an interpreter of their own, so");
+    println!("                                   not with --backend, --opt, --emit,");
+    println!("                                   --profile, --dump-ir, --visualize, --trace,");
+    println!("                                   --coverage or commands but run and fmt");
+    println!("  --tape-policy grow|wrap|error    what going off the end of the tape does");
+    println!("                                   (default grow)");
+    println!("  --seed-tape CELLS                initial cells, e.g. 1,2,@here:3,4");
+    println!("  --start-at LABEL                 start on a labelled seed cell");
+    println!("  --emit llvm|rust|c|wasm|bf|bfc|ast-json");
+    println!("                                   print LLVM IR, Rust or C source, a WebAssembly");
+    println!("                                   module, the optimized program as Brainfuck or");
+    println!("                                   bytecode to run later, or the parsed program");
+    println!("                                   (llvm-ir is another name for llvm, and its");
+    println!("                                   opaque pointers need LLVM 15 or later, or 14");
+    println!("                                   with -opaque-pointers)");
+    println!("  -o FILE                          write what --emit prints to FILE instead, for");
+    println!("                                   bf build bytecode if FILE ends in .bfc");
+    println!("  --max-steps N                    stop with exit status 2 after N steps");
+    println!("  --timeout SECS                   stop with exit status 2 after SECS seconds");
+    println!("  --checkpoint-every N             save the run's state every N commands, to be");
+    println!("                                   picked up again with --resume");
+    println!("  --checkpoint FILE                where to save it (default the --resume file or");
+    println!("                                   the program's name with .bfstate added)");
+    println!("  --resume FILE                    carry on a run from a checkpoint, given the");
+    println!("                                   same program and input");
+    println!("  --sandbox                        for untrusted programs: keep the tape to");
+    println!("                                   --tape-size, and time out after 10 seconds");
+    println!("                                   unless --timeout says otherwise");
+    println!("  --dump-ir                        print the BigInsn form to stderr before running");
+    println!("  --profile                        run the program as written, instrumented, and");
+    println!("                                   print its hottest loops to stderr");
+    println!("  --coverage                       run the program as written and print its");
+    println!("                                   source to stderr, with how often every line ran");
+    println!("                                   and the commands that never did marked");
+    println!("  --lcov FILE                      run it so and write the counts to FILE as an");
+    println!("                                   lcov tracefile");
+    println!("  --visualize                      show the program running, a command at a time");
+    println!("  --speed MS                       time per command under --visualize, or 0 to");
+    println!("                                   wait for Enter (default 100)");
+    println!("  --trace                          log every command run, and the pointer and cell");
+    println!("                                   after it, to stderr");
+    println!("  --trace-filter io,loops,all      which commands --trace logs: ',' and '.', '['");
+    println!("                                   and ']', or all of them (default all)");
+    println!("  --trace-format text|json         one line of text per command, or a JSON object");
+    println!("                                   (default text)");
+    println!("  --style minify|pretty|strip      how fmt prints the program: on one line, with");
+    println!("                                   loops indented, or on its own lines without");
+    println!("                                   comments (default minify)");
+    println!("  --stage ast|ir|bytecode          what dump prints: the parsed tree, the BigInsn");
+    println!("                                   form or bytecode (default ir, or ast with --opt");
+    println!("                                   none)");
+    println!("  --format debug|json|sexpr        how dump prints it (default debug)");
+    println!("  --text TEXT                      what the program from gen prints");
+    println!("  --width N                        break the lines of fmt and gen after N columns");
+    println!("  --runs N                         timed runs per backend for bench (default 5)");
+    println!("  --warmup N                       untimed runs before them (default 1)");
+    process::exit(1);
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("bf: {}", message);
+    process::exit(1);
+}
+
+#[cfg(feature = "json")]
+fn load_json(file: File) -> Vec<Instruction> {
+    json::from_json(io::BufReader::new(file)).unwrap_or_else(|e| fail(&e))
+}
+
+#[cfg(feature = "json")]
+fn emit_ast_json(program: &[Instruction]) -> String {
+    json::to_json(program) + "\n"
+}
 
-        // Non-opcode characters are simply comments
-        if let Some(op) = op {
-            operations.push(op);
+#[cfg(not(feature = "json"))]
+fn load_json(_file: File) -> Vec<Instruction> {
+    unreachable!("JSON programs need the json feature")
+}
+
+#[cfg(not(feature = "json"))]
+fn emit_ast_json(_program: &[Instruction]) -> String {
+    fail("can't emit 'ast-json' in this build");
+}
+
+#[cfg(feature = "json")]
+fn dump_json(program: &[Instruction], stage: Stage, opt: OptLevel) -> String {
+    let json = match stage {
+        Stage::Ast => json::to_json(program),
+        Stage::Ir => json::lowered_to_json(&lower_at(program, opt)),
+        Stage::Bytecode => json::bytecode_to_json(&bytecode::compile(&lower_at(program, opt))),
+    };
+    json + "\n"
+}
+
+#[cfg(not(feature = "json"))]
+fn dump_json(_program: &[Instruction], _stage: Stage, _opt: OptLevel) -> String {
+    fail("can't dump JSON in this build");
+}
+
+/// What `bf dump` prints for a stage of the program
+fn dump(program: &[Instruction], stage: Stage, format: DumpFormat, opt: OptLevel) -> String {
+    match (format, stage) {
+        (DumpFormat::Json, _) => dump_json(program, stage, opt),
+        (DumpFormat::Debug, Stage::Ast) => format!("{:?}\n", program),
+        (DumpFormat::Debug, Stage::Ir) => format!("{:?}\n", lower_at(program, opt)),
+        (DumpFormat::Debug, Stage::Bytecode) => {
+            format!("{:?}\n", bytecode::compile(&lower_at(program, opt)))
+        }
+        (DumpFormat::Sexpr, Stage::Ast) => sexpr::from_instructions(program),
+        (DumpFormat::Sexpr, Stage::Ir) => sexpr::from_lowered(&lower_at(program, opt)),
+        (DumpFormat::Sexpr, Stage::Bytecode) => {
+            sexpr::from_bytecode(&bytecode::compile(&lower_at(program, opt)))
         }
     }
+}
 
-    operations
+#[cfg(feature = "llvm")]
+fn emit_llvm(program: &[BigInsn], tape: &[u8], data_pointer: usize, eof: EofBehavior) -> String {
+    llvm::emit(program, tape, data_pointer, eof)
 }
 
-fn parse(opcodes: Vec<OpCode>) -> Vec<Instruction> {
-    let mut program: Vec<Instruction> = Vec::new();
-    let mut loop_stack = 0;
-    let mut loop_start = 0;
+#[cfg(not(feature = "llvm"))]
+fn emit_llvm(
+    _program: &[BigInsn],
+    _tape: &[u8],
+    _data_pointer: usize,
+    _eof: EofBehavior,
+) -> String {
+    fail("can't emit 'llvm' in this build");
+}
 
-    for (i, op) in opcodes.iter().enumerate() {
-        if loop_stack == 0 {
-            let instr = match op {
-                OpCode::IncrementPointer => Some(Instruction::IncrementPointer),
-                OpCode::DecrementPointer => Some(Instruction::DecrementPointer),
-                OpCode::Increment => Some(Instruction::Increment),
-                OpCode::Decrement => Some(Instruction::Decrement),
-                OpCode::Write => Some(Instruction::Write),
-                OpCode::Read => Some(Instruction::Read),
+/// Reports a run that stopped by panicking like any other error and exits,
+/// running out of steps or time with a status of its own
+fn stopped(payload: Box<dyn Any + Send>, machine: &mut Machine) -> ! {
+    let (message, status) = if let Some(e) = payload.downcast_ref::<StepLimitExceeded>() {
+        (format!("resource limit exceeded ({})", e), 2)
+    } else if let Some(e) = payload.downcast_ref::<TimeLimitExceeded>() {
+        (format!("resource limit exceeded ({})", e), 2)
+    } else if let Some(e) = payload.downcast_ref::<OutOfBounds>() {
+        (e.to_string(), 1)
+    } else if let Some(e) = payload.downcast_ref::<CellOverflow>() {
+        (e.to_string(), 1)
+    } else if let Some(e) = payload.downcast_ref::<InputError>() {
+        (e.to_string(), 1)
+    } else if let Some(e) = payload.downcast_ref::<OutputError>() {
+        (e.to_string(), 1)
+    } else {
+        panic::resume_unwind(payload)
+    };
+    machine.output.finish();
+    eprintln!("bf: {}", message);
+    process::exit(status);
+}
 
-                OpCode::LoopBegin => {
-                    loop_start = i;
-                    loop_stack += 1;
-                    None
-                }
+/// Reports a run that stopped with an error and exits with the status
+/// `stopped` would have given it
+fn run_failed(e: &RunError) -> ! {
+    match e {
+        RunError::StepLimit(_) | RunError::TimeLimit(_) => {
+            eprintln!("bf: resource limit exceeded ({})", e);
+            process::exit(2);
+        }
+        _ => fail(&e.to_string()),
+    }
+}
 
-                OpCode::LoopEnd => panic!("loop ending at #{} has no beginning", i),
-            };
+/// Translates the lowered program for `--emit`, given the initial tape, data
+/// pointer and EOF behavior
+type Emitter = fn(&[BigInsn], &[u8], usize, EofBehavior) -> Vec<u8>;
+
+/// Writes what `--emit` produced to the `-o` file, or else to stdout
+fn write_emitted(emitted: &[u8], path: Option<&str>) {
+    let result = match path {
+        Some(path) => fs::write(path, emitted).map_err(|e| format!("can't write {}: {}", path, e)),
+        None => io::stdout()
+            .write_all(emitted)
+            .map_err(|e| format!("failed to write output: {}", e)),
+    };
+    result.unwrap_or_else(|e| fail(&e));
+}
+
+/// Checks that a program printed exactly its own source
+fn check_quine(source: &[u8], output: &[u8]) -> Result<(), String> {
+    match source.iter().zip(output).position(|(a, b)| a != b) {
+        Some(i) => Err(format!("output differs from the source at byte {}", i)),
+        None if source.len() != output.len() => Err(format!(
+            "output is {} bytes but the source is {}",
+            output.len(),
+            source.len()
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Prints the cells from `start` to `end`, counted from the starting cell,
+/// sixteen to a line with the one under the data pointer in brackets
+fn dump_tape(tape: &Tape, origin: i32, pointer: i32, start: i32, end: i32) {
+    let mut line = String::new();
 
-            if let Some(instr) = instr {
-                program.push(instr);
+    for cell in start..end {
+        if (cell - start) % 16 == 0 {
+            if !line.is_empty() {
+                println!("{}", line.trim_end());
             }
+            line = format!("{:>6}:", cell);
+        }
+        let value = match tape.peek(origin + cell) {
+            Some(value) => value.to_string(),
+            None => "-".to_string(),
+        };
+        if origin + cell == pointer {
+            line.push_str(&format!(" [{:>3}]", value));
         } else {
-            match op {
-                OpCode::LoopBegin => {
-                    loop_stack += 1;
+            line.push_str(&format!("  {:>3} ", value));
+        }
+    }
+
+    if !line.is_empty() {
+        println!("{}", line.trim_end());
+    }
+}
+
+/// Tells the user on stderr where the data pointer is, and which cells a
+/// line changed, counted from the starting cell
+fn show_changes(before: &Tape, after: &Tape, origin: i32, pointer: i32) {
+    const SHOWN: usize = 16;
+
+    let (a, b) = (before.range(), after.range());
+    let changed: Vec<i32> = (a.start.min(b.start)..a.end.max(b.end))
+        .filter(|&p| before.get(p) != after.get(p))
+        .collect();
+
+    let mut report = format!("pointer {}", pointer - origin);
+    if !changed.is_empty() {
+        report.push_str(", changed");
+        for &p in changed.iter().take(SHOWN) {
+            report.push_str(&format!(" {}:{}", p - origin, after.get(p)));
+        }
+        if changed.len() > SHOWN {
+            report.push_str(&format!(" and {} more", changed.len() - SHOWN));
+        }
+    }
+    eprintln!("{}", report);
+}
+
+/// Runs each line of stdin as a program of its own against one machine, so
+/// the tape and data pointer carry over from line to line, showing what
+/// changed after each one. A line that leaves a loop open carries on to the
+/// next ones until it is closed. Lines starting with ':' are commands
+/// instead.
+fn repl(mut interpreter: Interpreter, origin: i32) {
+    let interactive = io::stdin().is_terminal();
+    let mut line = String::new();
+    // Lines of a loop still open
+    let mut pending = String::new();
+
+    loop {
+        if interactive {
+            eprint!("{}", if pending.is_empty() { "bf> " } else { "..> " });
+        }
+        line.clear();
+        match io::stdin().lock().read_line(&mut line) {
+            Ok(0) => break,
+            Ok(_) => (),
+            Err(e) => fail(&format!("failed to read stdin: {}", e)),
+        }
+
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some(":quit") => break,
+            Some(":tape") => {
+                let mut bound = |default| match words.next().map(|n| n.parse()) {
+                    None => Some(default),
+                    Some(Ok(n)) => Some(n),
+                    Some(Err(_)) => None,
+                };
+                match (bound(0), bound(16)) {
+                    (Some(start), Some(end)) => dump_tape(
+                        interpreter.tape(),
+                        origin,
+                        interpreter.pointer(),
+                        start,
+                        end,
+                    ),
+                    _ => eprintln!("bf: usage: :tape [START [END]]"),
                 }
-                OpCode::LoopEnd => {
-                    loop_stack -= 1;
+            }
+            Some(":help") => {
+                println!(":tape [START [END]]  show cells, counted from the starting cell");
+                println!(":quit                leave, as does end of input");
+            }
+            Some(command) if command.starts_with(':') && pending.is_empty() => {
+                eprintln!("bf: unknown command '{}', try :help", command)
+            }
+            _ => {
+                pending.push_str(&line);
+                match parse_source("<stdin>", pending.as_bytes()) {
+                    Ok(program) if program.is_empty() => (),
+                    Ok(program) => {
+                        let before = interpreter.tape().clone();
+                        match interpreter.run(&Program::from_instructions(program)) {
+                            // Nothing more can be shown
+                            Err(e @ RunError::Output(_)) => run_failed(&e),
+                            Err(e) => eprintln!("bf: {}", e),
+                            Ok(()) => (),
+                        }
+                        show_changes(&before, interpreter.tape(), origin, interpreter.pointer());
+                    }
+                    Err(BfError::Parse {
+                        error: ParseError::UnmatchedBegin(_),
+                        ..
+                    }) => continue,
+                    Err(e) => eprintln!("bf: {}", e),
+                }
+                pending.clear();
+            }
+        }
+    }
+
+    if let Err(e) = interpreter.finish() {
+        run_failed(&e);
+    }
+}
+
+/// The source of the program, for what needs more than its commands, as
+/// Brainfuck if it is written with the tokens of a frontend. With
+/// `--bang-input` that is only what comes before the first `!`.
+fn read_source(
+    inline: &Option<String>,
+    filename: &Option<String>,
+    frontend: Option<&TokenMap>,
+    bang_input: bool,
+) -> Vec<u8> {
+    let mut source = match (inline, filename) {
+        (Some(source), _) => source.clone().into_bytes(),
+        (None, Some(filename)) => {
+            fs::read(filename).unwrap_or_else(|e| fail(&format!("{}: {}", filename, e)))
+        }
+        (None, None) => unreachable!(),
+    };
+    if bang_input {
+        source.truncate(bang(&source));
+    }
+    match frontend {
+        Some(tokens) => tokens.translate(&source),
+        None => source,
+    }
+}
+
+/// Where the input embedded in a `program!input` source starts, the first
+/// `!` or the end if there is none
+fn bang(source: &[u8]) -> usize {
+    source
+        .iter()
+        .position(|&byte| byte == b'!')
+        .unwrap_or(source.len())
+}
+
+/// Shows a line of the source with a caret under the column
+fn show_location(location: &Location) {
+    println!("{}:{}:", location.line, location.column);
+    println!("    {}", location.text);
+    let indent: String = location
+        .text
+        .chars()
+        .take(location.column - 1)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    println!("    {}^", indent);
+}
+
+/// Takes debugger commands from stdin until the user quits. The data
+/// pointer starts at `origin`, which tape addresses are counted from.
+fn debug(mut debugger: Debugger, origin: i32) {
+    let interactive = io::stdin().is_terminal();
+    let mut line = String::new();
 
-                    if loop_stack == 0 {
-                        program.push(Instruction::Loop(parse(
-                            opcodes[loop_start + 1..i].to_vec(),
-                        )));
+    if let Some(location) = debugger.location() {
+        show_location(&location);
+    }
+    loop {
+        if interactive {
+            eprint!("(bf) ");
+        }
+        line.clear();
+        match io::stdin().lock().read_line(&mut line) {
+            Ok(0) => break,
+            Ok(_) => (),
+            Err(e) => fail(&format!("failed to read stdin: {}", e)),
+        }
+
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or("step");
+        let argument = words.next();
+        let stop = match command {
+            "s" | "step" => match argument.map_or(Ok(1), str::parse::<usize>) {
+                Ok(count) => {
+                    let mut stop = Ok(Stop::Done);
+                    for _ in 0..count {
+                        stop = debugger.step();
+                        if stop != Ok(Stop::Done) {
+                            break;
+                        }
                     }
+                    stop
+                }
+                Err(_) => {
+                    eprintln!("bf: usage: step [COUNT]");
+                    continue;
+                }
+            },
+            "n" | "next" => debugger.step_over(),
+            "c" | "continue" => debugger.resume(),
+            "b" | "break" => {
+                let mut numbers = argument.unwrap_or("").splitn(2, ':').map(str::parse);
+                match (numbers.next(), numbers.next().unwrap_or(Ok(1))) {
+                    (Some(Ok(line)), Ok(column)) => match debugger.set_breakpoint(line, column) {
+                        Some(location) => {
+                            println!("breakpoint at {}:{}", location.line, location.column)
+                        }
+                        None => eprintln!("bf: no command from there on"),
+                    },
+                    _ => eprintln!("bf: usage: break LINE[:COLUMN]"),
                 }
-                _ => (),
+                continue;
+            }
+            "d" | "delete" => {
+                debugger.clear_breakpoints();
+                continue;
+            }
+            "t" | "tape" => {
+                match argument.map_or(Ok(8), str::parse::<i32>) {
+                    Ok(radius) => {
+                        let pointer = debugger.pointer();
+                        let (start, end) = (pointer - origin - radius, pointer - origin + radius);
+                        dump_tape(&debugger.machine().tape, origin, pointer, start, end + 1);
+                    }
+                    Err(_) => eprintln!("bf: usage: tape [RADIUS]"),
+                }
+                continue;
+            }
+            "w" | "where" => {
+                match debugger.location() {
+                    Some(location) => show_location(&location),
+                    None => println!("the program has finished"),
+                }
+                continue;
+            }
+            "q" | "quit" => break,
+            "h" | "help" => {
+                println!("step [COUNT]      run the next command, or COUNT of them");
+                println!("next              run the next command, and all of a loop it starts");
+                println!("continue          run until a breakpoint or the end");
+                println!("break LINE[:COL]  stop at the first command from there on");
+                println!("delete            remove every breakpoint");
+                println!("tape [RADIUS]     show the cells around the data pointer");
+                println!("where             show the next command in the source");
+                println!("quit              leave, as does end of input");
+                println!("An empty line steps, and every command can be cut to its first letter.");
+                continue;
+            }
+            _ => {
+                eprintln!("bf: unknown command '{}', try help", command);
+                continue;
+            }
+        };
+
+        match stop {
+            Ok(Stop::Finished) => println!("the program has finished"),
+            Ok(stop) => {
+                if stop == Stop::Breakpoint {
+                    print!("breakpoint at ");
+                }
+                if let Some(location) = debugger.location() {
+                    show_location(&location);
+                }
+            }
+            Err(e) => eprintln!("bf: {}", e),
+        }
+    }
+
+    debugger.finish();
+}
+
+/// Shows the program running, a frame per command, waiting `speed`
+/// milliseconds after each or for Enter if it's 0
+fn visualize(mut debugger: Debugger, origin: i32, speed: u64) {
+    const HIDE_CURSOR: &str = "\x1b[?25l";
+    const SHOW_CURSOR: &str = "\x1b[?25h";
+
+    let mut stdout = io::stdout();
+    let mut line = String::new();
+    let mut error = None;
+
+    print!("{}", HIDE_CURSOR);
+    loop {
+        print!("{}", visualize::frame(&debugger, origin));
+        stdout
+            .flush()
+            .unwrap_or_else(|e| fail(&format!("failed to write output: {}", e)));
+        if debugger.is_finished() || error.is_some() {
+            break;
+        }
+
+        if speed == 0 {
+            line.clear();
+            io::stdin()
+                .lock()
+                .read_line(&mut line)
+                .unwrap_or_else(|e| fail(&format!("failed to read stdin: {}", e)));
+        } else {
+            thread::sleep(Duration::from_millis(speed));
+        }
+        // A '#' would stop a debugger, but here it's as good as a comment
+        if let Err(e) = debugger.step() {
+            error = Some(e);
+        }
+    }
+    print!("{}", SHOW_CURSOR);
+
+    if let Some(e) = error {
+        run_failed(&e);
+    }
+}
+
+/// Runs the program to its end, saving a checkpoint to `path` after every
+/// `every` commands
+fn checkpointed(mut debugger: Debugger, every: Option<u64>, path: &Path) {
+    loop {
+        match debugger.run_for(every.unwrap_or(u64::MAX)) {
+            Ok(Stop::Finished) => break,
+            // A '#' is a comment here
+            Ok(Stop::Breakpoint) => (),
+            // The output is flushed after every '.', so it is as far along
+            // as the checkpoint
+            Ok(Stop::Done) => debugger.checkpoint().save(path).unwrap_or_else(|e| {
+                fail(&format!("can't save checkpoint {}: {}", path.display(), e))
+            }),
+            Err(e) => {
+                debugger.finish();
+                run_failed(&e);
+            }
+        }
+    }
+
+    debugger.finish();
+}
+
+/// Runs the program to its end counting its commands, then prints the
+/// annotated source to stderr if `annotate` and writes an lcov tracefile for
+/// the source at `name` to `lcov`
+fn cover(mut debugger: Debugger, annotate: bool, lcov: Option<&str>, name: &str) {
+    loop {
+        match debugger.resume() {
+            Ok(Stop::Finished) => break,
+            // '#' is a comment here
+            Ok(_) => (),
+            Err(e) => {
+                debugger.finish();
+                run_failed(&e);
+            }
+        }
+    }
+
+    let coverage = debugger.coverage().unwrap();
+    debugger.finish();
+    if annotate {
+        eprint!("{}", coverage.annotate());
+    }
+    if let Some(path) = lcov {
+        fs::write(path, coverage.to_lcov(name))
+            .unwrap_or_else(|e| fail(&format!("can't write {}: {}", path, e)));
+    }
+}
+
+/// Runs the program to its end, logging what the tracer keeps to stderr
+fn trace(mut debugger: Debugger, tracer: &Tracer) {
+    let stderr = io::stderr();
+    let mut log = io::BufWriter::new(stderr.lock());
+    let write_failed = |e: io::Error| fail(&format!("failed to write trace: {}", e));
+
+    loop {
+        let (stop, entry) = tracer.step(&mut debugger);
+        if let Some(entry) = entry {
+            writeln!(log, "{}", entry).unwrap_or_else(write_failed);
+        }
+        match stop {
+            Ok(Stop::Finished) => break,
+            // '#' is a comment when tracing
+            Ok(_) => (),
+            Err(e) => {
+                log.flush().unwrap_or_else(write_failed);
+                debugger.finish();
+                run_failed(&e);
             }
         }
     }
 
-    if loop_stack != 0 {
-        panic!(
-            "loop that starts at #{} has no matching ending!",
-            loop_start
-        );
+    log.flush().unwrap_or_else(write_failed);
+    debugger.finish();
+}
+
+fn main() {
+    // Output can fail to be written wherever it is, stopping everything
+    bf::silence_step_limit_panics();
+    if let Err(payload) = panic::catch_unwind(cli) {
+        match payload.downcast::<OutputError>() {
+            Ok(e) => fail(&e.to_string()),
+            Err(payload) => panic::resume_unwind(payload),
+        }
+    }
+}
+
+fn cli() {
+    let opts = Options::parse();
+    opts.check();
+
+    if opts.command == Command::Gen {
+        gen_mode(&opts);
+        return;
+    }
+    if opts.interactive {
+        repl_mode(&opts);
+        return;
+    }
+    // Compiled already, so there is only running it left to do
+    if let Some(path) = opts
+        .filename
+        .as_deref()
+        .filter(|name| name.ends_with(".bfc"))
+    {
+        run_compiled(&opts, path);
+        return;
+    }
+
+    // A pbrain program has a parser of its own, and leaves `program` empty
+    let procedures = (opts.dialect == Dialect::Pbrain).then(|| {
+        let source = opts.source();
+        pbrain::parse(&source).unwrap_or_else(|e| fail(&format!("{}:{}", opts.name(), e)))
+    });
+    let program = match procedures {
+        Some(_) => Vec::new(),
+        None => opts.program(),
+    };
+
+    match opts.command {
+        Command::Fmt => return fmt_mode(&opts, &program),
+        Command::Selftest | Command::Bench => return bench_mode(&opts, &program),
+        _ if opts.emit.is_some() => return build_mode(&opts, program),
+        _ => (),
     }
 
-    program
+    let (program, tape, data_pointer) = opts.set_up(program);
+    if opts.command == Command::Dump {
+        dump_mode(&opts, &program);
+        return;
+    }
+    if opts.dump_ir {
+        match opts.opt {
+            OptLevel::None => eprintln!("{:?}", program),
+            _ => eprintln!("{:?}", lower_at(&program, opts.opt)),
+        }
+    }
+    match opts.is_debugging() {
+        true => debug_mode(&opts, tape, data_pointer),
+        false => run_mode(&opts, &program, procedures.as_deref(), tape, data_pointer),
+    }
+}
+
+/// What the command line asks for
+struct Options {
+    command: Command,
+    /// `bf repl` or `--repl`
+    interactive: bool,
+    dialect: Dialect,
+    filename: Option<String>,
+    /// The program given with `-c`
+    inline: Option<String>,
+    input_mode: InputMode,
+    range_policy: RangePolicy,
+    eof: EofBehavior,
+    output_mode: OutputMode,
+    seed: Option<Seed>,
+    start_at: Option<String>,
+    emit: Option<String>,
+    /// Where `-o` puts what `--emit` or `gen` produced
+    emitted: Option<String>,
+    tee: Option<String>,
+    input_file: Option<String>,
+    input_text: Option<String>,
+    output_file: Option<String>,
+    backend: Option<&'static dyn Backend>,
+    opt: OptLevel,
+    feedback: bool,
+    quine_check: bool,
+    bang_input: bool,
+    /// `--interactive`, reading a keypress at a time
+    keystrokes: bool,
+    style: Option<Style>,
+    width: Option<usize>,
+    text: Option<String>,
+    stage: Option<Stage>,
+    dump_format: Option<DumpFormat>,
+    runs: Option<usize>,
+    warmup: Option<usize>,
+    tape_size: usize,
+    tape_policy: Option<TapePolicy>,
+    cell_width: CellWidth,
+    overflow: Overflow,
+    dump_ir: bool,
+    profiling: bool,
+    annotating: bool,
+    lcov: Option<String>,
+    visualizing: bool,
+    speed: u64,
+    tracing: bool,
+    trace_filter: TraceFilter,
+    trace_format: TraceFormat,
+    max_steps: Option<u64>,
+    timeout: Option<Duration>,
+    sandbox: bool,
+    checkpoint_every: Option<u64>,
+    checkpoint: Option<PathBuf>,
+    resume: Option<PathBuf>,
 }
 
-fn compile(
-    instructions: &[Instruction],
-    delta_p: i32,
-) -> Box<dyn '_ + Fn(&mut Vec<u8>, i32) -> i32> {
-    if instructions.is_empty() {
-        return Box::new(move |_tape, p| p + delta_p);
+impl Options {
+    /// Reads the command line, leaving what doesn't go together to `check`
+    fn parse() -> Options {
+        // Reversed, so that the next argument is the last one
+        let mut args: Vec<String> = env::args().skip(1).collect();
+        args.reverse();
+        let command = match args.last().map(String::as_str) {
+            Some("run") => Some(Command::Run),
+            Some("build") => Some(Command::Build),
+            Some("debug") => Some(Command::Debug),
+            Some("dump") => Some(Command::Dump),
+            Some("fmt") => Some(Command::Fmt),
+            Some("repl") => Some(Command::Repl),
+            Some("selftest") => Some(Command::Selftest),
+            Some("gen") => Some(Command::Gen),
+            Some("bench") => Some(Command::Bench),
+            _ => None,
+        };
+        if command.is_some() {
+            args.pop();
+        }
+        let command = command.unwrap_or(Command::Run);
+
+        let mut dialect = None;
+        let mut opts = Options {
+            command,
+            interactive: command == Command::Repl,
+            dialect: Dialect::Bf,
+            filename: None,
+            inline: None,
+            input_mode: InputMode::Byte,
+            range_policy: RangePolicy::Error,
+            eof: EofBehavior::Unchanged,
+            output_mode: OutputMode::Char,
+            seed: None,
+            start_at: None,
+            emit: None,
+            emitted: None,
+            tee: None,
+            input_file: None,
+            input_text: None,
+            output_file: None,
+            backend: None,
+            opt: OptLevel::Full,
+            feedback: false,
+            quine_check: false,
+            bang_input: false,
+            keystrokes: false,
+            style: None,
+            width: None,
+            text: None,
+            stage: None,
+            dump_format: None,
+            runs: None,
+            warmup: None,
+            tape_size: TAPE_SIZE,
+            tape_policy: None,
+            cell_width: CellWidth::U8,
+            overflow: Overflow::Wrap,
+            dump_ir: false,
+            profiling: false,
+            annotating: false,
+            lcov: None,
+            visualizing: false,
+            speed: 100,
+            tracing: false,
+            trace_filter: TraceFilter::ALL,
+            trace_format: TraceFormat::Text,
+            max_steps: None,
+            timeout: None,
+            sandbox: false,
+            checkpoint_every: None,
+            checkpoint: None,
+            resume: None,
+        };
+
+        while let Some(mut arg) = args.pop() {
+            // --name=value is the same as --name value
+            if arg.starts_with("--") {
+                if let Some(i) = arg.find('=') {
+                    args.push(arg[i + 1..].to_string());
+                    arg.truncate(i);
+                }
+            }
+
+            match arg.as_str() {
+                "--input-mode" => {
+                    opts.input_mode = match args.pop().as_deref() {
+                        Some("byte") => InputMode::Byte,
+                        Some("numeric") => InputMode::Numeric,
+                        _ => usage(),
+                    }
+                }
+                "--input-range" => {
+                    opts.range_policy = match args.pop().as_deref() {
+                        Some("truncate") => RangePolicy::Truncate,
+                        Some("error") => RangePolicy::Error,
+                        Some("saturate") => RangePolicy::Saturate,
+                        _ => usage(),
+                    }
+                }
+                "--eof" => {
+                    opts.eof = match args.pop().as_deref() {
+                        Some("unchanged") => EofBehavior::Unchanged,
+                        Some("zero") => EofBehavior::Zero,
+                        Some("neg1") | Some("minus1") | Some("max") => EofBehavior::Max,
+                        _ => usage(),
+                    }
+                }
+                "--output-mode" => {
+                    opts.output_mode = match args.pop().as_deref() {
+                        Some("char") => OutputMode::Char,
+                        Some("hex") => OutputMode::Hex,
+                        Some("hexdump") => OutputMode::HexDump,
+                        _ => usage(),
+                    }
+                }
+                "--tape-size" => {
+                    opts.tape_size = match args.pop().map(|n| n.parse()) {
+                        Some(Ok(n)) if n > 0 => n,
+                        _ => usage(),
+                    }
+                }
+                "--tape-policy" => {
+                    opts.tape_policy = match args.pop().as_deref() {
+                        Some("grow") => Some(TapePolicy::Grow),
+                        Some("wrap") => Some(TapePolicy::Wrap),
+                        Some("error") => Some(TapePolicy::Error),
+                        _ => usage(),
+                    }
+                }
+                "--dialect" => {
+                    dialect = Some(match args.pop().as_deref() {
+                        Some("bf") => Dialect::Bf,
+                        Some("pbrain") => Dialect::Pbrain,
+                        Some("ook") => Dialect::Tokens(TokenMap::ook()),
+                        Some("blub") => Dialect::Tokens(TokenMap::blub()),
+                        Some(spec) if spec.starts_with("tokens:") => {
+                            let path = &spec["tokens:".len()..];
+                            Dialect::Tokens(TokenMap::load(path).unwrap_or_else(|e| fail(&e)))
+                        }
+                        _ => usage(),
+                    })
+                }
+                "--cell-size" => {
+                    opts.cell_width = match args.pop().as_deref() {
+                        Some("8") => CellWidth::U8,
+                        Some("16") => CellWidth::U16,
+                        Some("32") => CellWidth::U32,
+                        _ => usage(),
+                    }
+                }
+                "--cell-overflow" => {
+                    opts.overflow = match args.pop().as_deref() {
+                        Some("wrap") => Overflow::Wrap,
+                        Some("trap") => Overflow::Trap,
+                        _ => usage(),
+                    }
+                }
+                "--dump-ir" => opts.dump_ir = true,
+                "--profile" => opts.profiling = true,
+                "--coverage" => opts.annotating = true,
+                "--lcov" => opts.lcov = Some(args.pop().unwrap_or_else(|| usage())),
+                "--visualize" => opts.visualizing = true,
+                "--trace" => opts.tracing = true,
+                "--trace-filter" => {
+                    let names = args.pop().unwrap_or_else(|| usage());
+                    opts.trace_filter = TraceFilter {
+                        io: false,
+                        loops: false,
+                        rest: false,
+                    };
+                    for name in names.split(',') {
+                        match name {
+                            "io" => opts.trace_filter.io = true,
+                            "loops" => opts.trace_filter.loops = true,
+                            "all" => opts.trace_filter = TraceFilter::ALL,
+                            _ => usage(),
+                        }
+                    }
+                }
+                "--trace-format" => {
+                    opts.trace_format = match args.pop().as_deref() {
+                        Some("text") => TraceFormat::Text,
+                        Some("json") => TraceFormat::Json,
+                        _ => usage(),
+                    }
+                }
+                "--style" => {
+                    opts.style = match args.pop().as_deref() {
+                        Some("minify") => Some(Style::Minify),
+                        Some("pretty") => Some(Style::Pretty),
+                        Some("strip") => Some(Style::Strip),
+                        _ => usage(),
+                    }
+                }
+                "--text" => opts.text = Some(args.pop().unwrap_or_else(|| usage())),
+                "--stage" => {
+                    opts.stage = match args.pop().as_deref() {
+                        Some("ast") => Some(Stage::Ast),
+                        Some("ir") => Some(Stage::Ir),
+                        Some("bytecode") => Some(Stage::Bytecode),
+                        _ => usage(),
+                    }
+                }
+                "--format" => {
+                    opts.dump_format = match args.pop().as_deref() {
+                        Some("debug") => Some(DumpFormat::Debug),
+                        Some("json") => Some(DumpFormat::Json),
+                        Some("sexpr") => Some(DumpFormat::Sexpr),
+                        _ => usage(),
+                    }
+                }
+                "--width" => {
+                    opts.width = match args.pop().map(|n| n.parse()) {
+                        Some(Ok(n)) if n > 0 => Some(n),
+                        _ => usage(),
+                    }
+                }
+                "--runs" => {
+                    opts.runs = match args.pop().map(|n| n.parse()) {
+                        Some(Ok(n)) if n > 0 => Some(n),
+                        _ => usage(),
+                    }
+                }
+                "--warmup" => {
+                    opts.warmup = match args.pop().map(|n| n.parse()) {
+                        Some(Ok(n)) => Some(n),
+                        _ => usage(),
+                    }
+                }
+                "--speed" => {
+                    opts.speed = match args.pop().map(|n| n.parse()) {
+                        Some(Ok(n)) => n,
+                        _ => usage(),
+                    }
+                }
+                "--max-steps" => {
+                    opts.max_steps = match args.pop().map(|n| n.parse()) {
+                        Some(Ok(n)) => Some(n),
+                        _ => usage(),
+                    }
+                }
+                "--timeout" => {
+                    let seconds = match args.pop().map(|n| n.parse()) {
+                        Some(Ok(seconds)) => Duration::try_from_secs_f64(seconds).ok(),
+                        _ => None,
+                    };
+                    opts.timeout = match seconds {
+                        Some(seconds) if !seconds.is_zero() => Some(seconds),
+                        _ => usage(),
+                    }
+                }
+                "--sandbox" => opts.sandbox = true,
+                "--checkpoint-every" => {
+                    opts.checkpoint_every = match args.pop().map(|n| n.parse()) {
+                        Some(Ok(n)) if n > 0 => Some(n),
+                        _ => usage(),
+                    }
+                }
+                "--checkpoint" => {
+                    opts.checkpoint = Some(PathBuf::from(args.pop().unwrap_or_else(|| usage())))
+                }
+                "--resume" => {
+                    opts.resume = Some(PathBuf::from(args.pop().unwrap_or_else(|| usage())))
+                }
+                "--seed-tape" => {
+                    let spec = args.pop().unwrap_or_else(|| usage());
+                    opts.seed = Some(Seed::parse(&spec).unwrap_or_else(|e| fail(&e)));
+                }
+                "--start-at" => opts.start_at = Some(args.pop().unwrap_or_else(|| usage())),
+                "--tee" => opts.tee = Some(args.pop().unwrap_or_else(|| usage())),
+                "--input" => opts.input_file = Some(args.pop().unwrap_or_else(|| usage())),
+                "--input-str" => opts.input_text = Some(args.pop().unwrap_or_else(|| usage())),
+                "--output" => opts.output_file = Some(args.pop().unwrap_or_else(|| usage())),
+                "--backend" => {
+                    let name = args.pop().unwrap_or_else(|| usage());
+                    opts.backend = Some(
+                        backend::find(&name)
+                            .unwrap_or_else(|| fail(&format!("unknown backend '{}'", name))),
+                    );
+                }
+                "--jit" => {
+                    opts.backend = Some(backend::find("jit").unwrap_or_else(|| {
+                        fail("can't --jit in this build, it needs the jit feature")
+                    }));
+                }
+                "--opt" => {
+                    opts.opt = match args.pop().as_deref() {
+                        Some("none") => OptLevel::None,
+                        Some("basic") => OptLevel::Basic,
+                        Some("full") => OptLevel::Full,
+                        _ => usage(),
+                    }
+                }
+                "--list-backends" => {
+                    for backend in BACKENDS {
+                        println!("{:<12} {}", backend.name(), backend.description());
+                    }
+                    process::exit(0);
+                }
+                "--feedback" => opts.feedback = true,
+                "--repl" => opts.interactive = true,
+                "--quine-check" => opts.quine_check = true,
+                "--bang-input" => opts.bang_input = true,
+                "--interactive" => opts.keystrokes = true,
+                "--conformance" => {
+                    let failures = conformance::run_battery();
+                    for failure in &failures {
+                        println!("FAIL {}", failure);
+                    }
+                    println!(
+                        "{} conformance cases, {} failures",
+                        conformance::cases().len(),
+                        failures.len()
+                    );
+                    process::exit(if failures.is_empty() { 0 } else { 1 });
+                }
+                "--emit" => opts.emit = Some(args.pop().unwrap_or_else(|| usage())),
+                "-o" => opts.emitted = Some(args.pop().unwrap_or_else(|| usage())),
+                "-c" => opts.inline = Some(args.pop().unwrap_or_else(|| usage())),
+                _ if opts.filename.is_none() && !arg.starts_with("--") => opts.filename = Some(arg),
+                _ => usage(),
+            }
+        }
+
+        let extension = opts
+            .filename
+            .as_deref()
+            .and_then(|name| Path::new(name).extension())
+            .and_then(|extension| extension.to_str());
+        opts.dialect = dialect.unwrap_or_else(|| match extension {
+            Some("ook") => Dialect::Tokens(TokenMap::ook()),
+            Some("blub") => Dialect::Tokens(TokenMap::blub()),
+            _ => Dialect::Bf,
+        });
+        // A sandboxed program can't run for ever
+        if opts.sandbox {
+            opts.timeout = opts.timeout.or(Some(SANDBOX_TIMEOUT));
+        }
+        if opts.command == Command::Build && opts.emit.is_none() {
+            let bfc = opts
+                .emitted
+                .as_ref()
+                .is_some_and(|path| path.ends_with(".bfc"));
+            opts.emit = Some(if bfc { "bfc" } else { "c" }.to_string());
+        }
+
+        opts
     }
 
-    match &instructions[0] {
-        Instruction::IncrementPointer => compile(&instructions[1..], delta_p + 1),
-        Instruction::DecrementPointer => compile(&instructions[1..], delta_p - 1),
-        Instruction::Increment => {
-            let rest = compile(&instructions[1..], 0);
+    /// Fails on options that don't go together, or don't go with the
+    /// command
+    fn check(&self) {
+        let command = self.command;
+        let covering = self.is_covering();
+        let checkpointing = self.is_checkpointing();
+        let plain_cells = self.has_plain_cells();
+        let program_json = self
+            .filename
+            .as_ref()
+            .is_some_and(|name| name.ends_with(".json"));
+
+        // A sandboxed program can't take more memory than its tape was given
+        if self.sandbox && self.tape_policy == Some(TapePolicy::Grow) {
+            fail("--sandbox keeps the tape from growing");
+        }
+
+        // The source is run as written, by a debugger counting every command
+        if covering
+            && (command != Command::Run
+                || self.emit.is_some()
+                || self.interactive
+                || self.visualizing
+                || self.tracing
+                || self.profiling
+                || checkpointing)
+        {
+            fail("--coverage and --lcov only apply to running a program, without --emit, --repl, --visualize, --trace, --profile or checkpoints");
+        }
 
-            Box::new(move |tape, mut p| {
-                p += delta_p;
-                tape[p as usize] += 1;
-                rest(tape, p)
-            })
+        if self.backend.is_some() && self.opt != OptLevel::Full {
+            fail("--backend can only be chosen with --opt full");
+        }
+        if self.style.is_some() && command != Command::Fmt {
+            fail("--style only applies to fmt");
+        }
+        if self.width.is_some() && !matches!(command, Command::Fmt | Command::Gen) {
+            fail("--width only applies to fmt and gen");
+        }
+        if self.text.is_some() && command != Command::Gen {
+            fail("--text only applies to gen");
+        }
+        if (self.stage.is_some() || self.dump_format.is_some()) && command != Command::Dump {
+            fail("--stage and --format only apply to dump");
+        }
+        if (self.runs.is_some() || self.warmup.is_some()) && command != Command::Bench {
+            fail("--runs and --warmup only apply to bench");
         }
-        Instruction::Decrement => {
-            let rest = compile(&instructions[1..], 0);
 
-            Box::new(move |tape, mut p| {
-                p += delta_p;
-                tape[p as usize] -= 1;
-                rest(tape, p)
-            })
+        // gen and the repl make up their program, everything else takes
+        // exactly one
+        let sources = usize::from(self.inline.is_some()) + usize::from(self.filename.is_some());
+        if command == Command::Gen {
+            if sources > 0 {
+                usage();
+            }
+            return;
         }
-        Instruction::Write => {
-            let rest = compile(&instructions[1..], 0);
 
-            Box::new(move |tape, mut p| {
-                p += delta_p;
-                print!("{}", tape[p as usize] as char);
-                rest(tape, p)
-            })
+        // Only 8-bit cells that wrap are optimized, any others the program
+        // is interpreted as parsed
+        if !plain_cells
+            && (self.backend.is_some()
+                || self.opt != OptLevel::Full
+                || self.emit.is_some()
+                || self.interactive
+                || self.profiling
+                || self.dump_ir
+                || self.visualizing
+                || self.tracing
+                || covering
+                || !matches!(command, Command::Run | Command::Fmt))
+        {
+            fail("--cell-size and --cell-overflow trap only apply to bf run and bf fmt, without --backend, --opt, --emit, --profile, --dump-ir, --visualize, --trace or --coverage");
+        }
+        if self.frontend().is_some() && self.interactive {
+            fail("--repl only takes Brainfuck");
         }
-        Instruction::Read => {
-            let rest = compile(&instructions[1..], 0);
 
-            Box::new(move |tape, mut p| {
-                let mut input: [u8; 1] = [0; 1];
-                std::io::stdin()
-                    .read_exact(&mut input)
-                    .expect("failed to read stdin");
-                p += delta_p;
-                tape[p as usize] = input[0];
-                rest(tape, p)
-            })
+        // Procedures are only known to their own interpreter
+        if self.dialect == Dialect::Pbrain
+            && (self.backend.is_some()
+                || self.opt != OptLevel::Full
+                || self.emit.is_some()
+                || self.interactive
+                || self.profiling
+                || self.dump_ir
+                || self.visualizing
+                || self.tracing
+                || covering
+                || checkpointing
+                || !plain_cells
+                || command != Command::Run)
+        {
+            fail("--dialect pbrain only applies to running a program, with none of the options for inspecting or optimizing one");
         }
 
-        Instruction::Loop(nested_instructions) => {
-            let rest = compile(&instructions[1..], 0);
+        let input_given = self.input_file.is_some() || self.input_text.is_some();
+        if self.input_file.is_some() && self.input_text.is_some() {
+            fail("--input and --input-str can't be combined");
+        }
+        if self.bang_input && (input_given || self.interactive) {
+            fail("--bang-input can't be combined with --input, --input-str or --repl");
+        }
+        // Ook! and others spell commands with '!'
+        if self.bang_input && self.frontend().is_some() {
+            fail("--bang-input only applies to bf and pbrain programs");
+        }
+        // Keypresses only come from a terminal, for a program running as usual
+        if self.keystrokes
+            && (input_given
+                || self.bang_input
+                || self.interactive
+                || self.feedback
+                || self.visualizing
+                || self.tracing
+                || self.profiling
+                || covering
+                || checkpointing
+                || command != Command::Run)
+        {
+            fail("--interactive only applies to running a program on stdin, without --input, --input-str, --bang-input, --repl, --feedback, --visualize, --trace, --profile, --coverage or checkpoints");
+        }
+        if self.keystrokes && !io::stdin().is_terminal() {
+            fail("--interactive needs stdin to be a terminal");
+        }
+        if checkpointing && self.output_mode != OutputMode::Char {
+            fail("--checkpoint-every and --resume need --output-mode char");
+        }
 
-            if nested_instructions.len() == 1 && nested_instructions[0] == Instruction::Decrement {
-                // Special case [-] which sets take[p] to 0
-                return Box::new(move |tape, mut p| {
-                    p += delta_p;
-                    tape[p as usize] = 0;
-                    rest(tape, p)
-                });
+        if self.interactive {
+            if sources > 0 || !matches!(command, Command::Run | Command::Repl) {
+                usage();
             }
+            return;
+        }
+        match sources {
+            0 => usage(),
+            2 => fail("-c and a file can't both be given"),
+            _ => (),
+        }
+
+        if self
+            .filename
+            .as_ref()
+            .is_some_and(|name| name.ends_with(".bfc"))
+            && (command != Command::Run
+                || self.backend.is_some()
+                || self.opt != OptLevel::Full
+                || self.emit.is_some()
+                || self.profiling
+                || self.dump_ir
+                || self.visualizing
+                || self.tracing
+                || covering
+                || checkpointing
+                || self.feedback
+                || self.quine_check
+                || self.bang_input
+                || self.seed.is_some()
+                || !plain_cells
+                || self.dialect != Dialect::Bf)
+        {
+            fail("a .bfc program can only be run, with none of the options for inspecting, optimizing or setting up the program");
+        }
+        if matches!(command, Command::Fmt | Command::Selftest | Command::Bench) {
+            return;
+        }
+
+        match self.emit.as_deref() {
+            Some("ast-json") | Some("llvm") | Some("llvm-ir") | Some("rust") | Some("c")
+            | Some("wasm") | Some("bf") | Some("bfc") => (),
+            None if self.emitted.is_some() => fail("-o only applies to --emit"),
+            None => (),
+            Some(target) => fail(&format!("unknown --emit target '{}'", target)),
+        }
+        if self.start_at.is_some() && self.seed.is_none() {
+            fail("--start-at requires --seed-tape");
+        }
+        if self.emit.is_some() || command == Command::Dump {
+            return;
+        }
+
+        if self.visualizing && self.tracing {
+            fail("--visualize and --trace can't be combined");
+        }
+        if checkpointing
+            && (command != Command::Run || self.visualizing || self.tracing || self.profiling)
+        {
+            fail("--checkpoint-every and --resume only apply to running a program");
+        }
+        if self.is_debugging() && program_json {
+            fail("bf debug, --visualize, --trace, --coverage and checkpoints need the program's source, not JSON");
+        }
+    }
+
+    fn is_covering(&self) -> bool {
+        self.annotating || self.lcov.is_some()
+    }
+
+    fn is_checkpointing(&self) -> bool {
+        self.checkpoint_every.is_some() || self.resume.is_some()
+    }
+
+    /// Run by a `Debugger`, a command at a time, rather than by a backend
+    fn is_debugging(&self) -> bool {
+        self.command == Command::Debug
+            || self.visualizing
+            || self.tracing
+            || self.is_checkpointing()
+            || self.is_covering()
+    }
+
+    fn has_plain_cells(&self) -> bool {
+        cell::is_plain(self.cell_width, self.overflow)
+    }
+
+    fn frontend(&self) -> Option<&TokenMap> {
+        match &self.dialect {
+            Dialect::Tokens(tokens) => Some(tokens),
+            _ => None,
+        }
+    }
+
+    fn backend(&self) -> &'static dyn Backend {
+        self.backend.unwrap_or(BACKENDS[0])
+    }
+
+    fn tape_policy(&self) -> TapePolicy {
+        self.tape_policy.unwrap_or(match self.sandbox {
+            true => TapePolicy::Error,
+            false => TapePolicy::Grow,
+        })
+    }
+
+    /// What errors in the program are reported against
+    fn name(&self) -> &str {
+        self.filename.as_deref().unwrap_or("-c")
+    }
+
+    /// The source of the program, see `read_source`
+    fn source(&self) -> Vec<u8> {
+        read_source(
+            &self.inline,
+            &self.filename,
+            self.frontend(),
+            self.bang_input,
+        )
+    }
 
-            let inner = compile(&nested_instructions, 0);
-            Box::new(move |tape, mut p| {
-                p += delta_p;
-                while tape[p as usize] != 0 {
-                    p = inner(tape, p);
+    /// The program, parsed
+    fn program(&self) -> Vec<Instruction> {
+        match (&self.inline, &self.filename) {
+            // Everything after the frontend takes it as Brainfuck, and the
+            // input embedded in the source isn't part of the program
+            _ if self.frontend().is_some() || self.bang_input => {
+                parse_source(self.name(), &self.source()).unwrap_or_else(|e| fail(&e.to_string()))
+            }
+            (Some(source), _) => {
+                parse_source("-c", source.as_bytes()).unwrap_or_else(|e| fail(&e.to_string()))
+            }
+            (None, Some(filename)) => {
+                if cfg!(feature = "json")
+                    && filename.ends_with(".json")
+                    && self.command != Command::Fmt
+                {
+                    let file = File::open(filename)
+                        .unwrap_or_else(|e| fail(&format!("{}: {}", filename, e)));
+                    load_json(file)
+                } else {
+                    parse_file(filename).unwrap_or_else(|e| fail(&e.to_string()))
                 }
-                rest(tape, p)
-            })
+            }
+            (None, None) => unreachable!("checked that there is a program"),
         }
     }
-}
 
-/// Executes a program that was previously parsed
-// This is the original code, keeping it here for now
-#[allow(dead_code)]
-fn run(instructions: &[Instruction], tape: &mut Vec<u8>, data_pointer: &mut usize) {
-    for instr in instructions {
-        match instr {
-            Instruction::IncrementPointer => *data_pointer += 1,
-            Instruction::DecrementPointer => *data_pointer -= 1,
-            Instruction::Increment => tape[*data_pointer] += 1,
-            Instruction::Decrement => tape[*data_pointer] -= 1,
-            Instruction::Write => print!("{}", tape[*data_pointer] as char),
-            Instruction::Read => {
-                let mut input: [u8; 1] = [0; 1];
-                std::io::stdin()
-                    .read_exact(&mut input)
-                    .expect("failed to read stdin");
-                tape[*data_pointer] = input[0];
+    /// With `--bang-input`, what comes after the first `!` of the source
+    fn embedded(&self) -> Option<Vec<u8>> {
+        self.bang_input.then(|| {
+            let source = read_source(&self.inline, &self.filename, None, false);
+            let start = (bang(&source) + 1).min(source.len());
+            source[start..].to_vec()
+        })
+    }
+
+    fn input(&self) -> Input {
+        let (input_mode, range_policy) = (self.input_mode, self.range_policy);
+        let input = match (&self.input_file, &self.input_text) {
+            (Some(path), _) => {
+                let file = File::open(path).unwrap_or_else(|e| fail(&format!("{}: {}", path, e)));
+                Input::new(input_mode, range_policy, Box::new(io::BufReader::new(file)))
+            }
+            (None, Some(text)) => {
+                let text = io::Cursor::new(text.clone().into_bytes());
+                Input::new(input_mode, range_policy, Box::new(text))
             }
-            Instruction::Loop(nested_instructions) => {
-                while tape[*data_pointer] != 0 {
-                    run(&nested_instructions, tape, data_pointer)
+            (None, None) => match self.embedded() {
+                Some(data) => Input::new(input_mode, range_policy, Box::new(io::Cursor::new(data))),
+                // Nothing is waiting on a line, so there is nothing to hint at
+                None if self.keystrokes => {
+                    Input::new(input_mode, range_policy, Box::new(io::stdin()))
                 }
+                None => Input::stdin(input_mode, range_policy),
+            },
+        };
+        input.on_eof(self.eof)
+    }
+
+    /// Writes to `--output`, or else to stdout, and to `--tee` as well. A
+    /// run resumed from a checkpoint carries on from what had been written
+    /// by then.
+    fn output(&self, resumed: Option<&Checkpoint>) -> Output {
+        let create = |path: &str| {
+            let failed = |e: io::Error| format!("can't create {}: {}", path, e);
+            let mut options = OpenOptions::new();
+            match resumed {
+                Some(_) => options.append(true),
+                None => options.write(true).truncate(true),
+            };
+            let file = options
+                .create(true)
+                .open(path)
+                .unwrap_or_else(|e| fail(&failed(e)));
+            if let Some(state) = resumed {
+                file.set_len(state.output)
+                    .unwrap_or_else(|e| fail(&failed(e)));
+            }
+            file
+        };
+
+        let sink: Box<dyn Write + Send> = match &self.output_file {
+            Some(path) => Box::new(create(path)),
+            None => Box::new(io::stdout()),
+        };
+        let sink: Box<dyn Write + Send> = match &self.tee {
+            Some(path) => {
+                let output = self.output_file.as_deref().unwrap_or("stdout").to_string();
+                let copy = path.clone();
+                Box::new(Tee::new(sink, create(path)).on_drop(move |which, e| {
+                    let name = match which {
+                        Sink::First => &output,
+                        Sink::Second => &copy,
+                    };
+                    eprintln!("bf: tee: can't write {} any more: {}", name, e);
+                }))
+            }
+            None => sink,
+        };
+        match self.keystrokes {
+            true => Output::new(self.output_mode, Box::new(Flushing(sink))),
+            false => Output::new(self.output_mode, Box::new(io::BufWriter::new(sink))),
+        }
+    }
+
+    /// A machine on `tape`, under the step and time limits
+    fn machine(&self, tape: Tape, input: Input, output: Output) -> Machine {
+        Machine {
+            step_limit: self.max_steps,
+            time_limit: self.timeout.map(TimeLimit::start),
+            ..Machine::new(tape.with_policy(self.tape_policy()), input, output)
+        }
+    }
+
+    /// Folds what it can of the program and lays out the tape, returning
+    /// them with where the data pointer starts
+    fn set_up(&self, mut program: Vec<Instruction>) -> (Vec<Instruction>, Vec<u8>, usize) {
+        let tape_size = self.tape_size;
+
+        // A seeded tape isn't blank, so the prefix can't be evaluated ahead
+        // of time, and steps taken at compile time would escape the limits
+        let mut data_pointer = tape_size / 2;
+        let limited = (self.max_steps.is_some() || self.timeout.is_some()) && self.emit.is_none();
+        if self.seed.is_none()
+            && self.opt == OptLevel::Full
+            && self.has_plain_cells()
+            && !self.profiling
+            && !limited
+        {
+            let bounds = -(data_pointer as i32)..(tape_size - data_pointer) as i32;
+            program = fold_constant_prefix(program, bounds);
+            if self.emit.is_some() {
+                program = drop_final_snapshot(program);
             }
         }
+
+        let mut tape = vec![0; tape_size];
+        if let Some(seed) = &self.seed {
+            if seed.cells.len() > tape.len() - data_pointer {
+                fail("seed tape does not fit on the tape");
+            }
+            tape[data_pointer..data_pointer + seed.cells.len()].copy_from_slice(&seed.cells);
+            if let Some(label) = &self.start_at {
+                let offset = seed
+                    .label(label)
+                    .unwrap_or_else(|| fail(&format!("no label '{}' in seed tape", label)));
+                data_pointer += offset;
+            }
+        }
+
+        (program, tape, data_pointer)
     }
 }
 
-fn main() {
-    // Determine which file to execute
-    let args: Vec<String> = env::args().collect();
+/// `bf gen`: prints a program that writes the text
+fn gen_mode(opts: &Options) {
+    let text = opts
+        .text
+        .clone()
+        .map(String::into_bytes)
+        .unwrap_or_else(|| {
+            let mut data = Vec::new();
+            io::stdin()
+                .read_to_end(&mut data)
+                .unwrap_or_else(|e| fail(&format!("failed to read input: {}", e)));
+            data
+        });
+    let program = generate::text(&text);
+    let program = match opts.width {
+        Some(width) => format::wrap(program.trim_end(), width) + "\n",
+        None => program,
+    };
+    write_emitted(program.as_bytes(), opts.emitted.as_deref());
+}
+
+/// `bf repl`: runs lines as they are typed
+fn repl_mode(opts: &Options) {
+    let machine = Machine::new(
+        Tape::new(vec![0; opts.tape_size]).with_policy(opts.tape_policy()),
+        opts.input(),
+        opts.output(None),
+    );
+    let origin = (opts.tape_size / 2) as i32;
+    let interpreter = Interpreter::from_machine(machine, origin)
+        .with_backend(opts.backend())
+        .with_opt(opts.opt);
+    repl(interpreter, origin);
+}
+
+/// Runs a `.bfc` file, as compiled by `bf build`
+fn run_compiled(opts: &Options, path: &str) {
+    let compiled = Compiled::load(Path::new(path)).unwrap_or_else(|e| fail(&e));
+    let tape = Tape::new(vec![0; compiled.tape_size]);
+    let mut machine = opts.machine(tape, opts.input(), opts.output(None));
+    let _raw = opts.keystrokes.then(|| {
+        RawMode::enable().unwrap_or_else(|e| fail(&format!("can't read keypresses: {}", e)))
+    });
+    let p = compiled.data_pointer as i32;
+    let run = panic::catch_unwind(AssertUnwindSafe(|| {
+        bytecode::run(&compiled.code, &mut machine, p)
+    }));
+    if let Err(payload) = run {
+        stopped(payload, &mut machine);
+    }
+    machine.output.finish();
+}
+
+/// `bf fmt`: prints the program laid out in `--style`
+fn fmt_mode(opts: &Options, program: &[Instruction]) {
+    // Parsed already, so what is printed has matching brackets
+    let text = match opts.style.unwrap_or(Style::Minify) {
+        Style::Minify => format::minify(program),
+        Style::Pretty => format::pretty(program),
+        Style::Strip => format::strip(&opts.source()),
+    };
+    match opts.width {
+        Some(width) => println!("{}", format::wrap(&text, width)),
+        None => println!("{}", text),
+    }
+}
+
+/// `bf bench`, and `bf selftest` which checks that every backend agrees
+fn bench_mode(opts: &Options, program: &[Instruction]) {
+    // Every run gets the same input, so it is read up front, unless there
+    // is no ',' to read it
+    let input = match (&opts.input_file, &opts.input_text, opts.embedded()) {
+        _ if !reads_input(program) => Vec::new(),
+        (Some(path), _, _) => fs::read(path).unwrap_or_else(|e| fail(&format!("{}: {}", path, e))),
+        (None, Some(text), _) => text.clone().into_bytes(),
+        (None, None, Some(data)) => data,
+        (None, None, None) => {
+            let mut data = Vec::new();
+            io::stdin()
+                .read_to_end(&mut data)
+                .unwrap_or_else(|e| fail(&format!("failed to read input: {}", e)));
+            data
+        }
+    };
 
-    if args.len() != 2 {
-        println!("usage: bf <file.bf>");
-        std::process::exit(1);
+    if opts.command == Command::Bench {
+        let defaults = BenchOptions::default();
+        let bench_opts = BenchOptions {
+            warmup: opts.warmup.unwrap_or(defaults.warmup),
+            runs: opts.runs.unwrap_or(defaults.runs),
+            step_limit: opts.max_steps,
+        };
+        match bench::bench(program, &input, opts.eof, bench_opts) {
+            Ok(report) => print!("{}", report),
+            Err(e) => fail(&e),
+        }
+        return;
     }
 
-    let filename = &args[1];
+    let failures = conformance::compare(opts.name(), program, &input, opts.eof);
+    for failure in &failures {
+        eprintln!("{}", failure);
+    }
+    if !failures.is_empty() {
+        process::exit(1);
+    }
+    eprintln!("bf: every backend agrees");
+}
 
-    // Read file
-    let mut file = File::open(filename).expect("program file not found");
-    let mut source = String::new();
-    file.read_to_string(&mut source)
-        .expect("failed to read program file");
+/// `bf build` and `--emit`: translates the program for `-o`
+fn build_mode(opts: &Options, program: Vec<Instruction>) {
+    if opts.emit.as_deref() == Some("ast-json") {
+        write_emitted(emit_ast_json(&program).as_bytes(), opts.emitted.as_deref());
+        return;
+    }
 
-    // Lex file into opcodes
-    let opcodes = lex(source);
+    let emitter: Emitter = match opts.emit.as_deref() {
+        Some("llvm") | Some("llvm-ir") => |p, t, d, e| emit_llvm(p, t, d, e).into_bytes(),
+        Some("rust") => |p, t, d, e| rust::emit(p, t, d, e).into_bytes(),
+        Some("c") => |p, t, d, e| c::emit(p, t, d, e).into_bytes(),
+        Some("wasm") => wasm::emit,
+        Some("bf") => |p, t, d, e| brainfuck::emit(p, t, d, e).into_bytes(),
+        Some("bfc") => |p, t, d, _| Compiled::new(p, t, d).to_bytes(),
+        _ => unreachable!("checked that --emit is a target"),
+    };
+    let (program, tape, data_pointer) = opts.set_up(program);
+    let module = emitter(&lower_at(&program, opts.opt), &tape, data_pointer, opts.eof);
+    write_emitted(&module, opts.emitted.as_deref());
+}
+
+/// `bf dump`: prints the program at `--stage`
+fn dump_mode(opts: &Options, program: &[Instruction]) {
+    let stage = opts.stage.unwrap_or(match opts.opt {
+        OptLevel::None => Stage::Ast,
+        _ => Stage::Ir,
+    });
+    let format = opts.dump_format.unwrap_or(DumpFormat::Debug);
+    print!("{}", dump(program, stage, format, opts.opt));
+}
 
-    // Parse opcodes into program
-    let program = parse(opcodes);
+/// `bf debug`, and running a command at a time for `--visualize`, `--trace`,
+/// `--coverage` and checkpoints
+fn debug_mode(opts: &Options, tape: Vec<u8>, data_pointer: usize) {
+    let resumed = opts
+        .resume
+        .as_ref()
+        .map(|path| Checkpoint::load(path).unwrap_or_else(|e| fail(&e)));
+    // Visualized output is only shown in its pane
+    let shown = match opts.visualizing {
+        true => Output::new(OutputMode::Char, Box::new(io::sink())).record(),
+        false => opts.output(resumed.as_ref()),
+    };
+    let machine = opts.machine(Tape::new(tape), opts.input(), shown);
+    // The source was parsed already, so the brackets match
+    let mut debugger = Debugger::new(&opts.source(), machine, data_pointer as i32).unwrap();
+    let origin = data_pointer as i32;
 
-    // Set up environment and run program
-    let mut tape: Vec<u8> = vec![0; 1024];
-    let data_pointer = 512;
-    // run(&program, &mut tape, &mut data_pointer);
-    let code = compile(&program, 0);
-    code(&mut tape, data_pointer);
+    if opts.is_checkpointing() {
+        if let (Some(path), Some(state)) = (&opts.resume, &resumed) {
+            debugger
+                .restore(state)
+                .unwrap_or_else(|e| fail(&format!("{}: {}", path.display(), e)));
+        }
+        // Saved over the one resumed from, unless told otherwise
+        let path = opts
+            .checkpoint
+            .clone()
+            .or(opts.resume.clone())
+            .unwrap_or_else(|| {
+                let name = opts.filename.as_deref().unwrap_or("bf");
+                PathBuf::from(format!("{}.bfstate", name))
+            });
+        checkpointed(debugger, opts.checkpoint_every, &path);
+    } else if opts.is_covering() {
+        let coverage = debugger.with_coverage();
+        cover(coverage, opts.annotating, opts.lcov.as_deref(), opts.name());
+    } else if opts.visualizing {
+        visualize(debugger, origin, opts.speed);
+    } else if opts.tracing {
+        let tracer = Tracer {
+            filter: opts.trace_filter,
+            format: opts.trace_format,
+            origin,
+        };
+        trace(debugger, &tracer);
+    } else {
+        debug(debugger, origin);
+    }
+}
+
+/// `bf run`: runs the program on the backend, or with `procedures` on the
+/// pbrain interpreter
+fn run_mode(
+    opts: &Options,
+    program: &[Instruction],
+    procedures: Option<&[pbrain::Instruction]>,
+    tape: Vec<u8>,
+    data_pointer: usize,
+) {
+    let execute = |input, output| {
+        let mut machine = opts.machine(Tape::new(tape.clone()), input, output);
+        let run = panic::catch_unwind(AssertUnwindSafe(|| {
+            if opts.profiling {
+                // Fused as written, every loop is one in the source
+                let (_, mut profile) =
+                    profile::profile(&fuse(program), &mut machine, data_pointer as i32);
+                let json = opts
+                    .filename
+                    .as_ref()
+                    .is_some_and(|name| name.ends_with(".json"));
+                if !(cfg!(feature = "json") && json) {
+                    profile.locate(&opts.source());
+                }
+                Some(profile)
+            } else if let Some(procedures) = procedures {
+                if let Err(e) = pbrain::run(procedures, &mut machine, data_pointer as i32) {
+                    machine.output.finish();
+                    fail(&e.to_string());
+                }
+                None
+            } else if !opts.has_plain_cells() {
+                let p = data_pointer as i32;
+                cell::run_width(
+                    opts.cell_width,
+                    opts.overflow,
+                    program,
+                    &tape,
+                    opts.tape_policy(),
+                    &mut machine,
+                    p,
+                );
+                None
+            } else {
+                let backend = opts.backend();
+                backend::run_at(
+                    opts.opt,
+                    backend,
+                    program,
+                    &mut machine,
+                    data_pointer as i32,
+                );
+                None
+            }
+        }));
+        let profile = run.unwrap_or_else(|payload| stopped(payload, &mut machine));
+        machine.output.finish();
+        if let Some(profile) = profile {
+            eprint!("{}", profile);
+        }
+        machine.output.take_recording().unwrap_or_default()
+    };
+
+    if opts.feedback {
+        // Second run reads what the first one wrote
+        let written = execute(opts.input(), opts.output(None).record());
+        let replay = io::Cursor::new(written);
+        let replay = Input::new(opts.input_mode, opts.range_policy, Box::new(replay));
+        execute(replay.on_eof(opts.eof), opts.output(None));
+    } else if opts.quine_check {
+        let written = execute(opts.input(), opts.output(None).record());
+        // A quine prints itself as written, not as translated
+        let source = read_source(&opts.inline, &opts.filename, None, opts.bang_input);
+        if let Err(e) = check_quine(&source, &written) {
+            fail(&e);
+        }
+        eprintln!("bf: output matches the program source");
+    } else if opts.keystrokes {
+        // Put back on the way out, including by process::exit or a signal
+        let _raw =
+            RawMode::enable().unwrap_or_else(|e| fail(&format!("can't read keypresses: {}", e)));
+        execute(opts.input(), opts.output(None));
+    } else {
+        execute(opts.input(), opts.output(None));
+    }
 }
@@ -0,0 +1,454 @@
+use std::error;
+use std::fmt;
+use std::io::{self, BufWriter, Write};
+use std::panic;
+
+/// How bytes written by `.` are presented
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputMode {
+    /// Every byte is written as is
+    Char,
+    /// Every byte is printed as two hex digits, separated by spaces
+    Hex,
+    /// Sixteen bytes per line with their address and ASCII, like `hexdump -C`
+    HexDump,
+}
+
+/// Panic payload of a `.`, or a flush, that couldn't write its output
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputError {
+    /// What writing failed with
+    pub reason: String,
+}
+
+impl fmt::Display for OutputError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "failed to write output: {}", self.reason)
+    }
+}
+
+impl error::Error for OutputError {}
+
+/// Where the bytes of an `Output` go. Everything that is `Write` is one, and
+/// embedders can implement it for whatever else collects what a program
+/// writes, like a channel or a text view.
+pub trait BfOutput {
+    /// Takes all of `bytes`, or fails
+    fn write_bytes(&mut self, bytes: &[u8]) -> io::Result<()>;
+    /// Pushes out anything held back, called before the program reads and
+    /// once it has finished
+    fn flush_output(&mut self) -> io::Result<()>;
+}
+
+impl<W: Write> BfOutput for W {
+    fn write_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.write_all(bytes)
+    }
+
+    fn flush_output(&mut self) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+/// Holds back what is written and passes it on to a `BfOutput` in large
+/// pieces, as `BufWriter` does for a `Write`
+pub struct Buffered<O: BfOutput> {
+    inner: O,
+    buffer: Vec<u8>,
+}
+
+const BUFFER_SIZE: usize = 8192;
+
+impl<O: BfOutput> Buffered<O> {
+    pub fn new(inner: O) -> Buffered<O> {
+        Buffered {
+            inner,
+            buffer: Vec::with_capacity(BUFFER_SIZE),
+        }
+    }
+}
+
+impl<O: BfOutput> Write for Buffered<O> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.buffer.len() + buf.len() > BUFFER_SIZE {
+            self.inner.write_bytes(&self.buffer)?;
+            self.buffer.clear();
+        }
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.write_bytes(&self.buffer)?;
+        self.buffer.clear();
+        self.inner.flush_output()
+    }
+}
+
+impl<O: BfOutput> Drop for Buffered<O> {
+    fn drop(&mut self) {
+        // Like `BufWriter`, whatever goes wrong this late is lost
+        let _ = self.flush();
+    }
+}
+
+/// Sink for the bytes a program writes
+pub struct Output {
+    mode: OutputMode,
+    sink: Box<dyn BfOutput + Send>,
+    written: usize,
+    line: Vec<u8>,
+    recording: Option<Vec<u8>>,
+    /// Set once the sink has failed, after which nothing more is written
+    failed: bool,
+}
+
+impl Output {
+    pub fn new(mode: OutputMode, sink: Box<dyn BfOutput + Send>) -> Output {
+        Output {
+            mode,
+            sink,
+            written: 0,
+            line: Vec::with_capacity(16),
+            recording: None,
+            failed: false,
+        }
+    }
+
+    /// Also keeps a copy of every byte written, before any formatting
+    pub fn record(mut self) -> Output {
+        self.recording = Some(Vec::new());
+        self
+    }
+
+    /// The bytes written so far, if recording
+    pub fn take_recording(&mut self) -> Option<Vec<u8>> {
+        self.recording.take()
+    }
+
+    /// `take_recording`, leaving the recording to carry on
+    pub fn recording(&self) -> Option<&[u8]> {
+        self.recording.as_deref()
+    }
+
+    /// Writes to stdout through a buffer, which is only flushed by `flush`
+    /// and `finish`
+    pub fn stdout(mode: OutputMode) -> Output {
+        Output::new(mode, Box::new(BufWriter::new(io::stdout())))
+    }
+
+    /// Bytes written so far, before any formatting
+    pub fn written(&self) -> u64 {
+        self.written as u64
+    }
+
+    /// Carries on after `written` bytes that an earlier run wrote
+    pub fn continue_from(&mut self, written: u64) {
+        self.written = written as usize;
+    }
+
+    /// Writes the byte `.` wrote. A sink that fails panics with an
+    /// `OutputError`, and is left alone from then on.
+    pub fn write(&mut self, byte: u8) {
+        let first = self.written == 0;
+        self.written += 1;
+
+        if let Some(recording) = &mut self.recording {
+            recording.push(byte);
+        }
+        if self.failed {
+            return;
+        }
+
+        let result = match self.mode {
+            OutputMode::Char => self.sink.write_bytes(&[byte]),
+            OutputMode::Hex if first => self.sink.write_bytes(format!("{:02x}", byte).as_bytes()),
+            OutputMode::Hex => self.sink.write_bytes(format!(" {:02x}", byte).as_bytes()),
+            OutputMode::HexDump => {
+                self.line.push(byte);
+                if self.line.len() == 16 {
+                    self.dump_line()
+                } else {
+                    Ok(())
+                }
+            }
+        };
+
+        self.check(result);
+    }
+
+    /// Pushes out everything written so far
+    pub fn flush(&mut self) {
+        if !self.failed {
+            let result = self.sink.flush_output();
+            self.check(result);
+        }
+    }
+
+    /// Terminates the output once the program has finished
+    pub fn finish(&mut self) {
+        if self.failed {
+            return;
+        }
+        let result = match self.mode {
+            OutputMode::Char => Ok(()),
+            OutputMode::Hex if self.written > 0 => self.sink.write_bytes(b"\n"),
+            OutputMode::Hex => Ok(()),
+            OutputMode::HexDump => {
+                let line = match self.line.is_empty() {
+                    true => Ok(()),
+                    false => self.dump_line(),
+                };
+                let end = format!("{:08x}\n", self.written);
+                line.and_then(|_| self.sink.write_bytes(end.as_bytes()))
+            }
+        };
+
+        let result = result.and_then(|_| self.sink.flush_output());
+        self.check(result);
+    }
+
+    /// Stops the program with an `OutputError` if writing failed
+    fn check(&mut self, result: io::Result<()>) {
+        if let Err(e) = result {
+            self.failed = true;
+            panic::panic_any(OutputError {
+                reason: e.to_string(),
+            });
+        }
+    }
+
+    fn dump_line(&mut self) -> io::Result<()> {
+        let address = self.written - self.line.len();
+        let mut hex = String::new();
+        let mut ascii = String::new();
+
+        for (i, &byte) in self.line.iter().enumerate() {
+            if i == 8 {
+                hex.push(' ');
+            }
+            hex.push_str(&format!(" {:02x}", byte));
+            ascii.push(if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '.'
+            });
+        }
+
+        self.line.clear();
+        let line = format!("{:08x} {:<49}  |{}|\n", address, hex, ascii);
+        self.sink.write_bytes(line.as_bytes())
+    }
+}
+
+/// Writer that duplicates everything into two sinks. If one of them fails it
+/// is dropped, and the other one carries on; only when both have failed does
+/// writing fail.
+pub struct Tee<A, B> {
+    first: Option<A>,
+    second: Option<B>,
+    on_drop: Box<OnDrop>,
+}
+
+/// What a `Tee` tells about a sink it drops
+type OnDrop = dyn FnMut(Sink, &io::Error) + Send;
+
+/// Which of the sinks of a `Tee`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sink {
+    First,
+    Second,
+}
+
+impl<A: Write, B: Write> Tee<A, B> {
+    pub fn new(first: A, second: B) -> Tee<A, B> {
+        Tee {
+            first: Some(first),
+            second: Some(second),
+            on_drop: Box::new(|_, _| ()),
+        }
+    }
+
+    /// Calls `report` with the sink and what it failed with when one is
+    /// dropped, which is otherwise done quietly
+    pub fn on_drop(mut self, report: impl FnMut(Sink, &io::Error) + Send + 'static) -> Tee<A, B> {
+        self.on_drop = Box::new(report);
+        self
+    }
+
+    fn each(&mut self, f: &dyn Fn(&mut dyn Write) -> io::Result<()>) -> io::Result<()> {
+        let mut last_error = None;
+
+        if let Some(first) = &mut self.first {
+            if let Err(e) = f(first) {
+                (self.on_drop)(Sink::First, &e);
+                self.first = None;
+                last_error = Some(e);
+            }
+        }
+        if let Some(second) = &mut self.second {
+            if let Err(e) = f(second) {
+                (self.on_drop)(Sink::Second, &e);
+                self.second = None;
+                last_error = Some(e);
+            }
+        }
+
+        if self.first.is_none() && self.second.is_none() {
+            return Err(last_error.unwrap_or_else(|| {
+                io::Error::new(io::ErrorKind::BrokenPipe, "every output sink has failed")
+            }));
+        }
+
+        Ok(())
+    }
+}
+
+impl<A: Write, B: Write> Write for Tee<A, B> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.each(&|sink| sink.write_all(buf))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.each(&|sink| sink.flush())
+    }
+}
+
+/// Writer that flushes after every write, for output that has to be seen as
+/// soon as it is written, like the screen of an interactive game
+pub struct Flushing<W>(pub W);
+
+impl<W: Write> Write for Flushing<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write_all(buf)?;
+        self.0.flush()?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// What tests of anything that writes have in common
+#[cfg(test)]
+pub(crate) mod testing {
+    use std::io::{self, Write};
+    use std::sync::{Arc, Mutex};
+
+    /// Sink that can still be read once an `Output` owns it
+    #[derive(Clone, Default)]
+    pub struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Shared {
+        /// Everything written so far
+        pub fn bytes(&self) -> Vec<u8> {
+            self.0.lock().unwrap().clone()
+        }
+
+        pub fn text(&self) -> String {
+            String::from_utf8(self.bytes()).unwrap()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::testing::Shared;
+    use super::*;
+    use {catch_stop, RunError};
+
+    fn written(mode: OutputMode, bytes: &[u8]) -> String {
+        let sink = Shared::default();
+        let mut output = Output::new(mode, Box::new(sink.clone()));
+        for &byte in bytes {
+            output.write(byte);
+        }
+        output.finish();
+        sink.text()
+    }
+
+    #[test]
+    fn hex_separates_bytes_with_spaces() {
+        assert_eq!(written(OutputMode::Hex, &[0, 1, 2, 3]), "00 01 02 03\n");
+    }
+
+    #[test]
+    fn hexdump_shows_addresses_and_ascii() {
+        let dump = written(OutputMode::HexDump, b"Hi\x00");
+        let expected = format!("00000000 {:<49}  |Hi.|\n00000003\n", " 48 69 00");
+        assert_eq!(dump, expected);
+    }
+
+    #[test]
+    fn tee_writes_the_same_bytes_to_both_sinks() {
+        let (first, second) = (Shared::default(), Shared::default());
+        let mut output = Output::new(
+            OutputMode::Char,
+            Box::new(Tee::new(first.clone(), second.clone())),
+        );
+        for &byte in b"Hello World!\n" {
+            output.write(byte);
+        }
+        output.finish();
+        assert_eq!(first.text(), "Hello World!\n");
+        assert_eq!(second.text(), first.text());
+    }
+
+    struct Broken;
+
+    impl Write for Broken {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::BrokenPipe, "broken"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn tee_carries_on_without_a_failed_sink() {
+        let sink = Shared::default();
+        let dropped = Arc::new(Mutex::new(Vec::new()));
+        let reported = dropped.clone();
+        let mut tee = Tee::new(Broken, sink.clone())
+            .on_drop(move |which, e| reported.lock().unwrap().push((which, e.to_string())));
+        tee.write_all(b"one").unwrap();
+        tee.write_all(b" two").unwrap();
+        assert_eq!(sink.text(), "one two");
+        // Only once, when it failed
+        assert_eq!(
+            *dropped.lock().unwrap(),
+            [(Sink::First, "broken".to_string())]
+        );
+
+        let mut tee = Tee::new(Broken, Broken);
+        assert!(tee.write_all(b"lost").is_err());
+    }
+
+    #[test]
+    fn a_failed_write_stops_the_program_once() {
+        let mut output = Output::new(OutputMode::Char, Box::new(Broken));
+        let e = OutputError {
+            reason: "broken".to_string(),
+        };
+        assert_eq!(catch_stop(|| output.write(b'x')), Err(RunError::Output(e)));
+        // Finishing what stopped doesn't fail all over again
+        assert_eq!(catch_stop(|| output.finish()), Ok(()));
+    }
+}
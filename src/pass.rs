@@ -0,0 +1,99 @@
+use propagate::propagate_values;
+use {mul_add_targets, BigInsn};
+
+/// A rewrite of the fused form of a program that keeps what it does
+pub trait Pass: Sync {
+    fn name(&self) -> &'static str;
+    fn description(&self) -> &'static str;
+    fn run(&self, program: Vec<BigInsn>) -> Vec<BigInsn>;
+}
+
+/// Every pass `lower` runs, in order
+pub static PASSES: &[&dyn Pass] = &[&LowerLoops, &PropagateValues];
+
+/// Runs the passes one after the other
+pub fn run_passes(program: Vec<BigInsn>, passes: &[&dyn Pass]) -> Vec<BigInsn> {
+    passes
+        .iter()
+        .fold(program, |program, pass| pass.run(program))
+}
+
+/// Lowers loops that are really a clear, a scan or a multiply to the
+/// instruction for it, inner loops included
+pub fn lower_loops(program: Vec<BigInsn>) -> Vec<BigInsn> {
+    let mut result = Vec::new();
+
+    for insn in program {
+        let body = match insn {
+            BigInsn::Loop(body) => lower_loops(body),
+            insn => {
+                result.push(insn);
+                continue;
+            }
+        };
+
+        match body.as_slice() {
+            // An odd step reaches zero from anywhere
+            [BigInsn::Adj(n)] if n % 2 != 0 => result.push(BigInsn::Clear),
+            [BigInsn::Move(stride)] => result.push(BigInsn::Seek(*stride)),
+            _ => match mul_add_targets(&body) {
+                Some(targets) => {
+                    result.push(BigInsn::MulAdd(targets));
+                    result.push(BigInsn::Clear);
+                }
+                None => result.push(BigInsn::Loop(body)),
+            },
+        }
+    }
+
+    result
+}
+
+struct LowerLoops;
+
+impl Pass for LowerLoops {
+    fn name(&self) -> &'static str {
+        "loops"
+    }
+
+    fn description(&self) -> &'static str {
+        "turns clear, scan and multiply loops into instructions of their own"
+    }
+
+    fn run(&self, program: Vec<BigInsn>) -> Vec<BigInsn> {
+        lower_loops(program)
+    }
+}
+
+struct PropagateValues;
+
+impl Pass for PropagateValues {
+    fn name(&self) -> &'static str {
+        "propagate"
+    }
+
+    fn description(&self) -> &'static str {
+        "simplifies what works on cells with a known value"
+    }
+
+    fn run(&self, program: Vec<BigInsn>) -> Vec<BigInsn> {
+        propagate_values(program)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {fuse, lex, parse};
+
+    fn lowered(source: &str) -> Vec<BigInsn> {
+        lower_loops(fuse(&parse(lex(source.to_string())).unwrap()))
+    }
+
+    #[test]
+    fn clear_loops_become_clear() {
+        assert_eq!(lowered("[-]"), [BigInsn::Clear]);
+        assert_eq!(lowered("[+]"), [BigInsn::Clear]);
+        assert_eq!(lowered("[[-]]"), [BigInsn::Loop(vec![BigInsn::Clear])]);
+    }
+}
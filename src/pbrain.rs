@@ -0,0 +1,202 @@
+//! The pbrain dialect, Brainfuck with procedures.
+//!
+//! `(` starts defining a procedure, numbered by the value of the current
+//! cell, whose body runs up to the matching `)`; the definition itself does
+//! nothing else, and a later one with the same number replaces it. `:` calls
+//! the procedure numbered by the current cell, on the same tape and pointer.
+//! Procedures can be defined anywhere, inside loops and other procedures
+//! too, and can call themselves.
+//!
+//! None of the optimizer's passes know about procedures, so pbrain programs
+//! are walked as parsed, like `cell` does for cells that aren't plain.
+
+use std::fmt;
+use std::mem;
+
+use debug::location_at;
+use {Location, Machine};
+
+/// How deeply procedure calls may nest before the program is stopped,
+/// rather than the interpreter running out of stack
+pub const MAX_DEPTH: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instruction {
+    IncrementPointer,
+    DecrementPointer,
+    Increment,
+    Decrement,
+    Write,
+    Read,
+    Loop(Vec<Instruction>),
+    /// Defines the procedure numbered by the current cell
+    Procedure(Vec<Instruction>),
+    /// Calls the procedure numbered by the current cell
+    Call,
+}
+
+/// A bracket or parenthesis that doesn't pair up
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxError {
+    /// The offending character
+    pub symbol: char,
+    pub location: Location,
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: unmatched '{}'",
+            self.location.line, self.location.column, self.symbol
+        )
+    }
+}
+
+/// Why a pbrain program stopped short of its end
+#[derive(Debug, Clone, PartialEq)]
+pub enum CallError {
+    /// `:` on a cell no procedure has been defined for
+    Undefined { procedure: u8 },
+    /// Calls nested more than `MAX_DEPTH` deep
+    TooDeep,
+}
+
+impl fmt::Display for CallError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CallError::Undefined { procedure } => {
+                write!(f, "call to procedure {}, which isn't defined", procedure)
+            }
+            CallError::TooDeep => write!(f, "procedure calls nested over {} deep", MAX_DEPTH),
+        }
+    }
+}
+
+pub fn parse(source: &[u8]) -> Result<Vec<Instruction>, SyntaxError> {
+    // The instructions of every block still open, with the offset of the
+    // symbol that opened it
+    let mut open: Vec<(usize, Vec<Instruction>)> = Vec::new();
+    let mut block = Vec::new();
+    let error = |offset: usize| SyntaxError {
+        symbol: source[offset] as char,
+        location: location_at(source, offset),
+    };
+
+    for (offset, &symbol) in source.iter().enumerate() {
+        let instr = match symbol {
+            b'>' => Instruction::IncrementPointer,
+            b'<' => Instruction::DecrementPointer,
+            b'+' => Instruction::Increment,
+            b'-' => Instruction::Decrement,
+            b'.' => Instruction::Write,
+            b',' => Instruction::Read,
+            b':' => Instruction::Call,
+            b'[' | b'(' => {
+                open.push((offset, mem::take(&mut block)));
+                continue;
+            }
+            b']' | b')' => {
+                let (start, outer) = match open.pop() {
+                    Some((start, outer))
+                        if matches!((source[start], symbol), (b'[', b']') | (b'(', b')')) =>
+                    {
+                        (start, outer)
+                    }
+                    _ => return Err(error(offset)),
+                };
+                let body = mem::replace(&mut block, outer);
+                match source[start] {
+                    b'[' => Instruction::Loop(body),
+                    _ => Instruction::Procedure(body),
+                }
+            }
+            _ => continue,
+        };
+        block.push(instr);
+    }
+
+    match open.first() {
+        Some(&(start, _)) => Err(error(start)),
+        None => Ok(block),
+    }
+}
+
+/// Runs a program on the machine from data pointer `p`, returning where
+/// the pointer ends up
+pub fn run(program: &[Instruction], m: &mut Machine, p: i32) -> Result<i32, CallError> {
+    m.enter(p, straight_steps(program));
+    let mut procedures = Procedures {
+        table: vec![None; 256],
+        depth: 0,
+    };
+    procedures.run(program, m, p)
+}
+
+/// Steps the commands of a block cost as the backends count them, one each
+/// but for inner loops, which count their own. A definition is one command,
+/// its body is counted when it is called.
+fn straight_steps(block: &[Instruction]) -> u64 {
+    block
+        .iter()
+        .filter(|instr| !matches!(instr, Instruction::Loop(_)))
+        .count() as u64
+}
+
+/// The procedures defined so far, by number
+struct Procedures<'a> {
+    table: Vec<Option<&'a [Instruction]>>,
+    /// Calls in progress
+    depth: usize,
+}
+
+impl<'a> Procedures<'a> {
+    fn run(
+        &mut self,
+        program: &'a [Instruction],
+        m: &mut Machine,
+        mut p: i32,
+    ) -> Result<i32, CallError> {
+        for instr in program {
+            match instr {
+                Instruction::IncrementPointer => p += 1,
+                Instruction::DecrementPointer => p -= 1,
+                Instruction::Increment => m.tape.set(p, m.tape.get(p).wrapping_add(1)),
+                Instruction::Decrement => m.tape.set(p, m.tape.get(p).wrapping_sub(1)),
+                Instruction::Write => m.output.write(m.tape.get(p)),
+                Instruction::Read => {
+                    if let Some(value) = m.read() {
+                        m.tape.set(p, value);
+                    }
+                }
+                Instruction::Loop(body) => {
+                    let steps = 1 + straight_steps(body);
+                    while m.tape.get(p) != 0 {
+                        m.back_edge(p, steps);
+                        p = self.run(body, m, p)?;
+                    }
+                }
+                Instruction::Procedure(body) => {
+                    self.table[usize::from(m.tape.get(p))] = Some(body);
+                }
+                Instruction::Call => {
+                    let procedure = m.tape.get(p);
+                    let body = self.table[usize::from(procedure)]
+                        .ok_or(CallError::Undefined { procedure })?;
+                    if self.depth == MAX_DEPTH {
+                        return Err(CallError::TooDeep);
+                    }
+
+                    // Costed like a loop iteration, so limits catch runaway
+                    // calls too
+                    m.back_edge(p, 1 + straight_steps(body));
+                    self.depth += 1;
+                    p = self.run(body, m, p)?;
+                    self.depth -= 1;
+                }
+            }
+        }
+
+        Ok(p)
+    }
+}
@@ -0,0 +1,124 @@
+//! JavaScript bindings for running programs in a browser, compiled to
+//! WebAssembly with wasm-bindgen.
+//!
+//! `run` runs a program in one go. A page that wants to show the program
+//! running, or stay responsive while a long one does, makes a `Playground`
+//! and calls `run_for` with a budget of commands from `requestAnimationFrame`
+//! or a timer, drawing the tape and the new output in between:
+//!
+//! ```js
+//! const playground = new Playground(source, new TextEncoder().encode(input));
+//! function frame() {
+//!     const finished = playground.run_for(10000);
+//!     show(playground.take_output(), playground.tape(0, 32), playground.pointer());
+//!     if (!finished) requestAnimationFrame(frame);
+//! }
+//! frame();
+//! ```
+
+use std::io;
+
+use wasm_bindgen::prelude::*;
+
+use debug::{Debugger, Stop};
+use input::{Input, InputMode, RangePolicy};
+use output::{Output, OutputMode};
+use tape::Tape;
+use {interpret, Machine, RunOptions, START_POINTER, TAPE_SIZE};
+
+/// Runs `source` to completion on `input`, returning what it wrote. Fails
+/// with the error's message after `step_limit` steps, if it isn't 0, though
+/// where panics don't unwind, as in most browser builds, the limit traps
+/// instead.
+#[wasm_bindgen]
+pub fn run(source: &str, input: &[u8], step_limit: u64) -> Result<Vec<u8>, JsValue> {
+    let opts = RunOptions {
+        step_limit: Some(step_limit).filter(|&limit| limit > 0),
+        ..RunOptions::default()
+    };
+    interpret(source, input, opts).map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+/// A program run a few commands at a time, see the module documentation
+#[wasm_bindgen]
+pub struct Playground {
+    debugger: Debugger,
+    /// Bytes of the output already handed out by `take_output`
+    taken: usize,
+}
+
+#[wasm_bindgen]
+impl Playground {
+    #[wasm_bindgen(constructor)]
+    pub fn new(source: &str, input: &[u8]) -> Result<Playground, JsValue> {
+        let machine = Machine::new(
+            Tape::new(vec![0; TAPE_SIZE]),
+            Input::new(
+                InputMode::Byte,
+                RangePolicy::Error,
+                Box::new(io::Cursor::new(input.to_vec())),
+            ),
+            Output::new(OutputMode::Char, Box::new(io::sink())).record(),
+        );
+        let debugger = Debugger::new(source.as_bytes(), machine, START_POINTER as i32)
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+        Ok(Playground { debugger, taken: 0 })
+    }
+
+    /// Runs up to `count` more commands, stopping early at a `#` or the end
+    /// of the program. True once it has finished.
+    pub fn run_for(&mut self, count: u64) -> Result<bool, JsValue> {
+        match self.debugger.run_for(count) {
+            Ok(Stop::Finished) => Ok(true),
+            Ok(_) => Ok(false),
+            Err(e) => Err(JsValue::from_str(&e.to_string())),
+        }
+    }
+
+    /// Runs the next command, true once the program has finished
+    pub fn step(&mut self) -> Result<bool, JsValue> {
+        self.run_for(1)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.debugger.is_finished()
+    }
+
+    /// What the program wrote since the last call
+    pub fn take_output(&mut self) -> Vec<u8> {
+        let written = self.debugger.machine().output.recording().unwrap_or(&[]);
+        let new = written[self.taken..].to_vec();
+        self.taken = written.len();
+        new
+    }
+
+    /// `len` cells starting at `start`, counted like `pointer`, with zeros
+    /// for cells the tape hasn't grown to
+    pub fn tape(&self, start: i32, len: u32) -> Vec<u8> {
+        let tape = &self.debugger.machine().tape;
+        (start..start.saturating_add(len as i32))
+            .map(|p| tape.peek(p).unwrap_or(0))
+            .collect()
+    }
+
+    pub fn pointer(&self) -> i32 {
+        self.debugger.pointer()
+    }
+
+    /// Commands run so far
+    pub fn steps(&self) -> u64 {
+        self.debugger.steps()
+    }
+
+    /// Line of the next command, counted from 1, 0 once finished
+    pub fn line(&self) -> usize {
+        self.debugger.location().map_or(0, |location| location.line)
+    }
+
+    /// Column of the next command, counted from 1, 0 once finished
+    pub fn column(&self) -> usize {
+        self.debugger
+            .location()
+            .map_or(0, |location| location.column)
+    }
+}
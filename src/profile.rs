@@ -0,0 +1,268 @@
+//! Instrumented execution of the fused form, for finding out where a program
+//! spends its time. It is a lot slower than any backend.
+//!
+//! Instructions are identified by their path of indices into the program
+//! profiled, as printed by `--dump-ir --opt basic` for the fused form: `4.1`
+//! is the second instruction in the body of the loop at index 4. Source
+//! positions aren't kept through parsing, but in a program fused as written
+//! the loops are the source's own in order, so `Profile::locate` can place
+//! them.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt;
+
+use pass::lower_loops;
+use {
+    decode, fused_iteration_steps, fused_straight_steps, BigInsn, Location, Machine, OpCode,
+    SEEK_STEPS,
+};
+
+/// How many loops the summary lists
+const HOTTEST: usize = 10;
+
+/// What one instruction did over the run
+#[derive(Debug, Clone, PartialEq)]
+pub struct Counts {
+    pub path: Vec<usize>,
+    pub executions: u64,
+    /// Times the body ran, for loops
+    pub iterations: u64,
+    /// Executions of the instruction and everything in its body
+    pub steps: u64,
+    pub is_loop: bool,
+    /// Whether the optimizer turns the loop into instructions of its own,
+    /// such as a clear or a multiply
+    pub optimized: bool,
+    /// Where a loop's `[` is, once located
+    pub location: Option<Location>,
+}
+
+/// What a profiled run did, with an entry for every instruction in program
+/// order, whether it ran or not
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub instructions: Vec<Counts>,
+    /// Cells the data pointer moved across, in either direction
+    pub distance: u64,
+}
+
+impl Profile {
+    pub fn steps(&self) -> u64 {
+        self.instructions
+            .iter()
+            .map(|counts| counts.executions)
+            .sum()
+    }
+
+    pub fn iterations(&self) -> u64 {
+        self.instructions
+            .iter()
+            .map(|counts| counts.iterations)
+            .sum()
+    }
+
+    /// Gives every loop the location of its `[` in `source`. Only right for
+    /// a profile of the program fused as written, with no loop lowered or
+    /// folded away.
+    pub fn locate(&mut self, source: &[u8]) {
+        let mut brackets = Vec::new();
+        let (mut line, mut start) = (1, 0);
+        for (offset, &byte) in source.iter().enumerate() {
+            match decode(byte) {
+                Some(OpCode::LoopBegin) => brackets.push((line, start, offset)),
+                None if byte == b'\n' => {
+                    line += 1;
+                    start = offset + 1;
+                }
+                _ => (),
+            }
+        }
+
+        let loops = self.instructions.iter_mut().filter(|counts| counts.is_loop);
+        for (counts, &(line, start, offset)) in loops.zip(&brackets) {
+            let text = String::from_utf8_lossy(&source[start..]);
+            let text = text.lines().next().unwrap_or_default();
+            counts.location = Some(Location {
+                line,
+                column: String::from_utf8_lossy(&source[start..offset])
+                    .chars()
+                    .count()
+                    + 1,
+                text: text.trim_end_matches('\r').to_string(),
+            });
+        }
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "steps:           {}", self.steps())?;
+        writeln!(f, "loop iterations: {}", self.iterations())?;
+        writeln!(f, "pointer moved:   {} cells", self.distance)?;
+
+        let mut loops: Vec<&Counts> = self
+            .instructions
+            .iter()
+            .filter(|counts| counts.iterations > 0)
+            .collect();
+        loops.sort_by_key(|counts| Reverse(counts.steps));
+
+        if !loops.is_empty() {
+            writeln!(f, "hottest loops, by the steps run in them:")?;
+        }
+        let total = self.steps().max(1);
+        for counts in loops.iter().take(HOTTEST) {
+            let path: Vec<String> = counts.path.iter().map(|i| i.to_string()).collect();
+            let location = match &counts.location {
+                Some(location) => format!("{}:{}", location.line, location.column),
+                None => "-".to_string(),
+            };
+            write!(
+                f,
+                "  {:<10} {:<16} {:>5.1}% {} iterations over {} entries",
+                location,
+                path.join("."),
+                100.0 * counts.steps as f64 / total as f64,
+                counts.iterations,
+                counts.executions
+            )?;
+            // Worth knowing when deciding what to optimize next
+            if counts.optimized {
+                write!(f, ", lowered by the optimizer")?;
+            }
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Counters, by the address of the instruction they belong to
+#[derive(Default)]
+struct Profiler {
+    executions: HashMap<*const BigInsn, u64>,
+    iterations: HashMap<*const BigInsn, u64>,
+    distance: u64,
+}
+
+/// Runs the fused form of a program like the `big` backend does, returning
+/// the final data pointer and what every instruction did
+pub fn profile(program: &[BigInsn], m: &mut Machine, p: i32) -> (i32, Profile) {
+    let mut profiler = Profiler::default();
+    m.enter(p, fused_straight_steps(program));
+    let p = profiler.run(program, m, p);
+
+    let mut instructions = Vec::new();
+    profiler.collect(program, &mut Vec::new(), &mut instructions);
+
+    let profile = Profile {
+        instructions,
+        distance: profiler.distance,
+    };
+    (p, profile)
+}
+
+impl Profiler {
+    fn run(&mut self, program: &[BigInsn], m: &mut Machine, mut p: i32) -> i32 {
+        for insn in program {
+            *self.executions.entry(insn).or_insert(0) += 1;
+
+            match insn {
+                BigInsn::Move(n) => {
+                    p += n;
+                    self.distance += u64::from(n.unsigned_abs());
+                }
+                BigInsn::Adj(n) => {
+                    m.tape.set(p, m.tape.get(p).wrapping_add(*n as u8));
+                }
+                BigInsn::Set(value) => m.tape.set(p, *value),
+                BigInsn::Clear => m.tape.set(p, 0),
+                BigInsn::MulAdd(targets) => {
+                    let count = m.tape.get(p);
+                    if count != 0 {
+                        for &(offset, k) in targets {
+                            let cell = m.tape.get(p + offset);
+                            m.tape
+                                .set(p + offset, cell.wrapping_add(count.wrapping_mul(k as u8)));
+                        }
+                    }
+                }
+                BigInsn::Seek(stride) => {
+                    while m.tape.get(p) != 0 {
+                        *self.iterations.entry(insn).or_insert(0) += 1;
+                        m.back_edge(p, SEEK_STEPS);
+                        p += stride;
+                        self.distance += u64::from(stride.unsigned_abs());
+                    }
+                }
+                BigInsn::Write => m.output.write(m.tape.get(p)),
+                BigInsn::Read => {
+                    if let Some(value) = m.read() {
+                        m.tape.set(p, value);
+                    }
+                }
+                BigInsn::Loop(body) => {
+                    let steps = fused_iteration_steps(body);
+                    while m.tape.get(p) != 0 {
+                        *self.iterations.entry(insn).or_insert(0) += 1;
+                        m.back_edge(p, steps);
+                        p = self.run(body, m, p);
+                    }
+                }
+                BigInsn::WriteBytes(bytes) => {
+                    for &byte in bytes {
+                        m.output.write(byte);
+                    }
+                }
+                BigInsn::Snapshot { cells, pointer } => {
+                    for &(offset, value) in cells {
+                        m.tape.set(p + offset, value);
+                    }
+                    p += pointer;
+                    self.distance += u64::from(pointer.unsigned_abs());
+                }
+            }
+        }
+
+        p
+    }
+
+    /// Lists the counters of every instruction in program order, returning
+    /// the steps run in all of them
+    fn collect(&self, program: &[BigInsn], path: &mut Vec<usize>, into: &mut Vec<Counts>) -> u64 {
+        let mut total = 0;
+
+        for (i, insn) in program.iter().enumerate() {
+            let key = insn as *const BigInsn;
+            let executions = self.executions.get(&key).cloned().unwrap_or(0);
+            path.push(i);
+
+            let index = into.len();
+            into.push(Counts {
+                path: path.clone(),
+                executions,
+                iterations: self.iterations.get(&key).cloned().unwrap_or(0),
+                steps: executions,
+                is_loop: false,
+                optimized: false,
+                location: None,
+            });
+            if let BigInsn::Loop(body) = insn {
+                let steps = self.collect(body, path, into);
+                let counts = &mut into[index];
+                counts.steps += steps;
+                counts.is_loop = true;
+                counts.optimized = !matches!(
+                    lower_loops(vec![insn.clone()]).as_slice(),
+                    [BigInsn::Loop(_)]
+                );
+            }
+            total += into[index].steps;
+
+            path.pop();
+        }
+
+        total
+    }
+}
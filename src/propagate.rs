@@ -0,0 +1,214 @@
+use std::collections::BTreeMap;
+
+use {balanced_adjustments, BigInsn};
+
+/// Tracks which cells hold a known constant and uses that to simplify the
+/// program. Adjustments of a known cell become a `Set`, storing a zero
+/// becomes a `Clear`, and loops and clears of a cell that is already zero,
+/// like a `[-]` right after another loop, are dropped. A transfer like
+/// `[->+<]` (as a loop or a `MulAdd`) entered with a known count is replaced
+/// by its closed form, so `[-]+++++[->+<]` ends up as a single transfer of 5.
+///
+/// Nothing is assumed about the tape on entry so this is also correct for
+/// seeded tapes and loop bodies.
+pub fn propagate_values(program: Vec<BigInsn>) -> Vec<BigInsn> {
+    let mut known = Known::default();
+    let mut result = Vec::new();
+
+    for insn in program {
+        match insn {
+            BigInsn::Move(n) => {
+                known.shift(n);
+                result.push(insn);
+            }
+            BigInsn::Adj(n) => match known.get(0) {
+                Some(value) => set(&mut result, &mut known, value.wrapping_add(n as u8)),
+                None => result.push(insn),
+            },
+            BigInsn::Set(value) => set(&mut result, &mut known, value),
+            BigInsn::Clear => set(&mut result, &mut known, 0),
+            BigInsn::Read => {
+                known.forget(0);
+                result.push(insn);
+            }
+            BigInsn::Write | BigInsn::WriteBytes(_) => result.push(insn),
+            // Never entered when the cell is already zero
+            BigInsn::Loop(_) | BigInsn::Seek(_) if known.get(0) == Some(0) => (),
+            BigInsn::Seek(_) => {
+                // Lands who knows where, but on a zero
+                known = Known::default();
+                known.cells.insert(0, 0);
+                result.push(insn);
+            }
+            BigInsn::Loop(body) => {
+                let counted = known
+                    .get(0)
+                    .filter(|&count| count != 0)
+                    .and_then(|count| counted_loop(&body, count));
+
+                match counted {
+                    Some((targets, iterations)) => {
+                        add_multiples(&targets, iterations, &mut result, &mut known);
+                        set(&mut result, &mut known, 0);
+                    }
+                    None => {
+                        known = Known::default();
+                        known.cells.insert(0, 0);
+                        result.push(BigInsn::Loop(propagate_values(body)));
+                    }
+                }
+            }
+            BigInsn::MulAdd(targets) => match known.get(0) {
+                Some(count) => add_multiples(&targets, i32::from(count), &mut result, &mut known),
+                None => {
+                    for &(offset, _) in &targets {
+                        known.forget(offset);
+                    }
+                    result.push(BigInsn::MulAdd(targets));
+                }
+            },
+            BigInsn::Snapshot { cells, pointer } => {
+                for &(offset, value) in &cells {
+                    known.cells.insert(offset, value);
+                }
+                known.shift(pointer);
+                result.push(BigInsn::Snapshot { cells, pointer });
+            }
+        }
+    }
+
+    result
+}
+
+/// Sets the current cell, unless it already holds the value, replacing a
+/// `Set` or `Clear` of the same cell just before it
+fn set(result: &mut Vec<BigInsn>, known: &mut Known, value: u8) {
+    if known.get(0) == Some(value) {
+        return;
+    }
+    if let Some(BigInsn::Set(_)) | Some(BigInsn::Clear) = result.last() {
+        result.pop();
+    }
+    result.push(if value == 0 {
+        BigInsn::Clear
+    } else {
+        BigInsn::Set(value)
+    });
+    known.cells.insert(0, value);
+}
+
+/// Known cell values, by offset from the current data pointer
+#[derive(Default)]
+struct Known {
+    cells: BTreeMap<i32, u8>,
+}
+
+impl Known {
+    fn get(&self, offset: i32) -> Option<u8> {
+        self.cells.get(&offset).cloned()
+    }
+
+    fn forget(&mut self, offset: i32) {
+        self.cells.remove(&offset);
+    }
+
+    /// Follows the data pointer moving by `n`
+    fn shift(&mut self, n: i32) {
+        if n != 0 {
+            let cells = std::mem::take(&mut self.cells);
+            self.cells = cells.into_iter().map(|(k, v)| (k - n, v)).collect();
+        }
+    }
+}
+
+/// The cells a balanced loop like `[+>--<]` adjusts through its (offset,
+/// per iteration) pairs, and how many times it runs when entered with
+/// `count`, if it steps its own cell by exactly one
+fn counted_loop(body: &[BigInsn], count: u8) -> Option<(Vec<(i32, i32)>, i32)> {
+    let mut totals = balanced_adjustments(body)?;
+
+    let iterations = match totals.remove(&0) {
+        Some(-1) => i32::from(count),
+        Some(1) => 256 - i32::from(count),
+        _ => return None,
+    };
+
+    Some((totals.into_iter().collect(), iterations))
+}
+
+/// Emits adding `iterations` times each multiplier to its cell
+fn add_multiples(
+    targets: &[(i32, i32)],
+    iterations: i32,
+    result: &mut Vec<BigInsn>,
+    known: &mut Known,
+) {
+    let mut position = 0;
+
+    for &(offset, k) in targets {
+        let amount = k.wrapping_mul(iterations) as u8;
+        if amount == 0 {
+            continue;
+        }
+
+        result.push(BigInsn::Move(offset - position));
+        position = offset;
+
+        match known.get(offset) {
+            Some(value) => {
+                let value = value.wrapping_add(amount);
+                result.push(if value == 0 {
+                    BigInsn::Clear
+                } else {
+                    BigInsn::Set(value)
+                });
+                known.cells.insert(offset, value);
+            }
+            None => result.push(BigInsn::Adj(i32::from(amount))),
+        }
+    }
+
+    if position != 0 {
+        result.push(BigInsn::Move(-position));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {lex, lower, parse, BigInsn};
+
+    fn lowered(source: &str) -> Vec<BigInsn> {
+        lower(&parse(lex(source.to_string())).unwrap())
+    }
+
+    #[test]
+    fn a_loop_right_after_a_clear_is_dropped() {
+        assert_eq!(lowered("[-][.,]"), [BigInsn::Clear]);
+        assert_eq!(lowered("[>][<+>-]"), [BigInsn::Seek(1)]);
+    }
+
+    #[test]
+    fn a_loop_of_a_cell_that_may_be_nonzero_is_kept() {
+        let body = vec![BigInsn::Write, BigInsn::Read];
+        assert_eq!(
+            lowered("[-]+[.,]"),
+            [BigInsn::Set(1), BigInsn::Loop(body.clone())]
+        );
+        assert_eq!(lowered(",[.,]"), [BigInsn::Read, BigInsn::Loop(body)]);
+    }
+
+    #[test]
+    fn a_transfer_of_a_known_count_is_an_add() {
+        // The count is 5, so the multiply adds 5 to the next cell
+        assert_eq!(
+            lowered("[-]+++++[->+<]"),
+            [
+                BigInsn::Set(5),
+                BigInsn::Move(1),
+                BigInsn::Adj(5),
+                BigInsn::Move(-1),
+                BigInsn::Clear,
+            ]
+        );
+    }
+}
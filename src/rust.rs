@@ -0,0 +1,153 @@
+use std::fmt::Write;
+
+use input::EofBehavior;
+use BigInsn;
+
+/// Translates a program to the source of a standalone Rust program, which
+/// `rustc -O` turns into an executable. The tape is a vector initialized from
+/// `tape`, loops become `while` loops and I/O goes through stdin and a
+/// buffered stdout, flushed before every read. Unlike the interpreter's tape
+/// the vector doesn't grow, running off either end panics.
+pub fn emit(program: &[BigInsn], tape: &[u8], data_pointer: usize, eof: EofBehavior) -> String {
+    let mut emitter = Emitter {
+        out: String::new(),
+        eof,
+        depth: 1,
+    };
+
+    emitter.header(tape, data_pointer);
+    emitter.block(program);
+    emitter.line("output.flush().unwrap();");
+    writeln!(emitter.out, "}}").unwrap();
+
+    emitter.out
+}
+
+/// Everything up to the body of `main` that is the same for every program
+const PRELUDE: &str = r#"// Generated by bf --emit rust
+
+// Not every program reads, or writes
+#![allow(unused)]
+
+use std::io::{self, Read, Write};
+
+fn read(
+    output: &mut impl Write,
+    input: &mut impl Iterator<Item = io::Result<u8>>,
+) -> Option<u8> {
+    output.flush().unwrap();
+    input.next().map(|byte| byte.unwrap())
+}
+
+fn main() {
+"#;
+
+struct Emitter {
+    out: String,
+    eof: EofBehavior,
+    /// How deeply the current line is indented
+    depth: usize,
+}
+
+impl Emitter {
+    fn header(&mut self, tape: &[u8], data_pointer: usize) {
+        self.out.push_str(PRELUDE);
+
+        if tape.iter().all(|&cell| cell == 0) {
+            self.line(&format!("let mut tape = vec![0u8; {}];", tape.len()));
+        } else {
+            let cells: Vec<String> = tape.iter().map(|cell| cell.to_string()).collect();
+            self.line(&format!(
+                "let mut tape: Vec<u8> = vec![{}];",
+                cells.join(", ")
+            ));
+        }
+        self.line(&format!("let mut p: usize = {};", data_pointer));
+        self.line("let mut input = io::stdin().lock().bytes();");
+        self.line("let mut output = io::BufWriter::new(io::stdout().lock());");
+        self.out.push('\n');
+    }
+
+    fn line(&mut self, line: &str) {
+        writeln!(self.out, "{:width$}{}", "", line, width = 4 * self.depth).unwrap();
+    }
+
+    /// The cell `offset` away from the data pointer
+    fn cell(offset: i32) -> String {
+        match offset {
+            0 => "tape[p]".to_string(),
+            n if n > 0 => format!("tape[p + {}]", n),
+            n => format!("tape[p - {}]", -n),
+        }
+    }
+
+    fn move_by(&mut self, n: i32) {
+        match n {
+            0 => (),
+            n if n > 0 => self.line(&format!("p += {};", n)),
+            n => self.line(&format!("p -= {};", -n)),
+        }
+    }
+
+    fn block(&mut self, program: &[BigInsn]) {
+        for insn in program {
+            self.insn(insn);
+        }
+    }
+
+    fn insn(&mut self, insn: &BigInsn) {
+        let here = Emitter::cell(0);
+
+        match insn {
+            BigInsn::Move(n) => self.move_by(*n),
+            BigInsn::Adj(n) => self.line(&format!("{0} = {0}.wrapping_add({1});", here, *n as u8)),
+            BigInsn::Set(value) => self.line(&format!("{} = {};", here, value)),
+            BigInsn::Clear => self.line(&format!("{} = 0;", here)),
+            BigInsn::MulAdd(targets) => {
+                for &(offset, k) in targets {
+                    self.line(&format!(
+                        "{0} = {0}.wrapping_add({1}.wrapping_mul({2}));",
+                        Emitter::cell(offset),
+                        here,
+                        k as u8
+                    ));
+                }
+            }
+            BigInsn::Seek(stride) => self.insn(&BigInsn::Loop(vec![BigInsn::Move(*stride)])),
+            BigInsn::Write => self.line(&format!("output.write_all(&[{}]).unwrap();", here)),
+            BigInsn::Read => {
+                let read = "read(&mut output, &mut input)";
+                match self.eof {
+                    EofBehavior::Unchanged => {
+                        self.line(&format!("if let Some(byte) = {} {{", read));
+                        self.line(&format!("    {} = byte;", here));
+                        self.line("}");
+                    }
+                    EofBehavior::Zero => self.line(&format!("{} = {}.unwrap_or(0);", here, read)),
+                    EofBehavior::Max => self.line(&format!("{} = {}.unwrap_or(255);", here, read)),
+                }
+            }
+            BigInsn::Loop(body) => {
+                self.line(&format!("while {} != 0 {{", here));
+                self.depth += 1;
+                self.block(body);
+                self.depth -= 1;
+                self.line("}");
+            }
+            BigInsn::WriteBytes(bytes) => {
+                let escaped: String = bytes
+                    .iter()
+                    .flat_map(|&byte| std::ascii::escape_default(byte))
+                    .map(char::from)
+                    .collect();
+                self.line(&format!("output.write_all(b\"{}\").unwrap();", escaped));
+            }
+            BigInsn::Snapshot { cells, pointer } => {
+                for &(offset, value) in cells {
+                    self.line(&format!("{} = {};", Emitter::cell(offset), value));
+                }
+                self.move_by(*pointer);
+            }
+        }
+    }
+}
@@ -0,0 +1,63 @@
+/// Initial tape contents given on the command line, e.g. `1,2,@here:3,4`.
+/// A cell may be prefixed with `@name:` to label its position.
+pub struct Seed {
+    pub cells: Vec<u8>,
+    labels: Vec<(String, usize)>,
+}
+
+impl Seed {
+    pub fn parse(spec: &str) -> Result<Seed, String> {
+        let mut cells = Vec::new();
+        let mut labels: Vec<(String, usize)> = Vec::new();
+
+        for item in spec.split(',') {
+            let item = item.trim();
+
+            let value = if let Some(labelled) = item.strip_prefix('@') {
+                let (name, value) = labelled
+                    .split_once(':')
+                    .ok_or_else(|| format!("label '@{}' must be followed by ':value'", labelled))?;
+
+                if name.is_empty() {
+                    return Err(format!("empty label in '{}'", item));
+                }
+                if labels.iter().any(|(existing, _)| existing == name) {
+                    return Err(format!("label '{}' is defined twice", name));
+                }
+
+                labels.push((name.to_string(), cells.len()));
+                value
+            } else {
+                item
+            };
+
+            let value = value
+                .parse::<u8>()
+                .map_err(|_| format!("invalid cell value '{}'", value))?;
+            cells.push(value);
+        }
+
+        Ok(Seed { cells, labels })
+    }
+
+    /// Offset of a labelled cell from the start of the seed
+    pub fn label(&self, name: &str) -> Option<usize> {
+        self.labels
+            .iter()
+            .find(|(label, _)| label == name)
+            .map(|&(_, offset)| offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_are_offsets_into_the_seed() {
+        let seed = Seed::parse("1,2,@here:3,4").unwrap();
+        assert_eq!(seed.cells, [1, 2, 3, 4]);
+        assert_eq!(seed.label("here"), Some(2));
+        assert_eq!(seed.label("there"), None);
+    }
+}
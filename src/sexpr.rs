@@ -0,0 +1,98 @@
+//! Every stage of a program as S-expressions, one top-level form per line,
+//! for tools that would rather not parse `Debug` output or pull in JSON.
+//!
+//! The parsed tree uses `(right)`, `(left)`, `(inc)`, `(dec)`, `(write)`,
+//! `(read)`, `(loop ...)`, `(write-bytes 72 105)` and
+//! `(snapshot (cells (0 7) (2 1)) (pointer 2))`. The `BigInsn` form adds
+//! `(move n)`, `(adj n)`, `(set v)`, `(clear)`, `(mul-add (offset k) ...)` and
+//! `(seek stride)`. Bytecode is `(index op operands...)`, e.g. `(3 open 9 4)`
+//! for an `Open` at index 3 that jumps to 9 and costs 4 steps an iteration.
+
+use std::fmt;
+
+use bytecode::Op;
+use {BigInsn, Instruction};
+
+pub fn from_instructions(program: &[Instruction]) -> String {
+    lines(program.iter().map(instruction))
+}
+
+pub fn from_lowered(program: &[BigInsn]) -> String {
+    lines(program.iter().map(big_insn))
+}
+
+pub fn from_bytecode(code: &[Op]) -> String {
+    lines(code.iter().enumerate().map(|(i, &op)| {
+        let op = match op {
+            Op::Move(n) => format!("move {}", n),
+            Op::Adj(offset, n) => format!("adj {} {}", offset, n),
+            Op::Set(offset, value) => format!("set {} {}", offset, value),
+            Op::MulAdd { from, to, k } => format!("mul-add {} {} {}", from, to, k),
+            Op::Seek(stride) => format!("seek {}", stride),
+            Op::Write(offset) => format!("write {}", offset),
+            Op::Emit(byte) => format!("emit {}", byte),
+            Op::Read(offset) => format!("read {}", offset),
+            Op::Open { close, steps } => format!("open {} {}", close, steps),
+            Op::Close { open, steps } => format!("close {} {}", open, steps),
+            Op::Steps(steps) => format!("steps {}", steps),
+        };
+        format!("({} {})", i, op)
+    }))
+}
+
+fn lines(forms: impl Iterator<Item = String>) -> String {
+    forms.map(|form| form + "\n").collect()
+}
+
+/// `(head item ...)`, with every item made by `f`
+fn form<T>(head: &str, items: &[T], f: impl Fn(&T) -> String) -> String {
+    let mut form = format!("({}", head);
+    for item in items {
+        form.push(' ');
+        form.push_str(&f(item));
+    }
+    form + ")"
+}
+
+fn pair(a: impl fmt::Display, b: impl fmt::Display) -> String {
+    format!("({} {})", a, b)
+}
+
+fn bytes(bytes: &[u8]) -> String {
+    form("write-bytes", bytes, u8::to_string)
+}
+
+fn snapshot(cells: &[(i32, u8)], pointer: i32) -> String {
+    let cells = form("cells", cells, |&(offset, value)| pair(offset, value));
+    format!("(snapshot {} (pointer {}))", cells, pointer)
+}
+
+fn instruction(insn: &Instruction) -> String {
+    match insn {
+        Instruction::IncrementPointer => "(right)".to_string(),
+        Instruction::DecrementPointer => "(left)".to_string(),
+        Instruction::Increment => "(inc)".to_string(),
+        Instruction::Decrement => "(dec)".to_string(),
+        Instruction::Write => "(write)".to_string(),
+        Instruction::Read => "(read)".to_string(),
+        Instruction::Loop(body) => form("loop", body, instruction),
+        Instruction::WriteBytes(written) => bytes(written),
+        Instruction::Snapshot { cells, pointer } => snapshot(cells, *pointer),
+    }
+}
+
+fn big_insn(insn: &BigInsn) -> String {
+    match insn {
+        BigInsn::Move(n) => format!("(move {})", n),
+        BigInsn::Adj(n) => format!("(adj {})", n),
+        BigInsn::Set(value) => format!("(set {})", value),
+        BigInsn::Clear => "(clear)".to_string(),
+        BigInsn::MulAdd(targets) => form("mul-add", targets, |&(offset, k)| pair(offset, k)),
+        BigInsn::Seek(stride) => format!("(seek {})", stride),
+        BigInsn::Write => "(write)".to_string(),
+        BigInsn::Read => "(read)".to_string(),
+        BigInsn::Loop(body) => form("loop", body, big_insn),
+        BigInsn::WriteBytes(written) => bytes(written),
+        BigInsn::Snapshot { cells, pointer } => snapshot(cells, *pointer),
+    }
+}
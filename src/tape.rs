@@ -0,0 +1,271 @@
+use std::convert::TryInto;
+use std::fmt;
+use std::ops::Range;
+use std::panic;
+
+use cell::Cell;
+
+/// What becomes of an access to a cell beyond the ones a tape started with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TapePolicy {
+    /// The tape grows to cover it
+    Grow,
+    /// Addresses wrap around, so the cell past the last one is the first
+    Wrap,
+    /// The program stops, by panicking with `OutOfBounds`
+    Error,
+}
+
+/// Panic payload of a program that went off a tape with `TapePolicy::Error`
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutOfBounds {
+    pub address: i32,
+    pub len: usize,
+}
+
+impl fmt::Display for OutOfBounds {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "cell {} is off the tape, which has cells 0 to {}",
+            self.address,
+            self.len as i64 - 1
+        )
+    }
+}
+
+/// The cells of a machine, addressed by the same data pointer values the
+/// backends work with. It starts out covering `0..len`, and by default
+/// grows in either direction, doubling in size, the first time a cell
+/// beyond it is written. Cells that were never written read as zero.
+/// Cells are bytes unless the tape is made of some other `Cell`.
+#[derive(Debug, Clone)]
+pub struct Tape<C = u8> {
+    cells: Vec<C>,
+    /// Index in `cells` of address 0
+    origin: isize,
+    policy: TapePolicy,
+}
+
+impl<C: Cell> Tape<C> {
+    pub fn new(cells: Vec<C>) -> Tape<C> {
+        Tape {
+            cells,
+            origin: 0,
+            policy: TapePolicy::Grow,
+        }
+    }
+
+    /// A tape that never grows has to have at least one cell
+    pub fn with_policy(mut self, policy: TapePolicy) -> Tape<C> {
+        assert!(
+            policy == TapePolicy::Grow || !self.cells.is_empty(),
+            "a {:?} tape needs cells",
+            policy
+        );
+        self.policy = policy;
+        self
+    }
+
+    /// Moves the tape so that its first cell is at address `start`
+    pub fn starting_at(mut self, start: i32) -> Tape<C> {
+        self.origin = -start as isize;
+        self
+    }
+
+    pub fn policy(&self) -> TapePolicy {
+        self.policy
+    }
+
+    /// Addresses currently backed by memory
+    pub fn range(&self) -> Range<i32> {
+        let start = -self.origin;
+        (start as i32)..(start + self.cells.len() as isize) as i32
+    }
+
+    #[inline]
+    pub fn get(&self, p: i32) -> C {
+        let index = p as isize + self.origin;
+        match self.cells.get(index as usize) {
+            Some(&cell) => cell,
+            None => self.get_beyond(p),
+        }
+    }
+
+    /// The cell at `p`, or None where reading it would stop the program,
+    /// for looking at a tape from outside it
+    pub fn peek(&self, p: i32) -> Option<C> {
+        match self.policy {
+            TapePolicy::Error if !self.range().contains(&p) => None,
+            _ => Some(self.get(p)),
+        }
+    }
+
+    #[inline]
+    pub fn set(&mut self, p: i32, value: C) {
+        let index = p as isize + self.origin;
+        match self.cells.get_mut(index as usize) {
+            Some(cell) => *cell = value,
+            None => self.set_beyond(p, value),
+        }
+    }
+
+    #[cold]
+    fn get_beyond(&self, p: i32) -> C {
+        match self.policy {
+            TapePolicy::Grow => C::default(),
+            TapePolicy::Wrap => self.cells[self.wrap(p)],
+            TapePolicy::Error => self.off_the_end(p),
+        }
+    }
+
+    #[cold]
+    fn set_beyond(&mut self, p: i32, value: C) {
+        match self.policy {
+            TapePolicy::Grow => {
+                self.grow_to(p);
+                let index = p as isize + self.origin;
+                self.cells[index as usize] = value;
+            }
+            TapePolicy::Wrap => {
+                let index = self.wrap(p);
+                self.cells[index] = value;
+            }
+            TapePolicy::Error => self.off_the_end(p),
+        }
+    }
+
+    /// Index of the cell at `p` on a tape that wraps, and so never grew and
+    /// still has its origin at 0
+    fn wrap(&self, p: i32) -> usize {
+        (p as i64).rem_euclid(self.cells.len() as i64) as usize
+    }
+
+    fn off_the_end(&self, p: i32) -> ! {
+        panic::panic_any(OutOfBounds {
+            address: p,
+            len: self.cells.len(),
+        })
+    }
+
+    /// Doubles the tape towards `p` until it covers it. Addresses don't
+    /// change, growing to the left just moves the origin along.
+    fn grow_to(&mut self, p: i32) {
+        loop {
+            let index = p as isize + self.origin;
+            let extra = self.cells.len().max(1);
+
+            if index < 0 {
+                let mut cells = vec![C::default(); extra];
+                cells.extend_from_slice(&self.cells);
+                self.cells = cells;
+                self.origin += extra as isize;
+            } else if index as usize >= self.cells.len() {
+                let len = self.cells.len() + extra;
+                self.cells.resize(len, C::default());
+            } else {
+                return;
+            }
+        }
+    }
+}
+
+impl Tape {
+    /// The memory backing the tape and the index in it of address 0, for
+    /// code that works on the cells directly
+    #[cfg(feature = "jit")]
+    pub(crate) fn memory(&mut self) -> (&mut [u8], isize) {
+        (&mut self.cells, self.origin)
+    }
+
+    /// Grows the tape to cover every address from `low` to `high`
+    #[cfg(feature = "jit")]
+    pub(crate) fn cover(&mut self, low: i32, high: i32) {
+        self.grow_to(low);
+        self.grow_to(high);
+    }
+
+    /// The first address from `p` on, going by `stride`, that holds a zero.
+    /// Strides of one cell search memory a word at a time, like `memchr`.
+    pub fn seek_zero(&self, mut p: i32, stride: i32) -> i32 {
+        // Only a growing tape is all zeros past the end of its memory
+        if self.policy != TapePolicy::Grow {
+            while self.get(p) != 0 {
+                p += stride;
+            }
+            return p;
+        }
+
+        let start = p as isize + self.origin;
+        if start < 0 || start as usize >= self.cells.len() {
+            return p;
+        }
+        let start = start as usize;
+
+        // Past the end of memory every cell is zero
+        let index = match stride {
+            1 => first_zero(&self.cells[start..])
+                .map_or(self.cells.len() as isize, |i| (start + i) as isize),
+            -1 => last_zero(&self.cells[..=start]).map_or(-1, |i| i as isize),
+            _ => {
+                let mut index = start as isize;
+                while self
+                    .cells
+                    .get(index as usize)
+                    .is_some_and(|&cell| cell != 0)
+                {
+                    index += stride as isize;
+                }
+                index
+            }
+        };
+
+        (index - self.origin) as i32
+    }
+}
+
+const WORD: usize = 8;
+
+/// Whether any byte of the word is zero
+#[inline]
+fn has_zero(word: u64) -> bool {
+    word.wrapping_sub(0x0101_0101_0101_0101) & !word & 0x8080_8080_8080_8080 != 0
+}
+
+fn first_zero(cells: &[u8]) -> Option<usize> {
+    let mut offset = 0;
+
+    for chunk in cells.chunks_exact(WORD) {
+        if has_zero(u64::from_ne_bytes(chunk.try_into().unwrap())) {
+            break;
+        }
+        offset += WORD;
+    }
+
+    cells[offset..]
+        .iter()
+        .position(|&cell| cell == 0)
+        .map(|i| offset + i)
+}
+
+fn last_zero(cells: &[u8]) -> Option<usize> {
+    let mut end = cells.len();
+
+    for chunk in cells.rchunks_exact(WORD) {
+        if has_zero(u64::from_ne_bytes(chunk.try_into().unwrap())) {
+            break;
+        }
+        end -= WORD;
+    }
+
+    cells[..end].iter().rposition(|&cell| cell == 0)
+}
+
+/// Tapes are equal when every address reads the same, however much memory
+/// each of them happens to have grown
+impl<C: Cell> PartialEq for Tape<C> {
+    fn eq(&self, other: &Tape<C>) -> bool {
+        let (a, b) = (self.range(), other.range());
+        (a.start.min(b.start)..a.end.max(b.end)).all(|p| self.get(p) == other.get(p))
+    }
+}
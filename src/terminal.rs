@@ -0,0 +1,114 @@
+//! Raw terminal input, for interactive programs that act on every keypress.
+//!
+//! Only line editing and echo are turned off: Ctrl-C still interrupts, and
+//! newlines are still written as the terminal expects them. The terminal is
+//! put back as it was when the `RawMode` is dropped, and also when the
+//! process exits or is interrupted before that.
+//!
+//! This needs the `terminal` feature, without it `RawMode::enable` always
+//! fails.
+
+use std::io;
+
+/// Holds the terminal on stdin in raw mode until dropped
+pub struct RawMode {
+    _private: (),
+}
+
+impl RawMode {
+    /// Fails if stdin isn't a terminal, or raw mode isn't supported here
+    pub fn enable() -> io::Result<RawMode> {
+        imp::enable()?;
+        Ok(RawMode { _private: () })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        imp::restore();
+    }
+}
+
+#[cfg(all(unix, feature = "terminal"))]
+mod imp {
+    use std::io;
+    use std::mem;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Once, OnceLock};
+
+    use libc;
+
+    /// The settings to go back to, those the terminal had the first time it
+    /// was put in raw mode, where a signal handler can read them without
+    /// taking a lock
+    static SAVED: OnceLock<libc::termios> = OnceLock::new();
+    static RAW: AtomicBool = AtomicBool::new(false);
+
+    pub fn enable() -> io::Result<()> {
+        static HOOKS: Once = Once::new();
+
+        unsafe {
+            let mut termios = mem::zeroed::<libc::termios>();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            SAVED.get_or_init(|| termios);
+
+            HOOKS.call_once(|| {
+                libc::atexit(restore_at_exit);
+                for &signal in &[libc::SIGINT, libc::SIGTERM, libc::SIGHUP, libc::SIGQUIT] {
+                    libc::signal(signal, restore_on_signal as *const () as libc::sighandler_t);
+                }
+            });
+
+            // A read returns as soon as there is one byte
+            termios.c_lflag &= !(libc::ICANON | libc::ECHO);
+            termios.c_cc[libc::VMIN] = 1;
+            termios.c_cc[libc::VTIME] = 0;
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            RAW.store(true, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+
+    /// Safe to call from a signal handler, and more than once
+    pub fn restore() {
+        if RAW.swap(false, Ordering::SeqCst) {
+            if let Some(saved) = SAVED.get() {
+                unsafe {
+                    libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, saved);
+                }
+            }
+        }
+    }
+
+    extern "C" fn restore_at_exit() {
+        restore();
+    }
+
+    /// Restores the terminal and dies of the signal as if it had never been
+    /// caught
+    extern "C" fn restore_on_signal(signal: libc::c_int) {
+        restore();
+        unsafe {
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+        }
+    }
+}
+
+#[cfg(not(all(unix, feature = "terminal")))]
+mod imp {
+    use std::io;
+
+    pub fn enable() -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "raw terminal input needs bf built with the terminal feature, on Unix",
+        ))
+    }
+
+    pub fn restore() {}
+}
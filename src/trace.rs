@@ -0,0 +1,94 @@
+//! A log of the commands a program runs, stepped through by a `Debugger`.
+//!
+//! Each entry has the step number, where the command is in the source, the
+//! command itself, and the data pointer and the cell under it once it has
+//! run. Entries are either text for reading, like
+//! `12 1:9 [ pointer 0 cell 8`, or JSON lines for tools, like
+//! `{"step":12,"line":1,"column":9,"command":"[","pointer":0,"cell":8}`.
+//! A pointer off a tape that doesn't grow has no cell, `-` or `null`.
+
+use debug::{Debugger, Stop};
+use RunError;
+
+/// Which commands a trace logs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TraceFilter {
+    /// `,` and `.`
+    pub io: bool,
+    /// `[` and `]`
+    pub loops: bool,
+    /// Every other command
+    pub rest: bool,
+}
+
+impl TraceFilter {
+    pub const ALL: TraceFilter = TraceFilter {
+        io: true,
+        loops: true,
+        rest: true,
+    };
+
+    fn keeps(&self, command: char) -> bool {
+        match command {
+            ',' | '.' => self.io,
+            '[' | ']' => self.loops,
+            _ => self.rest,
+        }
+    }
+}
+
+/// How trace entries are written
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceFormat {
+    Text,
+    /// A JSON object per line
+    Json,
+}
+
+pub struct Tracer {
+    pub filter: TraceFilter,
+    pub format: TraceFormat,
+    /// Where the data pointer started, which it is counted from
+    pub origin: i32,
+}
+
+impl Tracer {
+    /// Runs the debugger's next command, returning how that went and the
+    /// command's entry, None if there was no command or the filter drops it
+    pub fn step(&self, debugger: &mut Debugger) -> (Result<Stop, RunError>, Option<String>) {
+        let command = debugger.command();
+        let location = debugger.location();
+        let result = debugger.step();
+
+        let (command, location) = match (command, location) {
+            (Some(command), Some(location)) if self.filter.keeps(command) => (command, location),
+            _ => return (result, None),
+        };
+        let step = debugger.steps();
+        let pointer = debugger.pointer() - self.origin;
+        let cell = debugger.machine().tape.peek(debugger.pointer());
+
+        let entry = match self.format {
+            TraceFormat::Text => format!(
+                "{} {}:{} {} pointer {} cell {}",
+                step,
+                location.line,
+                location.column,
+                command,
+                pointer,
+                cell.map_or("-".to_string(), |cell| cell.to_string())
+            ),
+            // None of the commands need escaping
+            TraceFormat::Json => format!(
+                "{{\"step\":{},\"line\":{},\"column\":{},\"command\":\"{}\",\"pointer\":{},\"cell\":{}}}",
+                step,
+                location.line,
+                location.column,
+                command,
+                pointer,
+                cell.map_or("null".to_string(), |cell| cell.to_string())
+            ),
+        };
+        (result, Some(entry))
+    }
+}
@@ -0,0 +1,84 @@
+//! Frames of a terminal view of a program being stepped through by a
+//! `Debugger`: the command about to run, the tape around the data pointer
+//! and the end of the output. They are drawn with ANSI escapes, each one
+//! over the last.
+
+use std::fmt::Write;
+
+use debug::Debugger;
+
+/// Cells shown on either side of the data pointer
+const RADIUS: i32 = 6;
+/// Lines of output shown
+const OUTPUT_LINES: usize = 8;
+
+const HOME_AND_CLEAR: &str = "\x1b[H\x1b[J";
+const REVERSE: &str = "\x1b[7m";
+const NORMAL: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+
+/// The frame for where the debugger is now, with tape addresses counted
+/// from `origin`. The program's output is what its machine recorded.
+pub fn frame(debugger: &Debugger, origin: i32) -> String {
+    let mut out = String::from(HOME_AND_CLEAR);
+    let pointer = debugger.pointer();
+
+    let status = match debugger.location() {
+        Some(location) => format!("line {}, column {}", location.line, location.column),
+        None => "finished".to_string(),
+    };
+    writeln!(
+        out,
+        "{}step {}{}, {}",
+        BOLD,
+        debugger.steps(),
+        NORMAL,
+        status
+    )
+    .unwrap();
+    writeln!(out).unwrap();
+
+    match debugger.location() {
+        Some(location) => {
+            let before: String = location.text.chars().take(location.column - 1).collect();
+            let mut rest = location.text.chars().skip(location.column - 1);
+            let here = rest.next().unwrap_or(' ');
+            let after: String = rest.collect();
+            writeln!(out, "  {}{}{}{}{}", before, REVERSE, here, NORMAL, after).unwrap();
+        }
+        None => writeln!(out).unwrap(),
+    }
+    writeln!(out).unwrap();
+
+    let tape = &debugger.machine().tape;
+    let (mut addresses, mut values) = (String::new(), String::new());
+    for p in pointer - RADIUS..=pointer + RADIUS {
+        write!(addresses, "{:>6}", p - origin).unwrap();
+        // Cells off a tape that doesn't grow are left blank
+        let value = tape.peek(p).map_or(String::new(), |cell| cell.to_string());
+        if p == pointer {
+            write!(values, "  {}{:>4}{}", REVERSE, value, NORMAL).unwrap();
+        } else {
+            write!(values, "{:>6}", value).unwrap();
+        }
+    }
+    writeln!(out, "{}tape{}", BOLD, NORMAL).unwrap();
+    writeln!(out, "{}", addresses).unwrap();
+    writeln!(out, "{}", values).unwrap();
+    writeln!(out).unwrap();
+
+    writeln!(out, "{}output{}", BOLD, NORMAL).unwrap();
+    let output = debugger.machine().output.recording().unwrap_or_default();
+    let output = String::from_utf8_lossy(output);
+    let lines: Vec<&str> = output.split('\n').collect();
+    for line in &lines[lines.len().saturating_sub(OUTPUT_LINES)..] {
+        // Anything else that moves the cursor would spoil the frame
+        let line: String = line
+            .chars()
+            .map(|c| if c.is_control() { '.' } else { c })
+            .collect();
+        writeln!(out, "  {}", line).unwrap();
+    }
+
+    out
+}
@@ -0,0 +1,314 @@
+//! A WebAssembly module for a program, encoded by hand so that no toolchain
+//! is needed to make one.
+//!
+//! The module imports `env.read_byte: () -> i32`, which returns -1 at the end
+//! of the input, and `env.write_byte: (i32) -> ()`, and exports its linear
+//! memory as `memory` and the program as `_start: () -> ()`. The tape is the
+//! start of the memory and doesn't grow; running off its low end traps.
+
+use input::EofBehavior;
+use BigInsn;
+
+/// Size of a page of linear memory
+const PAGE: usize = 65536;
+
+const READ_BYTE: u32 = 0;
+const WRITE_BYTE: u32 = 1;
+
+// Locals of `_start`
+const P: u32 = 0;
+const BYTE: u32 = 1;
+
+/// Opcodes, and the type codes that go with them
+mod op {
+    pub const BLOCK: u8 = 0x02;
+    pub const LOOP: u8 = 0x03;
+    pub const IF: u8 = 0x04;
+    pub const END: u8 = 0x0b;
+    pub const BR: u8 = 0x0c;
+    pub const BR_IF: u8 = 0x0d;
+    pub const CALL: u8 = 0x10;
+    pub const SELECT: u8 = 0x1b;
+    pub const LOCAL_GET: u8 = 0x20;
+    pub const LOCAL_SET: u8 = 0x21;
+    pub const LOCAL_TEE: u8 = 0x22;
+    pub const I32_LOAD8_U: u8 = 0x2d;
+    pub const I32_STORE8: u8 = 0x3a;
+    pub const I32_CONST: u8 = 0x41;
+    pub const I32_EQZ: u8 = 0x45;
+    pub const I32_NE: u8 = 0x47;
+    pub const I32_ADD: u8 = 0x6a;
+    pub const I32_MUL: u8 = 0x6c;
+    /// Block type of blocks without a result
+    pub const EMPTY: u8 = 0x40;
+    pub const I32: u8 = 0x7f;
+    pub const FUNC: u8 = 0x60;
+}
+
+/// Encodes a program as a module, with the memory initialized from `tape`
+pub fn emit(program: &[BigInsn], tape: &[u8], data_pointer: usize, eof: EofBehavior) -> Vec<u8> {
+    let mut code = Code {
+        bytes: Vec::new(),
+        eof,
+    };
+    code.i32_const(data_pointer as i32);
+    code.local(op::LOCAL_SET, P);
+    code.block(program);
+    code.bytes.push(op::END);
+
+    let mut module = b"\0asm\x01\0\0\0".to_vec();
+
+    // Types: read_byte, write_byte and _start
+    section(&mut module, 1, |s| {
+        unsigned(s, 3);
+        s.extend_from_slice(&[op::FUNC, 0, 1, op::I32]);
+        s.extend_from_slice(&[op::FUNC, 1, op::I32, 0]);
+        s.extend_from_slice(&[op::FUNC, 0, 0]);
+    });
+    // Imports, which take the first function indices
+    section(&mut module, 2, |s| {
+        unsigned(s, 2);
+        for (i, name) in ["read_byte", "write_byte"].iter().enumerate() {
+            string(s, "env");
+            string(s, name);
+            s.push(0);
+            unsigned(s, i as u32);
+        }
+    });
+    // Functions, just _start
+    section(&mut module, 3, |s| {
+        unsigned(s, 1);
+        unsigned(s, 2);
+    });
+    // Memory, enough of it for the tape
+    section(&mut module, 5, |s| {
+        unsigned(s, 1);
+        s.push(0);
+        unsigned(s, tape.len().div_ceil(PAGE).max(1) as u32);
+    });
+    // Exports
+    section(&mut module, 7, |s| {
+        unsigned(s, 2);
+        string(s, "memory");
+        s.push(2);
+        unsigned(s, 0);
+        string(s, "_start");
+        s.push(0);
+        unsigned(s, 2);
+    });
+    // Code of _start
+    section(&mut module, 10, |s| {
+        let mut body = Vec::new();
+        // Two i32 locals, the data pointer and the last byte read
+        unsigned(&mut body, 1);
+        unsigned(&mut body, 2);
+        body.push(op::I32);
+        body.extend_from_slice(&code.bytes);
+
+        unsigned(s, 1);
+        unsigned(s, body.len() as u32);
+        s.extend_from_slice(&body);
+    });
+
+    // Only the part of the tape that isn't zero needs initializing
+    if let (Some(first), Some(last)) = (
+        tape.iter().position(|&cell| cell != 0),
+        tape.iter().rposition(|&cell| cell != 0),
+    ) {
+        section(&mut module, 11, |s| {
+            unsigned(s, 1);
+            s.push(0);
+            s.push(op::I32_CONST);
+            signed(s, first as i32);
+            s.push(op::END);
+            unsigned(s, (last + 1 - first) as u32);
+            s.extend_from_slice(&tape[first..=last]);
+        });
+    }
+
+    module
+}
+
+fn section(module: &mut Vec<u8>, id: u8, contents: impl FnOnce(&mut Vec<u8>)) {
+    let mut section = Vec::new();
+    contents(&mut section);
+
+    module.push(id);
+    unsigned(module, section.len() as u32);
+    module.extend_from_slice(&section);
+}
+
+fn unsigned(out: &mut Vec<u8>, mut n: u32) {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn signed(out: &mut Vec<u8>, mut n: i32) {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        // Done once what is left is all sign, and the sign bit says so
+        if (n == 0 && byte & 0x40 == 0) || (n == -1 && byte & 0x40 != 0) {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn string(out: &mut Vec<u8>, s: &str) {
+    unsigned(out, s.len() as u32);
+    out.extend_from_slice(s.as_bytes());
+}
+
+/// The body of `_start`
+struct Code {
+    bytes: Vec<u8>,
+    eof: EofBehavior,
+}
+
+impl Code {
+    fn i32_const(&mut self, n: i32) {
+        self.bytes.push(op::I32_CONST);
+        signed(&mut self.bytes, n);
+    }
+
+    fn local(&mut self, op: u8, index: u32) {
+        self.bytes.push(op);
+        unsigned(&mut self.bytes, index);
+    }
+
+    fn call(&mut self, function: u32) {
+        self.bytes.push(op::CALL);
+        unsigned(&mut self.bytes, function);
+    }
+
+    fn move_by(&mut self, n: i32) {
+        if n != 0 {
+            self.local(op::LOCAL_GET, P);
+            self.i32_const(n);
+            self.bytes.push(op::I32_ADD);
+            self.local(op::LOCAL_SET, P);
+        }
+    }
+
+    /// Pushes the address of the cell at `offset` and returns the offset the
+    /// access itself should add, since those can't be negative
+    fn address(&mut self, offset: i32) -> u32 {
+        self.local(op::LOCAL_GET, P);
+        if offset < 0 {
+            self.i32_const(offset);
+            self.bytes.push(op::I32_ADD);
+            0
+        } else {
+            offset as u32
+        }
+    }
+
+    fn memory(&mut self, op: u8, offset: u32) {
+        self.bytes.push(op);
+        // Byte accesses are always aligned
+        unsigned(&mut self.bytes, 0);
+        unsigned(&mut self.bytes, offset);
+    }
+
+    fn load(&mut self, offset: i32) {
+        let offset = self.address(offset);
+        self.memory(op::I32_LOAD8_U, offset);
+    }
+
+    /// Stores what `value` pushes in the cell at `offset`
+    fn store(&mut self, offset: i32, value: impl FnOnce(&mut Code)) {
+        let offset = self.address(offset);
+        value(self);
+        self.memory(op::I32_STORE8, offset);
+    }
+
+    /// A loop that runs `body` while the current cell isn't zero
+    fn while_nonzero(&mut self, body: impl FnOnce(&mut Code)) {
+        self.bytes
+            .extend_from_slice(&[op::BLOCK, op::EMPTY, op::LOOP, op::EMPTY]);
+        self.load(0);
+        self.bytes.extend_from_slice(&[op::I32_EQZ, op::BR_IF, 1]);
+        body(self);
+        self.bytes.extend_from_slice(&[op::BR, 0, op::END, op::END]);
+    }
+
+    fn block(&mut self, program: &[BigInsn]) {
+        for insn in program {
+            self.insn(insn);
+        }
+    }
+
+    fn insn(&mut self, insn: &BigInsn) {
+        match insn {
+            BigInsn::Move(n) => self.move_by(*n),
+            BigInsn::Adj(n) => self.store(0, |code| {
+                code.load(0);
+                code.i32_const(*n);
+                code.bytes.push(op::I32_ADD);
+            }),
+            BigInsn::Set(value) => self.store(0, |code| code.i32_const(i32::from(*value))),
+            BigInsn::Clear => self.store(0, |code| code.i32_const(0)),
+            BigInsn::MulAdd(targets) => {
+                for &(offset, k) in targets {
+                    self.store(offset, |code| {
+                        code.load(offset);
+                        code.load(0);
+                        code.i32_const(k);
+                        code.bytes.push(op::I32_MUL);
+                        code.bytes.push(op::I32_ADD);
+                    });
+                }
+            }
+            BigInsn::Seek(stride) => self.while_nonzero(|code| code.move_by(*stride)),
+            BigInsn::Write => {
+                self.load(0);
+                self.call(WRITE_BYTE);
+            }
+            BigInsn::WriteBytes(bytes) => {
+                for &byte in bytes {
+                    self.i32_const(i32::from(byte));
+                    self.call(WRITE_BYTE);
+                }
+            }
+            BigInsn::Read => {
+                let eof = self.eof;
+                match eof {
+                    EofBehavior::Unchanged => {
+                        self.call(READ_BYTE);
+                        self.local(op::LOCAL_TEE, BYTE);
+                        self.i32_const(-1);
+                        self.bytes
+                            .extend_from_slice(&[op::I32_NE, op::IF, op::EMPTY]);
+                        self.store(0, |code| code.local(op::LOCAL_GET, BYTE));
+                        self.bytes.push(op::END);
+                    }
+                    EofBehavior::Zero | EofBehavior::Max => self.store(0, |code| {
+                        // The byte read, or what stands in for it at the end
+                        code.call(READ_BYTE);
+                        code.local(op::LOCAL_TEE, BYTE);
+                        code.i32_const(if eof == EofBehavior::Zero { 0 } else { 255 });
+                        code.local(op::LOCAL_GET, BYTE);
+                        code.i32_const(-1);
+                        code.bytes.extend_from_slice(&[op::I32_NE, op::SELECT]);
+                    }),
+                }
+            }
+            BigInsn::Loop(body) => self.while_nonzero(|code| code.block(body)),
+            BigInsn::Snapshot { cells, pointer } => {
+                for &(offset, value) in cells {
+                    self.store(offset, |code| code.i32_const(i32::from(value)));
+                }
+                self.move_by(*pointer);
+            }
+        }
+    }
+}
@@ -0,0 +1,178 @@
+//! The `bf` command, run as a process of its own
+
+use std::process::{Command, Output, Stdio};
+
+fn bf(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_bf"))
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn trace_stops_at_the_step_limit() {
+    let output = bf(&["-c", "+[]", "--trace", "--max-steps", "10"]);
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("resource limit exceeded"));
+}
+
+#[test]
+fn coverage_stops_at_the_timeout() {
+    let output = bf(&["-c", "+[]", "--coverage", "--sandbox", "--timeout", "0.1"]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn the_step_limit_is_the_same_at_every_opt_level_and_on_every_backend() {
+    // 4 commands outside the loop, and 2 iterations of 1 and the 8 commands
+    // of its body
+    let program = "++[>+++++<-]>.";
+    let mut ways = vec![
+        ["--opt", "none"],
+        ["--opt", "basic"],
+        ["--opt", "full"],
+        ["--backend", "big-closure"],
+        ["--backend", "closure"],
+        ["--backend", "tree"],
+        ["--backend", "big"],
+        ["--backend", "bytecode"],
+    ];
+    if cfg!(feature = "jit") {
+        ways.push(["--backend", "jit"]);
+    }
+
+    for way in ways {
+        let output = bf(&["-c", program, "--max-steps", "21", way[0], way[1]]);
+        assert_eq!(output.status.code(), Some(2), "{:?} at 21", way);
+        assert!(output.stdout.is_empty(), "{:?} at 21", way);
+
+        let output = bf(&["-c", program, "--max-steps", "22", way[0], way[1]]);
+        assert!(output.status.success(), "{:?} at 22", way);
+        assert_eq!(output.stdout, b"\n", "{:?} at 22", way);
+    }
+}
+
+#[test]
+fn numeric_input_that_doesnt_fit_is_an_error() {
+    let output = bf(&["-c", ",.", "--input-mode", "numeric", "--input-str", "300"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(stderr, "bf: input value 300 does not fit in a cell\n");
+}
+
+#[test]
+fn start_at_puts_the_pointer_on_the_labelled_cell() {
+    let output = bf(&[
+        "-c",
+        ".",
+        "--seed-tape",
+        "1,2,@here:3,4",
+        "--start-at",
+        "here",
+    ]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, [3]);
+}
+
+/// A quine made of code that prints a program, and that program's bytes
+/// laid out on every other cell ahead of it, so the code can print the
+/// layout first and then the bytes themselves
+fn quine() -> String {
+    let code = "[<<]>>[[>+<<+>-]<[>+<-]><".to_string()
+        + &"+".repeat(62)
+        + "..[-]"
+        + &"+".repeat(43)
+        + ">>[<<.>>-]<<[-]>>>]<<[<<]>>[.>>]";
+    let layout: String = code
+        .bytes()
+        .map(|byte| ">>".to_string() + &"+".repeat(byte as usize))
+        .collect();
+    layout + &code
+}
+
+#[test]
+fn a_quine_passes_the_quine_check() {
+    let quine = quine();
+    let output = bf(&["-c", &quine, "--quine-check"]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, quine.as_bytes());
+
+    let output = bf(&["-c", "+++.", "--quine-check"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("differs from the source at byte 0"));
+}
+
+/// The major version of the `llc` on the path, None if there isn't one
+#[cfg(feature = "llvm")]
+fn llc_version() -> Option<u32> {
+    let output = Command::new("llc").arg("--version").output().ok()?;
+    let text = String::from_utf8_lossy(&output.stdout);
+    let version = text.split("LLVM version ").nth(1)?;
+    version.split('.').next()?.trim().parse().ok()
+}
+
+#[cfg(feature = "llvm")]
+#[test]
+#[ignore = "needs llc on the path, run with --ignored"]
+fn llc_takes_the_emitted_ir() {
+    let version = llc_version().expect("llc isn't installed");
+
+    let ir = std::env::temp_dir().join(format!("bf-llc-{}.ll", std::process::id()));
+    let ir = ir.to_str().unwrap();
+    let program = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.\
+                   +++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.,[.,][-]";
+    let output = bf(&["build", "--emit", "llvm", "-o", ir, "-c", program]);
+    assert!(output.status.success());
+
+    let mut llc = Command::new("llc");
+    if version < 15 {
+        llc.arg("-opaque-pointers");
+    }
+    let assembly = ir.replace(".ll", ".s");
+    let output = llc.args([ir, "-o", &assembly]).output().unwrap();
+    std::fs::remove_file(ir).unwrap();
+    let _ = std::fs::remove_file(&assembly);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn output_that_cant_be_written_is_an_error() {
+    let full = std::fs::OpenOptions::new()
+        .write(true)
+        .open("/dev/full")
+        .unwrap();
+    // Failing both while running and when finishing the output
+    for program in &["+[.]", "+."] {
+        let output = Command::new(env!("CARGO_BIN_EXE_bf"))
+            .args(["-c", program])
+            .stdin(Stdio::null())
+            .stdout(full.try_clone().unwrap())
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(1), "{}", program);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.starts_with("bf: failed to write output"),
+            "{}",
+            stderr
+        );
+    }
+}
+
+#[test]
+fn a_program_and_a_file_cant_both_be_given() {
+    for args in [["-c", "+", "prog.bf"], ["prog.bf", "-c", "+"]] {
+        let output = bf(&args);
+        assert_eq!(output.status.code(), Some(1));
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert_eq!(stderr, "bf: -c and a file can't both be given\n");
+    }
+}